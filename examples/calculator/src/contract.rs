@@ -1,36 +1,63 @@
 use crate::error::CalcError;
-use crate::message::{ProcMsg, QueryMsg, QueryRsp};
-use kelk_env::context::Context;
+use crate::message::{InstantiateMsg, MigrateMsg, ProcMsg, QueryMsg, QueryRsp};
+use kelk_env::context::{Context, QueryContext};
+use kelk_env::debug;
+use kelk_env::lifecycle::{self, ContractVersion, DATA_OFFSET};
+use kelk_lib::collections::item::{Slot, StorageItem};
+use kelk_lib::error::Error;
+use kelk_lib::math;
+
+/// The name of the contract, stored with its version
+const NAME: &str = "calculator";
+/// The version of the contract code
+const VERSION: (u32, u32, u32) = (0, 1, 0);
+
+/// The result of the last calculation
+const LAST_RESULT: Slot = Slot::new(DATA_OFFSET);
+
+fn save_result(ctx: Context, res: i32) -> Result<(), CalcError> {
+    StorageItem::from_slot(ctx.api, &LAST_RESULT).save(&res)?;
+    Ok(())
+}
 
 fn add(ctx: Context, a: i32, b: i32) -> Result<(), CalcError> {
-    ctx.api
-        .swrite_i32(0, a + b)
-        .map_err(|_| CalcError::KelkError)
+    save_result(ctx, math::add(a, b)?)
 }
 
 fn sub(ctx: Context, a: i32, b: i32) -> Result<(), CalcError> {
-    ctx.api
-        .swrite_i32(0, a - b)
-        .map_err(|_| CalcError::KelkError)
+    save_result(ctx, math::sub(a, b)?)
 }
 
 fn mul(ctx: Context, a: i32, b: i32) -> Result<(), CalcError> {
-    ctx.api
-        .swrite_i32(0, a * b)
-        .map_err(|_| CalcError::KelkError)
+    save_result(ctx, math::mul(a, b)?)
 }
 
 fn div(ctx: Context, a: i32, b: i32) -> Result<(), CalcError> {
     if b == 0 {
+        debug!(ctx, "division of {} by zero", a);
         return Err(CalcError::DivByZero);
     }
-    ctx.api
-        .swrite_i32(0, a / b)
-        .map_err(|_| CalcError::KelkError)
+    save_result(ctx, math::div(a, b)?)
 }
 
-fn query_result(ctx: Context) -> Result<i32, CalcError> {
-    ctx.api.sread_i32(0).map_err(|_| CalcError::KelkError)
+fn query_result(ctx: QueryContext) -> Result<i32, CalcError> {
+    Ok(StorageItem::from_slot(ctx.api, &LAST_RESULT).load()?)
+}
+
+#[cfg(target_arch = "wasm32")]
+mod __wasm_export_instantiate {
+    #[no_mangle]
+    extern "C" fn instantiate(msg_ptr: u64) -> u64 {
+        kelk_env::do_instantiate(&super::instantiate, msg_ptr)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod __wasm_export_migrate {
+    #[no_mangle]
+    extern "C" fn migrate(msg_ptr: u64) -> u64 {
+        kelk_env::do_migrate(&super::migrate, msg_ptr)
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -49,6 +76,29 @@ mod __wasm_export_query {
     }
 }
 
+// #[kelk_derive(instantiate)]
+pub fn instantiate(ctx: Context, msg: InstantiateMsg) -> Result<(), CalcError> {
+    lifecycle::set_version(ctx.api, &version())?;
+    save_result(ctx, msg.initial)
+}
+
+fn version() -> ContractVersion {
+    ContractVersion::new(NAME, VERSION.0, VERSION.1, VERSION.2)
+}
+
+// #[kelk_derive(migrate)]
+pub fn migrate(
+    _ctx: Context,
+    old_version: Option<ContractVersion>,
+    _msg: MigrateMsg,
+) -> Result<ContractVersion, CalcError> {
+    // The storage layout has not changed since the first version.
+    match old_version {
+        Some(old) if old.name == NAME && old.major == VERSION.0 => Ok(version()),
+        _ => Err(Error::InvalidVersion.into()),
+    }
+}
+
 // #[kelk_derive(process_msg)]
 pub fn process_msg(ctx: Context, msg: ProcMsg) -> Result<(), CalcError> {
     match msg {
@@ -60,7 +110,7 @@ pub fn process_msg(ctx: Context, msg: ProcMsg) -> Result<(), CalcError> {
 }
 
 // #[kelk_derive(query)]
-pub fn query(ctx: Context, msg: QueryMsg) -> Result<QueryRsp, CalcError> {
+pub fn query(ctx: QueryContext, msg: QueryMsg) -> Result<QueryRsp, CalcError> {
     let res = match msg {
         QueryMsg::LastResult => query_result(ctx),
     }?;
@@ -1,36 +1,234 @@
 use super::*;
-use kelk_env::mock::mock_context;
+use crate::message::Calculator;
+use kelk_env::client::{Client, ClientError, EntryPoint, Transport};
+use kelk_env::context::OwnedContext;
+use kelk_env::lifecycle;
+use kelk_env::mock::{mock_context, mock_execute, mock_migration, Fault, MockContextAPI};
+use kelk_lib::alloc::vec::Vec;
+use kelk_lib::error::{Error, HostErrorKind};
+
+#[test]
+fn test_instantiate() {
+    let ctx = mock_context(256);
+    let msg = ProcMsg::Add { a: 1, b: 2 };
+    assert!(matches!(
+        lifecycle::process_msg(&process_msg, ctx.as_ref(), msg),
+        Err(CalcError::KelkError(Error::NotInstantiated))
+    ));
+    assert!(matches!(
+        lifecycle::query(&query, ctx.as_query(), QueryMsg::LastResult),
+        Err(CalcError::KelkError(Error::NotInstantiated))
+    ));
+
+    let msg = InstantiateMsg { initial: 7 };
+    lifecycle::instantiate(&instantiate, ctx.as_ref(), msg).unwrap();
+    assert_eq!(query_result(ctx.as_query()).unwrap(), 7);
+
+    let msg = InstantiateMsg { initial: 8 };
+    assert!(matches!(
+        lifecycle::instantiate(&instantiate, ctx.as_ref(), msg),
+        Err(CalcError::KelkError(Error::AlreadyInstantiated))
+    ));
+
+    let msg = ProcMsg::Add { a: 1, b: 2 };
+    lifecycle::process_msg(&process_msg, ctx.as_ref(), msg).unwrap();
+    assert_eq!(query_result(ctx.as_query()).unwrap(), 3);
+}
 
 #[test]
 fn test_add() {
-    let ctx = mock_context(10);
+    let ctx = mock_context(256);
     add(ctx.as_ref(), 1, 2).unwrap();
-    let res = query_result(ctx.as_ref()).unwrap();
+    let res = query_result(ctx.as_query()).unwrap();
     assert_eq!(res, 3);
 }
 
 #[test]
 fn test_sub() {
-    let ctx = mock_context(10);
+    let ctx = mock_context(256);
     sub(ctx.as_ref(), 1, 2).unwrap();
-    let res = query_result(ctx.as_ref()).unwrap();
+    let res = query_result(ctx.as_query()).unwrap();
     assert_eq!(res, -1);
 }
 
 #[test]
 fn test_mul() {
-    let ctx = mock_context(10);
+    let ctx = mock_context(256);
     mul(ctx.as_ref(), 2, 2).unwrap();
-    let res = query_result(ctx.as_ref()).unwrap();
+    let res = query_result(ctx.as_query()).unwrap();
     assert_eq!(res, 4);
 }
 
 #[test]
 fn test_div() {
-    let ctx = mock_context(10);
+    let ctx = mock_context(256);
     div(ctx.as_ref(), 4, 2).unwrap();
-    let res = query_result(ctx.as_ref()).unwrap();
+    let res = query_result(ctx.as_query()).unwrap();
     assert_eq!(res, 2);
 
     assert!(div(ctx.as_ref(), 4, 0).is_err());
+    assert_eq!(ctx.api.debug_lines(), ["division of 4 by zero"]);
+}
+
+#[test]
+fn test_overflow() {
+    let ctx = mock_context(256);
+    add(ctx.as_ref(), 1, 2).unwrap();
+
+    assert!(matches!(
+        add(ctx.as_ref(), i32::MAX, 1),
+        Err(CalcError::KelkError(Error::Overflow))
+    ));
+    assert!(matches!(
+        sub(ctx.as_ref(), i32::MIN, 1),
+        Err(CalcError::KelkError(Error::Overflow))
+    ));
+    assert!(matches!(
+        mul(ctx.as_ref(), i32::MAX, 2),
+        Err(CalcError::KelkError(Error::Overflow))
+    ));
+    assert!(matches!(
+        div(ctx.as_ref(), i32::MIN, -1),
+        Err(CalcError::KelkError(Error::Overflow))
+    ));
+
+    // The last result is not changed
+    assert_eq!(query_result(ctx.as_query()).unwrap(), 3);
+}
+
+#[test]
+fn test_storage_failure() {
+    let ctx = mock_context(256);
+    assert!(matches!(
+        query_result(ctx.as_query()),
+        Err(CalcError::KelkError(Error::NotFound))
+    ));
+
+    ctx.api.inject(Fault::NthWrite {
+        n: 1,
+        kind: HostErrorKind::OutOfGas,
+    });
+    assert!(matches!(
+        add(ctx.as_ref(), 1, 2),
+        Err(CalcError::KelkError(Error::HostError(
+            HostErrorKind::OutOfGas
+        )))
+    ));
+
+    ctx.api.inject(Fault::ReadRange {
+        range: DATA_OFFSET..DATA_OFFSET + 1,
+        kind: HostErrorKind::OutOfGas,
+    });
+    assert!(matches!(
+        query_result(ctx.as_query()),
+        Err(CalcError::KelkError(Error::HostError(
+            HostErrorKind::OutOfGas
+        )))
+    ));
+}
+
+#[test]
+fn test_migrate() {
+    let old_code = |ctx: Context| {
+        let api = ctx.api;
+        let msg = InstantiateMsg { initial: 7 };
+        lifecycle::instantiate(&instantiate, ctx, msg)?;
+        lifecycle::set_version(api, &ContractVersion::new(NAME, 0, 0, 1))?;
+        Ok(())
+    };
+    let new_code = |ctx: Context| {
+        let res = lifecycle::query(&query, ctx.as_query(), QueryMsg::LastResult)?;
+        assert!(matches!(res, QueryRsp::Result { res: 7 }));
+        Ok(())
+    };
+    let ctx = mock_migration(256, &old_code, &migrate, MigrateMsg {}, &new_code).unwrap();
+    let version = lifecycle::get_version(ctx.api.storage()).unwrap();
+    assert_eq!(version, Some(ContractVersion::new(NAME, 0, 1, 0)));
+
+    let old_code = |ctx: Context| {
+        let api = ctx.api;
+        let msg = InstantiateMsg { initial: 7 };
+        lifecycle::instantiate(&instantiate, ctx, msg)?;
+        lifecycle::set_version(api, &ContractVersion::new("other", 0, 0, 1))?;
+        Ok(())
+    };
+    assert!(matches!(
+        mock_migration(256, &old_code, &migrate, MigrateMsg {}, &new_code),
+        Err(CalcError::KelkError(Error::InvalidVersion))
+    ));
+}
+
+#[test]
+fn test_schema() {
+    use kelk_lib::schema::{self, Schema};
+
+    let schemas = [
+        InstantiateMsg::schema(),
+        MigrateMsg::schema(),
+        ProcMsg::schema(),
+        QueryMsg::schema(),
+        QueryRsp::schema(),
+        CalcError::schema(),
+        Error::schema(),
+        HostErrorKind::schema(),
+    ];
+    // The committed schema should be updated with `KELK_UPDATE_SCHEMA=1 cargo test`.
+    schema::check(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/schema.json"),
+        &schemas,
+    )
+    .unwrap();
+}
+
+struct MockHost(OwnedContext<MockContextAPI>);
+
+impl Transport for MockHost {
+    type Error = Error;
+
+    fn call(&self, entry: EntryPoint, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let ctx = self.0.as_ref();
+        match entry {
+            EntryPoint::Instantiate => mock_execute(
+                &|ctx, msg| lifecycle::instantiate(&instantiate, ctx, msg),
+                ctx,
+                msg,
+            ),
+            EntryPoint::ProcessMsg => mock_execute(
+                &|ctx, msg| lifecycle::process_msg(&process_msg, ctx, msg),
+                ctx,
+                msg,
+            ),
+            EntryPoint::Query => mock_execute(
+                &|ctx: Context, msg| lifecycle::query(&query, ctx.as_query(), msg),
+                ctx,
+                msg,
+            ),
+            EntryPoint::Migrate => {
+                mock_execute(&|ctx, msg| lifecycle::migrate(&migrate, ctx, msg), ctx, msg)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_client() {
+    let client = Client::<Calculator, _>::new(MockHost(mock_context(256)));
+    assert!(matches!(
+        client.process_msg(&ProcMsg::Add { a: 1, b: 2 }),
+        Err(ClientError::Contract(CalcError::KelkError(
+            Error::NotInstantiated
+        )))
+    ));
+
+    client.instantiate(&InstantiateMsg { initial: 7 }).unwrap();
+    client.process_msg(&ProcMsg::Mul { a: 6, b: 7 }).unwrap();
+    assert!(matches!(
+        client.query(&QueryMsg::LastResult),
+        Ok(QueryRsp::Result { res: 42 })
+    ));
+    assert!(matches!(
+        client.process_msg(&ProcMsg::Div { a: 1, b: 0 }),
+        Err(ClientError::Contract(CalcError::DivByZero))
+    ));
+    client.migrate(&MigrateMsg {}).unwrap();
 }
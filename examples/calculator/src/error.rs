@@ -1,9 +1,23 @@
-use minicbor::Encode;
+use kelk_lib::error::{Error, HostError};
+use kelk_lib::schema::Schema;
+use minicbor::{Decode, Encode};
 
-#[derive(Clone, Debug, Encode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum CalcError {
     #[n(0)]
-    KelkError,
+    KelkError(#[n(0)] Error),
     #[n(1)]
     DivByZero,
 }
+
+impl From<Error> for CalcError {
+    fn from(error: Error) -> Self {
+        CalcError::KelkError(error)
+    }
+}
+
+impl From<HostError> for CalcError {
+    fn from(error: HostError) -> Self {
+        CalcError::KelkError(error.into())
+    }
+}
@@ -1,6 +1,18 @@
+use crate::error::CalcError;
+use kelk_env::client::Contract;
+use kelk_lib::schema::Schema;
 use minicbor::{Decode, Encode};
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
+pub struct InstantiateMsg {
+    #[n(0)]
+    pub initial: i32,
+}
+
+#[derive(Clone, Debug, Encode, Decode, Schema)]
+pub struct MigrateMsg {}
+
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum ProcMsg {
     #[n(0)]
     Add {
@@ -32,13 +44,13 @@ pub enum ProcMsg {
     },
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum QueryMsg {
     #[n(0)]
     LastResult,
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum QueryRsp {
     #[n(0)]
     Result {
@@ -46,3 +58,15 @@ pub enum QueryRsp {
         res: i32,
     },
 }
+
+/// The messages of the contract, for calling it with `kelk_env::client::Client`.
+pub struct Calculator;
+
+impl Contract for Calculator {
+    type InstantiateMsg = InstantiateMsg;
+    type ProcMsg = ProcMsg;
+    type QueryMsg = QueryMsg;
+    type QueryRsp = QueryRsp;
+    type MigrateMsg = MigrateMsg;
+    type Error = CalcError;
+}
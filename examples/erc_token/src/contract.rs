@@ -1,17 +1,21 @@
 use crate::error::TokenError;
-use crate::message::{ProcMsg, QueryMsg, QueryRsp};
-use kelk_env::context::Context;
+use crate::message::{InstantiateMsg, ProcMsg, QueryMsg, QueryRsp};
+use kelk_env::context::{Context, QueryContext};
+use kelk_env::lifecycle::DATA_OFFSET;
 use kelk_lib::alloc::vec::Vec;
 use kelk_lib::collections::bst::tree::StorageBST;
 
+/// The maximum number of accounts
+const CAPACITY: u32 = 1024;
+
 fn transfer(ctx: Context, from: [u8; 4], to: [u8; 4], amount: i64) -> Result<(), TokenError> {
-    let mut bst: StorageBST<[u8; 4], i64> = StorageBST::lazy_load(ctx.api, 0).unwrap(); // FIXME: no unwrap
-    let tx_balance = match bst.find(&from).unwrap() {
+    let mut bst = StorageBST::<[u8; 4], i64>::lazy_load(ctx.api, DATA_OFFSET)?;
+    let tx_balance = match bst.find(&from)? {
         Some(balance) => balance,
         None => 0,
     };
 
-    let rx_balance = match bst.find(&to).unwrap() {
+    let rx_balance = match bst.find(&to)? {
         Some(balance) => balance,
         None => 0,
     };
@@ -20,14 +24,22 @@ fn transfer(ctx: Context, from: [u8; 4], to: [u8; 4], amount: i64) -> Result<(),
         return Err(TokenError::InsufficientAmount);
     }
 
-    bst.insert(from, tx_balance - amount).unwrap();
-    bst.insert(to, rx_balance + amount).unwrap();
+    bst.insert(from, tx_balance - amount)?;
+    bst.insert(to, rx_balance + amount)?;
 
     Ok(())
 }
 
-fn query_result(ctx: Context) -> Result<i32, TokenError> {
-    ctx.api.sread_i32(0).map_err(|_| TokenError::KelkError)
+fn query_result(ctx: QueryContext) -> Result<i32, TokenError> {
+    Ok(ctx.api.sread_i32(DATA_OFFSET)?)
+}
+
+#[cfg(target_arch = "wasm32")]
+mod __wasm_export_instantiate {
+    #[no_mangle]
+    extern "C" fn instantiate(msg_ptr: u64) -> u64 {
+        kelk_env::do_instantiate(&super::instantiate, msg_ptr)
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -46,6 +58,13 @@ mod __wasm_export_query {
     }
 }
 
+// #[kelk_derive(instantiate)]
+pub fn instantiate(ctx: Context, msg: InstantiateMsg) -> Result<(), TokenError> {
+    let mut bst = StorageBST::<[u8; 4], i64>::create(ctx.api, DATA_OFFSET, CAPACITY)?;
+    bst.insert(msg.owner, msg.total_supply)?;
+    Ok(())
+}
+
 // #[kelk_derive(process_msg)]
 pub fn process_msg(ctx: Context, msg: ProcMsg) -> Result<(), TokenError> {
     match msg {
@@ -54,7 +73,7 @@ pub fn process_msg(ctx: Context, msg: ProcMsg) -> Result<(), TokenError> {
 }
 
 // #[kelk_derive(query)]
-pub fn query(ctx: Context, msg: QueryMsg) -> Result<QueryRsp, TokenError> {
+pub fn query(ctx: QueryContext, msg: QueryMsg) -> Result<QueryRsp, TokenError> {
     let res = match msg {
         QueryMsg::LastResult => query_result(ctx),
     }?;
@@ -1,20 +1,97 @@
 use super::*;
-use kelk_env::mock::mock_context;
+use kelk_env::lifecycle;
+use kelk_env::mock::{mock_context, Fault};
 use kelk_lib::collections::bst::tree::StorageBST;
+use kelk_lib::error::{Error, HostErrorKind};
 
 #[test]
-fn test_transfer() {
+fn test_instantiate() {
     let ctx = mock_context(1024 * 1024);
-    let mut bst: StorageBST<[u8; 4], i64> = StorageBST::create(ctx.as_ref().api, 0, 1024).unwrap();
+    let owner = [1; 4];
+    let msg = ProcMsg::Transfer {
+        from: owner,
+        to: [2; 4],
+        amount: 1,
+    };
+    assert!(matches!(
+        lifecycle::process_msg(&process_msg, ctx.as_ref(), msg),
+        Err(TokenError::KelkError(Error::NotInstantiated))
+    ));
+
+    let msg = InstantiateMsg {
+        owner,
+        total_supply: 100,
+    };
+    lifecycle::instantiate(&instantiate, ctx.as_ref(), msg.clone()).unwrap();
+    assert!(matches!(
+        lifecycle::instantiate(&instantiate, ctx.as_ref(), msg),
+        Err(TokenError::KelkError(Error::AlreadyInstantiated))
+    ));
+
+    let bst = StorageBST::<[u8; 4], i64>::lazy_load(ctx.as_ref().api, DATA_OFFSET).unwrap();
+    assert_eq!(bst.find(&owner).unwrap(), Some(100));
+}
 
+#[test]
+fn test_transfer() {
+    let ctx = mock_context(1024 * 1024);
     let sender = [1; 4];
     let receiver = [2; 4];
+    let msg = InstantiateMsg {
+        owner: sender,
+        total_supply: 11,
+    };
+    lifecycle::instantiate(&instantiate, ctx.as_ref(), msg).unwrap();
+    let bst = StorageBST::<[u8; 4], i64>::lazy_load(ctx.as_ref().api, DATA_OFFSET).unwrap();
 
-    let response_1 = transfer(ctx.as_ref(), sender, receiver, 10);
+    let response_1 = transfer(ctx.as_ref(), sender, receiver, 12);
     assert!(response_1.is_err());
-    bst.insert(sender, 11).unwrap();
     let response_2 = transfer(ctx.as_ref(), sender, receiver, 10);
     assert!(response_2.is_ok());
     assert_eq!(bst.find(&sender).unwrap(), Some(1));
     assert_eq!(bst.find(&receiver).unwrap(), Some(10));
 }
+
+#[test]
+fn test_transfer_storage_failure() {
+    let ctx = mock_context(1024 * 1024);
+    let mut bst = StorageBST::<[u8; 4], i64>::create(ctx.as_ref().api, DATA_OFFSET, 1024).unwrap();
+
+    let sender = [1; 4];
+    let receiver = [2; 4];
+    bst.insert(sender, 11).unwrap();
+
+    ctx.api.inject(Fault::CrashAfter {
+        writes: ctx.api.storage().write_count(),
+        kind: HostErrorKind::StorageFull,
+    });
+    let res = transfer(ctx.as_ref(), sender, receiver, 10);
+    assert!(matches!(
+        res,
+        Err(TokenError::KelkError(Error::HostError(
+            HostErrorKind::StorageFull
+        )))
+    ));
+    assert_eq!(bst.find(&sender).unwrap(), Some(11));
+}
+
+#[test]
+fn test_schema() {
+    use kelk_lib::schema::{self, Schema};
+
+    let schemas = [
+        InstantiateMsg::schema(),
+        ProcMsg::schema(),
+        QueryMsg::schema(),
+        QueryRsp::schema(),
+        TokenError::schema(),
+        Error::schema(),
+        HostErrorKind::schema(),
+    ];
+    // The committed schema should be updated with `KELK_UPDATE_SCHEMA=1 cargo test`.
+    schema::check(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/schema.json"),
+        &schemas,
+    )
+    .unwrap();
+}
@@ -1,9 +1,23 @@
-use minicbor::Encode;
+use kelk_lib::error::{Error, HostError};
+use kelk_lib::schema::Schema;
+use minicbor::{Decode, Encode};
 
-#[derive(Clone, Debug, Encode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum TokenError {
     #[n(0)]
-    KelkError,
+    KelkError(#[n(0)] Error),
     #[n(1)]
     InsufficientAmount,
 }
+
+impl From<Error> for TokenError {
+    fn from(error: Error) -> Self {
+        TokenError::KelkError(error)
+    }
+}
+
+impl From<HostError> for TokenError {
+    fn from(error: HostError) -> Self {
+        TokenError::KelkError(error.into())
+    }
+}
@@ -1,7 +1,18 @@
+use crate::error::TokenError;
+use kelk_env::client::Contract;
 use kelk_lib::alloc::vec::Vec;
+use kelk_lib::schema::Schema;
 use minicbor::{Decode, Encode};
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
+pub struct InstantiateMsg {
+    #[n(0)]
+    pub owner: [u8; 4],
+    #[n(1)]
+    pub total_supply: i64,
+}
+
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum ProcMsg {
     #[n(4)]
     Transfer {
@@ -14,13 +25,13 @@ pub enum ProcMsg {
     },
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum QueryMsg {
     #[n(0)]
     LastResult,
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode, Decode, Schema)]
 pub enum QueryRsp {
     #[n(0)]
     Result {
@@ -28,3 +39,15 @@ pub enum QueryRsp {
         res: i32,
     },
 }
+
+/// The messages of the contract, for calling it with `kelk_env::client::Client`.
+pub struct Token;
+
+impl Contract for Token {
+    type InstantiateMsg = InstantiateMsg;
+    type ProcMsg = ProcMsg;
+    type QueryMsg = QueryMsg;
+    type QueryRsp = QueryRsp;
+    type MigrateMsg = ();
+    type Error = TokenError;
+}
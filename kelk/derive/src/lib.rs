@@ -1,7 +1,19 @@
 use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+mod schema;
 
 #[proc_macro_attribute]
 pub fn kelk_derive(attr: TokenStream, item: TokenStream) -> TokenStream {
     println!("attr: \"{}\"", attr);
     item
 }
+
+/// Derives `kelk_lib::schema::Schema` from the `minicbor` attributes of the type.
+#[proc_macro_derive(Schema, attributes(n, b, cbor))]
+pub fn derive_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    schema::derive(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
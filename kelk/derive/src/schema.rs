@@ -0,0 +1,200 @@
+//! Implementation of `#[derive(Schema)]`.
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+/// The `#[cbor(...)]` options that change the layout of a type or variant.
+#[derive(Default)]
+struct CborOptions {
+    map: bool,
+    index_only: bool,
+    transparent: bool,
+}
+
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let options = cbor_options(&input.attrs)?;
+
+    let kind = match &input.data {
+        Data::Struct(data) if options.transparent => {
+            let field = data.fields.iter().next().ok_or_else(|| {
+                syn::Error::new(input.span(), "transparent struct needs exactly one field")
+            })?;
+            let ty = type_name(&field.ty);
+            quote!(::kelk_lib::schema::Kind::Alias(#ty))
+        }
+        Data::Struct(data) => {
+            let encoding = encoding(&options);
+            let fields = fields(&data.fields)?;
+            quote! {
+                ::kelk_lib::schema::Kind::Struct {
+                    encoding: #encoding,
+                    fields: &[#(#fields),*],
+                }
+            }
+        }
+        Data::Enum(data) => {
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                let variant_name = variant.ident.to_string();
+                let index = index(&variant.attrs, variant.span())?;
+                let encoding = match options.index_only {
+                    true => encoding(&options),
+                    false => encoding(&cbor_options(&variant.attrs)?),
+                };
+                let fields = fields(&variant.fields)?;
+                variants.push(quote! {
+                    ::kelk_lib::schema::Variant {
+                        name: #variant_name,
+                        index: #index,
+                        encoding: #encoding,
+                        fields: &[#(#fields),*],
+                    }
+                });
+            }
+            let encoding = encoding(&options);
+            quote! {
+                ::kelk_lib::schema::Kind::Enum {
+                    encoding: #encoding,
+                    variants: &[#(#variants),*],
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                input.span(),
+                "Schema can't be derived for unions",
+            ))
+        }
+    };
+
+    let type_name = name.to_string();
+    Ok(quote! {
+        impl #impl_generics ::kelk_lib::schema::Schema for #name #ty_generics #where_clause {
+            fn schema() -> ::kelk_lib::schema::TypeSchema {
+                ::kelk_lib::schema::TypeSchema {
+                    name: #type_name,
+                    kind: #kind,
+                }
+            }
+        }
+    })
+}
+
+fn encoding(options: &CborOptions) -> TokenStream {
+    if options.index_only {
+        quote!(::kelk_lib::schema::Encoding::IndexOnly)
+    } else if options.map {
+        quote!(::kelk_lib::schema::Encoding::Map)
+    } else {
+        quote!(::kelk_lib::schema::Encoding::Array)
+    }
+}
+
+fn fields(fields: &Fields) -> syn::Result<Vec<TokenStream>> {
+    let mut list = Vec::new();
+    for field in fields {
+        let name = match &field.ident {
+            Some(ident) => {
+                let ident = ident.to_string();
+                quote!(Some(#ident))
+            }
+            None => quote!(None),
+        };
+        let index = index(&field.attrs, field.span())?;
+        let ty = type_name(&field.ty);
+        list.push(quote! {
+            ::kelk_lib::schema::Field {
+                name: #name,
+                index: #index,
+                ty: #ty,
+            }
+        });
+    }
+    Ok(list)
+}
+
+/// reads the index from `#[n(...)]`, `#[b(...)]` or `#[cbor(n(...))]` attributes.
+fn index(attrs: &[Attribute], span: proc_macro2::Span) -> syn::Result<u32> {
+    for attr in attrs {
+        if attr.path.is_ident("n") || attr.path.is_ident("b") {
+            return match attr.parse_meta()? {
+                Meta::List(list) => parse_index(&list.nested),
+                meta => Err(syn::Error::new(meta.span(), "expected an index")),
+            };
+        }
+        if attr.path.is_ident("cbor") {
+            if let Meta::List(list) = attr.parse_meta()? {
+                for nested in &list.nested {
+                    if let NestedMeta::Meta(Meta::List(arg)) = nested {
+                        if arg.path.is_ident("n") || arg.path.is_ident("b") {
+                            return parse_index(&arg.nested);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Err(syn::Error::new(
+        span,
+        "missing `#[n(...)]` or `#[b(...)]` index",
+    ))
+}
+
+fn parse_index(
+    nested: &syn::punctuated::Punctuated<NestedMeta, syn::Token![,]>,
+) -> syn::Result<u32> {
+    match nested.first() {
+        Some(NestedMeta::Lit(Lit::Int(n))) => n.base10_parse(),
+        _ => Err(syn::Error::new(nested.span(), "expected a u32 index")),
+    }
+}
+
+fn cbor_options(attrs: &[Attribute]) -> syn::Result<CborOptions> {
+    let mut options = CborOptions::default();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("cbor")) {
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in &list.nested {
+                if let NestedMeta::Meta(Meta::Path(path)) = nested {
+                    if path.is_ident("map") {
+                        options.map = true;
+                    } else if path.is_ident("array") {
+                        options.map = false;
+                    } else if path.is_ident("index_only") {
+                        options.index_only = true;
+                    } else if path.is_ident("transparent") {
+                        options.transparent = true;
+                    }
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// formats the type as it is written in the source, e.g. `[u8; 4]` or `Option<u32>`.
+fn type_name(ty: &syn::Type) -> String {
+    let tokens = ty.to_token_stream().to_string();
+    let chars: Vec<char> = tokens.chars().collect();
+    let mut name = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == ' ' {
+            // Keep the space only between two words, e.g. `dyn Trait` or `'a str`.
+            let prev = chars[..i].last().copied().unwrap_or(' ');
+            let next = chars.get(i + 1).copied().unwrap_or(' ');
+            let is_word = |c: char| c.is_alphanumeric() || c == '_';
+            if is_word(prev) && (is_word(next) || next == '\'') {
+                name.push(' ');
+            }
+            continue;
+        }
+        name.push(*c);
+        if *c == ';' || *c == ',' {
+            name.push(' ');
+        }
+    }
+    name
+}
@@ -0,0 +1,277 @@
+//! Typed client for calling contracts from native Rust.
+//!
+//! The client shares the message types with the contract.
+//! It encodes the messages into the same CBOR bytes that the entry points expect
+//! and decodes the `Result` envelope that they return.
+//! Sending the bytes to the contract is left to a `Transport`,
+//! e.g. an RPC connection to a node or an in-process executor in tests.
+
+use core::fmt;
+use core::marker::PhantomData;
+use kelk_lib::alloc::vec::Vec;
+use kelk_lib::error::Error;
+use minicbor::{Decode, Encode};
+
+/// The entry points of a contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryPoint {
+    /// The `instantiate` entry point
+    Instantiate,
+    /// The `process_msg` entry point
+    ProcessMsg,
+    /// The `query` entry point
+    Query,
+    /// The `migrate` entry point
+    Migrate,
+}
+
+impl EntryPoint {
+    /// returns the name of the exported function
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryPoint::Instantiate => "instantiate",
+            EntryPoint::ProcessMsg => "process_msg",
+            EntryPoint::Query => "query",
+            EntryPoint::Migrate => "migrate",
+        }
+    }
+}
+
+/// The message and response types of a contract.
+/// Contracts usually implement it for a unit struct, so clients can refer to the contract by name.
+pub trait Contract {
+    /// The message of the `instantiate` entry point
+    type InstantiateMsg: Encode;
+    /// The message of the `process_msg` entry point
+    type ProcMsg: Encode;
+    /// The message of the `query` entry point
+    type QueryMsg: Encode;
+    /// The response of the `query` entry point
+    type QueryRsp: for<'b> Decode<'b>;
+    /// The message of the `migrate` entry point
+    type MigrateMsg: Encode;
+    /// The error type of the contract
+    type Error: for<'b> Decode<'b>;
+}
+
+/// Transport sends the encoded message to an entry point of the contract
+/// and returns the encoded result.
+pub trait Transport {
+    /// The error raised by the transport, e.g. a connection error
+    type Error;
+
+    /// calls the entry point with the encoded message
+    fn call(&self, entry: EntryPoint, msg: &[u8]) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// Error returned by the client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientError<T, E> {
+    /// The transport failed to call the contract
+    Transport(T),
+    /// The contract returned an error
+    Contract(E),
+    /// The message or the result can't be encoded or decoded
+    InvalidCbor,
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Display for ClientError<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(err) => write!(f, "transport error: {:?}", err),
+            ClientError::Contract(err) => write!(f, "contract error: {:?}", err),
+            ClientError::InvalidCbor => write!(f, "invalid CBOR data"),
+        }
+    }
+}
+
+/// encodes the message of an entry point
+pub fn encode_msg<D: Encode>(msg: &D) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    minicbor::encode(msg, &mut buf).map_err(|_| Error::InvalidCbor)?;
+    Ok(buf)
+}
+
+/// decodes the message of an entry point
+pub fn decode_msg<'a, D: Decode<'a>>(buf: &'a [u8]) -> Result<D, Error> {
+    minicbor::decode(buf).map_err(|_| Error::InvalidCbor)
+}
+
+/// encodes the result of an entry point
+pub fn encode_result<R: Encode, E: Encode>(res: &Result<R, E>) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    minicbor::encode(res, &mut buf).map_err(|_| Error::InvalidCbor)?;
+    Ok(buf)
+}
+
+/// decodes the result of an entry point
+pub fn decode_result<'a, R: Decode<'a>, E: Decode<'a>>(
+    buf: &'a [u8],
+) -> Result<Result<R, E>, Error> {
+    minicbor::decode(buf).map_err(|_| Error::InvalidCbor)
+}
+
+/// Client calls the entry points of the contract `C` through the transport `T`.
+pub struct Client<C: Contract, T: Transport> {
+    transport: T,
+    contract: PhantomData<C>,
+}
+
+type ClientResult<R, C, T> =
+    Result<R, ClientError<<T as Transport>::Error, <C as Contract>::Error>>;
+
+impl<C: Contract, T: Transport> Client<C, T> {
+    /// creates a new client
+    pub fn new(transport: T) -> Self {
+        Client {
+            transport,
+            contract: PhantomData,
+        }
+    }
+
+    /// returns the transport of the client
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// instantiates the contract
+    pub fn instantiate(&self, msg: &C::InstantiateMsg) -> ClientResult<(), C, T> {
+        self.call(EntryPoint::Instantiate, msg)
+    }
+
+    /// sends the message to the `process_msg` entry point
+    pub fn process_msg(&self, msg: &C::ProcMsg) -> ClientResult<(), C, T> {
+        self.call(EntryPoint::ProcessMsg, msg)
+    }
+
+    /// queries the contract
+    pub fn query(&self, msg: &C::QueryMsg) -> ClientResult<C::QueryRsp, C, T> {
+        self.call(EntryPoint::Query, msg)
+    }
+
+    /// migrates the contract
+    pub fn migrate(&self, msg: &C::MigrateMsg) -> ClientResult<(), C, T> {
+        self.call(EntryPoint::Migrate, msg)
+    }
+
+    fn call<D: Encode, R: for<'b> Decode<'b>>(
+        &self,
+        entry: EntryPoint,
+        msg: &D,
+    ) -> ClientResult<R, C, T> {
+        let msg = encode_msg(msg).map_err(|_| ClientError::InvalidCbor)?;
+        let buf = self
+            .transport
+            .call(entry, &msg)
+            .map_err(ClientError::Transport)?;
+        match decode_result::<R, C::Error>(&buf) {
+            Ok(Ok(rsp)) => Ok(rsp),
+            Ok(Err(err)) => Err(ClientError::Contract(err)),
+            Err(_) => Err(ClientError::InvalidCbor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use kelk_lib::alloc::vec;
+
+    struct Counter;
+
+    impl Contract for Counter {
+        type InstantiateMsg = u32;
+        type ProcMsg = u32;
+        type QueryMsg = ();
+        type QueryRsp = u32;
+        type MigrateMsg = ();
+        type Error = i32;
+    }
+
+    #[derive(Default)]
+    struct MockTransport {
+        value: RefCell<u32>,
+        calls: RefCell<Vec<EntryPoint>>,
+    }
+
+    impl Transport for MockTransport {
+        type Error = &'static str;
+
+        fn call(&self, entry: EntryPoint, msg: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            self.calls.borrow_mut().push(entry);
+            let res: Result<Option<u32>, i32> = match entry {
+                EntryPoint::Instantiate => {
+                    *self.value.borrow_mut() = decode_msg(msg).unwrap();
+                    Ok(None)
+                }
+                EntryPoint::ProcessMsg => {
+                    let add: u32 = decode_msg(msg).unwrap();
+                    let mut value = self.value.borrow_mut();
+                    match value.checked_add(add) {
+                        Some(v) => {
+                            *value = v;
+                            Ok(None)
+                        }
+                        None => Err(-1),
+                    }
+                }
+                EntryPoint::Query => Ok(Some(*self.value.borrow())),
+                EntryPoint::Migrate => return Err("not supported"),
+            };
+            Ok(match res {
+                Ok(None) => encode_result::<(), i32>(&Ok(())).unwrap(),
+                Ok(Some(v)) => encode_result::<u32, i32>(&Ok(v)).unwrap(),
+                Err(e) => encode_result::<(), i32>(&Err(e)).unwrap(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_envelope() {
+        // Result::Ok(()) -> http://cbor.me/?bytes=820080
+        assert_eq!(
+            encode_result::<(), i32>(&Ok(())).unwrap(),
+            vec![0x82, 0x00, 0x80]
+        );
+        // Result::Err(14) -> http://cbor.me/?bytes=82010e
+        assert_eq!(
+            encode_result::<(), i32>(&Err(14)).unwrap(),
+            vec![0x82, 0x01, 0x0e]
+        );
+        assert_eq!(
+            decode_result::<(), i32>(&[0x82, 0x01, 0x0e]).unwrap(),
+            Err(14)
+        );
+        assert_eq!(
+            decode_result::<(), i32>(&[0x82, 0x02]),
+            Err(Error::InvalidCbor)
+        );
+    }
+
+    #[test]
+    fn test_client() {
+        let client = Client::<Counter, _>::new(MockTransport::default());
+        client.instantiate(&5).unwrap();
+        client.process_msg(&3).unwrap();
+        assert_eq!(client.query(&()), Ok(8));
+        assert_eq!(
+            client.process_msg(&u32::MAX),
+            Err(ClientError::Contract(-1))
+        );
+        assert_eq!(
+            client.migrate(&()),
+            Err(ClientError::Transport("not supported"))
+        );
+        assert_eq!(
+            *client.transport().calls.borrow(),
+            vec![
+                EntryPoint::Instantiate,
+                EntryPoint::ProcessMsg,
+                EntryPoint::Query,
+                EntryPoint::ProcessMsg,
+                EntryPoint::Migrate,
+            ]
+        );
+    }
+}
@@ -1,15 +1,74 @@
 //! The context for running contract actor
 
 use crate::params::*;
+use kelk_lib::alloc::vec::Vec;
+use kelk_lib::error::{HostError, HostErrorKind};
+use kelk_lib::hash::{Hash, Hasher};
+use kelk_lib::random::Rng;
+use kelk_lib::storage::{ReadOnlyStorage, Storage};
 
-/// `ContextAPI` provides the necessary APIs to interact with the Tanour.
-/// It can't be copied or cloned since it doesn't have Copy and Clone traits.
-pub trait ContextAPI: kelk_lib::storage::Storage {
+/// `QueryContextAPI` provides the read-only APIs to interact with the Tanour.
+/// Queries receive this API, so they can read the storage but they can't change it.
+pub trait QueryContextAPI: ReadOnlyStorage {
     /// TODO move it to lib crate
     /// gets the parameter value
     fn get_param(&self, param_id: i32) -> Option<ParamType>;
+
+    /// sends the debug message to the host.
+    /// Use the `debug!` macro instead, so the messages are removed when the `debug` feature is disabled.
+    fn debug(&self, msg: &str);
+
+    /// returns `len` random bytes from the host.
+    /// The bytes are deterministic for the transaction, e.g. derived from the block hash
+    /// and the transaction hash, so all the validators get the same bytes.
+    fn random_bytes(&self, len: u32) -> Result<Vec<u8>, HostError>;
+
+    /// returns a random `u64` from the host.
+    /// It fails with `InvalidArgument` if the host doesn't return 8 bytes.
+    fn random_u64(&self) -> Result<u64, HostError> {
+        let bytes = self.random_bytes(8)?;
+        Ok(u64::from_le_bytes(random_array(&bytes)?))
+    }
+
+    /// returns a pseudo-random number generator that is seeded by the host.
+    /// It is cheaper than calling the host for every random number.
+    /// It fails with `InvalidArgument` if the host doesn't return 32 bytes.
+    fn rng(&self) -> Result<Rng, HostError> {
+        let bytes = self.random_bytes(32)?;
+        Ok(Rng::from_seed(random_array(&bytes)?))
+    }
+
+    /// returns the SHA-256 hash of the data, which is calculated by the host.
+    fn sha256(&self, data: &[u8]) -> Hash;
+}
+
+/// converts the random bytes of the host to an array, checking the length.
+fn random_array<const N: usize>(bytes: &[u8]) -> Result<[u8; N], HostError> {
+    bytes
+        .try_into()
+        .map_err(|_| HostErrorKind::InvalidArgument.into())
+}
+
+/// The contracts can pass the context as the hasher of the authenticated collections,
+/// so the data is hashed by the host.
+impl Hasher for dyn QueryContextAPI + '_ {
+    fn hash(&self, data: &[u8]) -> Hash {
+        self.sha256(data)
+    }
+}
+
+impl Hasher for dyn ContextAPI + '_ {
+    fn hash(&self, data: &[u8]) -> Hash {
+        self.sha256(data)
+    }
 }
 
+/// `ContextAPI` provides the necessary APIs to interact with the Tanour.
+/// It can't be copied or cloned since it doesn't have Copy and Clone traits.
+pub trait ContextAPI: QueryContextAPI + Storage {}
+
+impl<T: QueryContextAPI + Storage + ?Sized> ContextAPI for T {}
+
 /// `OwnedContext` owns the `ContextAPI` instance. It allow dependency injection at runtime.
 /// This cannot be copied or cloned since `api` doesn't implement Copy and Clone traits.
 /// It can be easily mocked for the testing environment.
@@ -24,9 +83,70 @@ pub struct Context<'a> {
     pub api: &'a dyn ContextAPI,
 }
 
+/// `QueryContext` owns the `QueryContextAPI` reference.
+/// It is passed to the queries and it has no access to the storage writes.
+pub struct QueryContext<'a> {
+    /// The instance of QueryContextAPI
+    pub api: &'a dyn QueryContextAPI,
+}
+
 impl<C: ContextAPI> OwnedContext<C> {
     /// returns the context as reference
     pub fn as_ref(&'_ self) -> Context<'_> {
         Context { api: &self.api }
     }
+
+    /// returns the read-only context as reference
+    pub fn as_query(&'_ self) -> QueryContext<'_> {
+        QueryContext { api: &self.api }
+    }
+}
+
+impl<'a> Context<'a> {
+    /// returns the read-only view of the context
+    pub fn as_query(&self) -> QueryContext<'a> {
+        QueryContext { api: self.api }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A host that returns one byte less than the requested length
+    struct ShortHost;
+
+    impl ReadOnlyStorage for ShortHost {
+        fn sread(&self, _offset: u32, _len: u32) -> Result<Vec<u8>, HostError> {
+            unimplemented!()
+        }
+    }
+
+    impl QueryContextAPI for ShortHost {
+        fn get_param(&self, _param_id: i32) -> Option<ParamType> {
+            unimplemented!()
+        }
+
+        fn debug(&self, _msg: &str) {}
+
+        fn random_bytes(&self, len: u32) -> Result<Vec<u8>, HostError> {
+            Ok(kelk_lib::alloc::vec![0; len as usize - 1])
+        }
+
+        fn sha256(&self, _data: &[u8]) -> Hash {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_short_random_bytes() {
+        assert_eq!(
+            ShortHost.random_u64().unwrap_err().kind,
+            HostErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            ShortHost.rng().err().unwrap().kind,
+            HostErrorKind::InvalidArgument
+        );
+    }
 }
@@ -0,0 +1,70 @@
+//! Debug messages for developing contracts.
+//!
+//! The `debug!` macro formats the message with `core::fmt` and sends it to the host
+//! through the `debug` import. The mocked context collects the messages,
+//! so tests can print them or assert on them.
+//!
+//! The messages are only sent if the `debug` feature of kelk-env is enabled.
+//! Otherwise the macro compiles to nothing, so release builds don't pay for formatting.
+//!
+//! ```
+//! use kelk_env::debug;
+//! use kelk_env::mock::mock_context;
+//!
+//! let ctx = mock_context(256);
+//! debug!(ctx.as_ref(), "result: {}", 42);
+//! # #[cfg(feature = "debug")]
+//! assert_eq!(ctx.api.debug_lines(), ["result: 42"]);
+//! ```
+
+#[doc(hidden)]
+pub use kelk_lib::alloc::format;
+
+/// Sends a formatted debug message to the host.
+/// The first argument is the context (`Context` or `QueryContext`),
+/// followed by the format string and its arguments, like `format!`.
+#[cfg(feature = "debug")]
+#[macro_export]
+macro_rules! debug {
+    ($ctx:expr, $($arg:tt)+) => {
+        $crate::context::QueryContextAPI::debug($ctx.api, &$crate::debug::format!($($arg)+))
+    };
+}
+
+/// Sends a formatted debug message to the host.
+/// The `debug` feature is disabled, so the message is not sent.
+/// The arguments are still type-checked, but they are never formatted.
+#[cfg(not(feature = "debug"))]
+#[macro_export]
+macro_rules! debug {
+    ($ctx:expr, $($arg:tt)+) => {
+        if false {
+            let _ = &$ctx;
+            let _ = ::core::format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+    use crate::mock::mock_context;
+
+    fn double(ctx: Context, value: i32) -> i32 {
+        debug!(ctx, "doubling {}", value);
+        value * 2
+    }
+
+    #[test]
+    fn test_debug() {
+        let ctx = mock_context(256);
+        assert_eq!(double(ctx.as_ref(), 3), 6);
+        debug!(ctx.as_query(), "{:?}", [1, 2]);
+
+        if cfg!(feature = "debug") {
+            assert_eq!(ctx.api.debug_lines(), ["doubling 3", "[1, 2]"]);
+        } else {
+            assert!(ctx.api.debug_lines().is_empty());
+        }
+    }
+}
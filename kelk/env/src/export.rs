@@ -1,13 +1,16 @@
 //! Kelk public API
 //!
-//! `do_deploy`, `do_process` and `do_query`
+//! `do_instantiate`, `do_process_msg`, `do_query` and `do_migrate`
 //! should be wrapped with a extern "C" entry point including
 //! the contract-specific function pointer.
 //! This is done via the `#[entry_point]` macro attribute.
 
-use crate::context::{Context, OwnedContext};
+use crate::client;
+use crate::context::{Context, OwnedContext, QueryContext};
 use crate::import::ContextExt;
+use crate::lifecycle::{self, MigrateFn};
 use crate::memory::Pointer;
+use kelk_lib::error::Error;
 use minicbor::{Decode, Encode};
 
 /// allocate reserves the given number of bytes in wasm memory and returns a pointer
@@ -27,29 +30,56 @@ extern "C" fn deallocate(ptr_u64: u64) {
 
 /// do_instantiate should be wrapped in an external "C" export,
 /// containing a contract-specific function as arg.
-pub fn do_instantiate<'a, D: Decode<'a>, E: Encode>(
+/// It fails with `AlreadyInstantiated` error if the contract is instantiated before.
+pub fn do_instantiate<'a, D: Decode<'a>, E: Encode + From<Error>>(
     instantiate_fn: &dyn Fn(Context, D) -> Result<(), E>,
     msg_ptr: u64,
 ) -> u64 {
-    do_execute(instantiate_fn, msg_ptr)
+    do_execute(
+        &|ctx, msg| lifecycle::instantiate(instantiate_fn, ctx, msg),
+        msg_ptr,
+    )
 }
 
 /// do_process_msg should be wrapped in an external "C" export,
 /// containing a contract-specific function as arg.
-pub fn do_process_msg<'a, D: Decode<'a>, E: Encode>(
+/// It fails with `NotInstantiated` error if the contract is not instantiated yet.
+pub fn do_process_msg<'a, D: Decode<'a>, E: Encode + From<Error>>(
     process_msg_fn: &dyn Fn(Context, D) -> Result<(), E>,
     msg_ptr: u64,
 ) -> u64 {
-    do_execute(process_msg_fn, msg_ptr)
+    do_execute(
+        &|ctx, msg| lifecycle::process_msg(process_msg_fn, ctx, msg),
+        msg_ptr,
+    )
 }
 
 /// do_query should be wrapped in an external "C" export,
 /// containing a contract-specific function as arg.
-pub fn do_query<'a, D: Decode<'a>, R: Encode, E: Encode>(
-    query_fn: &dyn Fn(Context, D) -> Result<R, E>,
+/// The query function receives a read-only context, so it can't change the storage.
+/// It fails with `NotInstantiated` error if the contract is not instantiated yet.
+pub fn do_query<'a, D: Decode<'a>, R: Encode, E: Encode + From<Error>>(
+    query_fn: &dyn Fn(QueryContext, D) -> Result<R, E>,
     msg_ptr: u64,
 ) -> u64 {
-    do_execute(query_fn, msg_ptr)
+    do_execute(
+        &|ctx: Context, msg| lifecycle::query(query_fn, ctx.as_query(), msg),
+        msg_ptr,
+    )
+}
+
+/// do_migrate should be wrapped in an external "C" export,
+/// containing a contract-specific function as arg.
+/// The migrate function receives the version of the old contract and returns the new version.
+/// It fails with `NotInstantiated` error if the contract is not instantiated yet.
+pub fn do_migrate<'a, D: Decode<'a>, E: Encode + From<Error>>(
+    migrate_fn: &MigrateFn<'_, D, E>,
+    msg_ptr: u64,
+) -> u64 {
+    do_execute(
+        &|ctx, msg| lifecycle::migrate(migrate_fn, ctx, msg),
+        msg_ptr,
+    )
 }
 
 fn do_execute<'a, D: Decode<'a>, R: Encode, E: Encode>(
@@ -58,11 +88,10 @@ fn do_execute<'a, D: Decode<'a>, R: Encode, E: Encode>(
 ) -> u64 {
     let ptr = Pointer::from_u64(msg_ptr);
     let buf = unsafe { ptr.to_slice() };
-    let msg = minicbor::decode(buf).expect("Decoding failed");
+    let msg = client::decode_msg(buf).expect("Decoding failed");
     let ctx = make_context();
     let res = func(ctx.as_ref(), msg);
-    let mut vec = kelk_lib::alloc::vec::Vec::new();
-    minicbor::encode(res, &mut vec).expect("Encoding failed");
+    let vec = client::encode_result(&res).expect("Encoding failed");
 
     Pointer::release_buffer(vec).as_u64()
 }
@@ -77,7 +106,11 @@ pub(crate) fn make_context() -> OwnedContext<ContextExt> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::import::clear_test_storage;
+    use crate::lifecycle::{ContractVersion, DATA_OFFSET};
     use kelk_lib::alloc::vec;
+    use kelk_lib::alloc::vec::Vec;
+
     use wasm_bindgen_test::*;
 
     // Uncomment this test if should_panic supported by wasm_bindgen_test.
@@ -93,87 +126,123 @@ mod tests {
     //     deallocate(ptr);
     // }
 
-    #[wasm_bindgen_test]
-    fn test_instantiate() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
-
-        let res_ptr = do_instantiate(
-            &|_: Context, _: i32| -> Result<(), i32> { Ok(()) },
-            msg_ptr.as_u64(),
-        );
+    // The test storage is shared by the tests, so each test clears it first.
 
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
-        assert_eq!(res_data, vec![0x82, 0x00, 0x80]); // Result::Ok(()) -> http://cbor.me/?bytes=820080
+    fn call(msg: u32, entry: impl FnOnce(u64) -> u64) -> Vec<u8> {
+        let msg_ptr = Pointer::release_buffer(client::encode_msg(&msg).unwrap());
+        let res_ptr = entry(msg_ptr.as_u64());
+        unsafe { Pointer::from_u64(res_ptr).to_slice() }.to_vec()
     }
 
-    #[wasm_bindgen_test]
-    fn test_instantiate_error() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
-
-        let res_ptr = do_instantiate(
-            &|_: Context, _: i32| -> Result<(), i32> { Err(0x0e) },
-            msg_ptr.as_u64(),
-        );
+    fn instantiate(msg_ptr: u64) -> u64 {
+        do_instantiate(
+            &|ctx: Context, value: u32| -> Result<(), Error> {
+                ctx.api.swrite_u32(DATA_OFFSET, value)?;
+                Ok(())
+            },
+            msg_ptr,
+        )
+    }
 
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
-        assert_eq!(res_data, vec![0x82, 0x01, 0x0e]); // Result::Err(0x0e) -> http://cbor.me/?bytes=82010e
+    fn result<R: for<'b> Decode<'b>>(res_data: &[u8]) -> Result<R, Error> {
+        client::decode_result(res_data).unwrap()
     }
 
     #[wasm_bindgen_test]
-    fn test_process_msg() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
-
-        let res_ptr = do_process_msg(
-            &|_: Context, _: i32| -> Result<(), i32> { Ok(()) },
-            msg_ptr.as_u64(),
-        );
+    fn test_instantiate() {
+        clear_test_storage();
 
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
+        let res_data = call(0, instantiate);
         assert_eq!(res_data, vec![0x82, 0x00, 0x80]); // Result::Ok(()) -> http://cbor.me/?bytes=820080
+
+        let res_data = call(0, instantiate);
+        assert_eq!(result::<()>(&res_data), Err(Error::AlreadyInstantiated));
     }
 
     #[wasm_bindgen_test]
-    fn test_process_msg_error() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
+    fn test_instantiate_error() {
+        clear_test_storage();
+
+        let res_data = call(0, |msg_ptr| {
+            do_instantiate(
+                &|_: Context, _: u32| -> Result<(), Error> { Err(Error::OutOfRange) },
+                msg_ptr,
+            )
+        });
+        assert_eq!(result::<()>(&res_data), Err(Error::OutOfRange));
+
+        // A failed instantiation doesn't mark the contract as instantiated.
+        let res_data = call(0, instantiate);
+        assert_eq!(result::<()>(&res_data), Ok(()));
+    }
 
-        let res_ptr = do_process_msg(
-            &|_: Context, _: i32| -> Result<(), i32> { Err(0x0e) },
-            msg_ptr.as_u64(),
-        );
+    #[wasm_bindgen_test]
+    fn test_process_msg() {
+        clear_test_storage();
+        let process_msg = |msg_ptr| {
+            do_process_msg(
+                &|ctx: Context, value: u32| -> Result<(), Error> {
+                    match value {
+                        0 => Err(Error::OutOfRange),
+                        _ => Ok(ctx.api.swrite_u32(DATA_OFFSET, value)?),
+                    }
+                },
+                msg_ptr,
+            )
+        };
+
+        let res_data = call(1, process_msg);
+        assert_eq!(result::<()>(&res_data), Err(Error::NotInstantiated));
+
+        call(7, instantiate);
+        let res_data = call(1, process_msg);
+        assert_eq!(res_data, vec![0x82, 0x00, 0x80]); // Result::Ok(()) -> http://cbor.me/?bytes=820080
 
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
-        assert_eq!(res_data, vec![0x82, 0x01, 0x0e]); // Result::Err(0x0e) -> http://cbor.me/?bytes=82010e
+        let res_data = call(0, process_msg);
+        assert_eq!(result::<()>(&res_data), Err(Error::OutOfRange));
     }
 
     #[wasm_bindgen_test]
     fn test_query() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
-
-        let res_ptr = do_query(
-            &|_: Context, _: i32| -> Result<&str, i32> { Ok("foo") },
-            msg_ptr.as_u64(),
-        );
-
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
-        assert_eq!(res_data, vec![0x82, 0x00, 0x63, 0x66, 0x6f, 0x6f]); // Result::Ok("foo") -> http://cbor.me/?bytes=0x820063666f6f
+        clear_test_storage();
+        let query = |msg_ptr| {
+            do_query(
+                &|ctx: QueryContext, _: u32| -> Result<u32, Error> {
+                    Ok(ctx.api.sread_u32(DATA_OFFSET)?)
+                },
+                msg_ptr,
+            )
+        };
+
+        let res_data = call(0, query);
+        assert_eq!(result::<u32>(&res_data), Err(Error::NotInstantiated));
+
+        call(7, instantiate);
+        let res_data = call(0, query);
+        assert_eq!(res_data, vec![0x82, 0x00, 0x07]); // Result::Ok(7) -> http://cbor.me/?bytes=820007
     }
 
     #[wasm_bindgen_test]
-    fn test_query_error() {
-        let msg_data = vec![0x00]; // http://cbor.me/?bytes=8100
-        let msg_ptr = Pointer::release_buffer(msg_data);
-
-        let res_ptr = do_query(
-            &|_: Context, _: i32| -> Result<&str, i32> { Err(0x0e) },
-            msg_ptr.as_u64(),
-        );
-
-        let res_data = unsafe { Pointer::from_u64(res_ptr).to_slice() };
-        assert_eq!(res_data, vec![0x82, 0x01, 0x0e]); // Result::Err(0x0e) -> http://cbor.me/?bytes=82010e
+    fn test_migrate() {
+        clear_test_storage();
+        let migrate = |msg_ptr| {
+            do_migrate(
+                &|_: Context, old: Option<ContractVersion>, minor: u32| {
+                    assert_eq!(old, None);
+                    Ok::<_, Error>(ContractVersion::new("test", 0, minor, 0))
+                },
+                msg_ptr,
+            )
+        };
+
+        let res_data = call(1, migrate);
+        assert_eq!(result::<()>(&res_data), Err(Error::NotInstantiated));
+
+        call(7, instantiate);
+        let res_data = call(1, migrate);
+        assert_eq!(result::<()>(&res_data), Ok(()));
+        let ctx = make_context();
+        let version = lifecycle::get_version(&ctx.api).unwrap();
+        assert_eq!(version, Some(ContractVersion::new("test", 0, 1, 0)));
     }
 }
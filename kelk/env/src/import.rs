@@ -1,8 +1,11 @@
-use crate::context::ContextAPI;
+use crate::context::QueryContextAPI;
 use crate::params::*;
 use kelk_lib::alloc::vec::Vec;
 use kelk_lib::error::HostError;
-use kelk_lib::storage::Storage;
+#[cfg(test)]
+use kelk_lib::error::HostErrorKind;
+use kelk_lib::hash::Hash;
+use kelk_lib::storage::{ReadOnlyStorage, Storage};
 
 #[cfg(not(test))]
 #[link(wasm_import_module = "zarb")]
@@ -15,6 +18,25 @@ extern "C" {
     /// `ptr` is the location in sandbox memory where data should be written to.
     /// `len` is the length of data.
     fn read_storage(offset: u32, ptr: u32, len: u32) -> i32;
+    /// aborts the execution of the contract.
+    /// `ptr` is the location in sandbox memory where the UTF-8 message should be read from.
+    /// `len` is the length of the message.
+    /// It is used by the panic handler of the `setup` feature.
+    #[cfg(feature = "setup")]
+    fn abort(ptr: u32, len: u32);
+    /// writes the debug message into the host's log.
+    /// `ptr` is the location in sandbox memory where the UTF-8 message should be read from.
+    /// `len` is the length of the message.
+    fn debug(ptr: u32, len: u32);
+    /// writes deterministic random bytes into the sandbox memory.
+    /// `ptr` is the location in sandbox memory where the bytes should be written to.
+    /// `len` is the number of the bytes.
+    fn random_bytes(ptr: u32, len: u32) -> i32;
+    /// writes the SHA-256 hash of the data into the sandbox memory.
+    /// `ptr` is the location in sandbox memory where the data should be read from.
+    /// `len` is the length of the data.
+    /// `hash_ptr` is the location in sandbox memory where the 32-byte hash should be written to.
+    fn sha256(ptr: u32, len: u32, hash_ptr: u32);
 }
 
 pub(crate) struct ContextExt {}
@@ -27,16 +49,73 @@ impl ContextExt {
 
 // TODO:
 // Is it possible to create a zarb module for testing and remove these code?
+
+/// The storage file of the tests. The tests run in a single thread of the sandbox.
 #[cfg(test)]
-pub unsafe fn write_storage(_offset: u32, _ptr: u32, _len: u32) -> i32 {
-    0
+static mut TEST_STORAGE: [u8; 1024] = [0; 1024];
+
+/// clears the storage file of the tests.
+#[cfg(test)]
+pub(crate) fn clear_test_storage() {
+    unsafe { core::ptr::write_bytes(core::ptr::addr_of_mut!(TEST_STORAGE), 0, 1) };
+}
+
+#[cfg(test)]
+unsafe fn test_storage_at(offset: u32, len: u32) -> Option<*mut u8> {
+    match offset.checked_add(len) {
+        Some(end) if end as usize <= 1024 => Some(
+            core::ptr::addr_of_mut!(TEST_STORAGE)
+                .cast::<u8>()
+                .add(offset as usize),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub unsafe fn write_storage(offset: u32, ptr: u32, len: u32) -> i32 {
+    match test_storage_at(offset, len) {
+        Some(dst) => {
+            core::ptr::copy_nonoverlapping(ptr as *const u8, dst, len as usize);
+            0
+        }
+        None => HostErrorKind::OutOfBounds.code(),
+    }
+}
+
+#[cfg(test)]
+pub unsafe fn read_storage(offset: u32, ptr: u32, len: u32) -> i32 {
+    match test_storage_at(offset, len) {
+        Some(src) => {
+            core::ptr::copy_nonoverlapping(src, ptr as *mut u8, len as usize);
+            0
+        }
+        None => HostErrorKind::OutOfBounds.code(),
+    }
 }
 
+#[cfg(all(test, feature = "setup"))]
+pub unsafe fn abort(_ptr: u32, _len: u32) {}
+
 #[cfg(test)]
-pub unsafe fn read_storage(_offset: u32, _ptr: u32, _len: u32) -> i32 {
+pub unsafe fn debug(_ptr: u32, _len: u32) {}
+
+#[cfg(test)]
+pub unsafe fn random_bytes(_ptr: u32, _len: u32) -> i32 {
     0
 }
 
+#[cfg(test)]
+pub unsafe fn sha256(_ptr: u32, _len: u32, _hash_ptr: u32) {}
+
+/// sends the message to the host and aborts the execution.
+/// The host is not expected to return, but if it does, the execution traps here.
+#[cfg(feature = "setup")]
+pub(crate) fn abort_with_message(msg: &[u8]) -> ! {
+    unsafe { abort(msg.as_ptr() as u32, msg.len() as u32) };
+    core::arch::wasm32::unreachable()
+}
+
 impl Storage for ContextExt {
     fn swrite(&self, offset: u32, data: &[u8]) -> Result<(), HostError> {
         let ptr = data.as_ptr() as u32;
@@ -44,26 +123,55 @@ impl Storage for ContextExt {
 
         let code = unsafe { write_storage(offset, ptr, len) };
         if code != 0 {
-            return Err(HostError { code });
+            return Err(HostError::from(code));
         }
         Ok(())
     }
+}
 
+impl ReadOnlyStorage for ContextExt {
     fn sread(&self, offset: u32, len: u32) -> Result<Vec<u8>, HostError> {
         let vec = kelk_lib::alloc::vec![0; len as usize];
         let ptr = vec.as_ptr() as u32;
 
         let code = unsafe { read_storage(offset, ptr, len) };
         if code != 0 {
-            return Err(HostError { code });
+            return Err(HostError::from(code));
         }
         Ok(vec)
     }
 }
 
-impl ContextAPI for ContextExt {
+impl QueryContextAPI for ContextExt {
     /// todo
     fn get_param(&self, _param_id: i32) -> Option<ParamType> {
         unimplemented!();
     }
+
+    fn debug(&self, msg: &str) {
+        unsafe { debug(msg.as_ptr() as u32, msg.len() as u32) };
+    }
+
+    fn random_bytes(&self, len: u32) -> Result<Vec<u8>, HostError> {
+        let vec = kelk_lib::alloc::vec![0; len as usize];
+        let ptr = vec.as_ptr() as u32;
+
+        let code = unsafe { random_bytes(ptr, len) };
+        if code != 0 {
+            return Err(HostError::from(code));
+        }
+        Ok(vec)
+    }
+
+    fn sha256(&self, data: &[u8]) -> Hash {
+        let mut hash = [0; 32];
+        unsafe {
+            sha256(
+                data.as_ptr() as u32,
+                data.len() as u32,
+                hash.as_mut_ptr() as u32,
+            )
+        };
+        hash
+    }
 }
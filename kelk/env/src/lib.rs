@@ -5,14 +5,12 @@
     missing_docs,
     bad_style,
     bare_trait_objects,
-    const_err,
     improper_ctypes,
     non_shorthand_field_patterns,
     no_mangle_generic_items,
     overflowing_literals,
     path_statements,
     patterns_in_fns_without_body,
-    private_in_public,
     unconditional_recursion,
     unused_allocation,
     unused_comparisons,
@@ -23,10 +21,17 @@
     unused_extern_crates
 )]
 
+pub mod client;
 pub mod context;
+#[macro_use]
+pub mod debug;
+pub mod lifecycle;
 pub mod mock;
 pub mod params;
 
+#[cfg(feature = "setup")]
+pub mod setup;
+
 #[cfg(target_arch = "wasm32")]
 mod memory;
 
@@ -37,6 +42,6 @@ mod import;
 pub mod export;
 
 #[cfg(target_arch = "wasm32")]
-pub use crate::export::{do_instantiate, do_process_msg, do_query};
+pub use crate::export::{do_instantiate, do_migrate, do_process_msg, do_query};
 
 pub use kelk_derive::kelk_derive;
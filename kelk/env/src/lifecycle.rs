@@ -0,0 +1,342 @@
+//! The lifecycle of the contract.
+//!
+//! Kelk reserves the first `RESERVED_SIZE` bytes of the storage file for its own bookkeeping.
+//! Contracts should keep their data at `DATA_OFFSET` or after it.
+//!
+//! The entry points are guarded by the lifecycle:
+//! `instantiate` runs only once, and `process_msg`, `query` and `migrate` run only after instantiation.
+//!
+//! The reserved region also keeps the version of the contract code that wrote the storage.
+//! Migration handlers receive this version, so they can transform the storage layout
+//! of the old code into the layout of the new code.
+
+use crate::context::{Context, QueryContext};
+use core::fmt;
+use kelk_lib::alloc::string::String;
+use kelk_lib::collections::item::{Slot, StorageItem};
+use kelk_lib::error::Error;
+use kelk_lib::storage::{CborValue, ReadOnlyStorage, Storage};
+use minicbor::{Decode, Encode};
+
+/// The offset of the reserved region in the storage file
+pub const RESERVED_OFFSET: u32 = 0;
+/// The size of the reserved region in the storage file
+pub const RESERVED_SIZE: u32 = 128;
+/// The first offset that contracts can use for their own data
+pub const DATA_OFFSET: u32 = RESERVED_OFFSET + RESERVED_SIZE;
+
+/// The instantiation flag, it keeps `INSTANTIATED_MAGIC` once the contract is instantiated.
+const INSTANTIATED: Slot = Slot::new(RESERVED_OFFSET);
+const INSTANTIATED_MAGIC: u32 = 0x6b656c6b; // "kelk"
+
+/// The version record, it keeps the CBOR-encoded version of the contract.
+const VERSION: Slot = Slot::new(RESERVED_OFFSET + 8);
+const VERSION_CAPACITY: usize = 112;
+
+type VersionRecord = CborValue<ContractVersion, VERSION_CAPACITY>;
+
+/// The name and the semantic version of the contract code.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ContractVersion {
+    /// The name of the contract
+    #[n(0)]
+    pub name: String,
+    /// The major version, incremented on incompatible changes
+    #[n(1)]
+    pub major: u32,
+    /// The minor version, incremented on backward compatible changes
+    #[n(2)]
+    pub minor: u32,
+    /// The patch version, incremented on bug fixes
+    #[n(3)]
+    pub patch: u32,
+}
+
+impl ContractVersion {
+    /// creates a new contract version
+    pub fn new(name: &str, major: u32, minor: u32, patch: u32) -> Self {
+        ContractVersion {
+            name: name.into(),
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for ContractVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v{}.{}.{}",
+            self.name, self.major, self.minor, self.patch
+        )
+    }
+}
+
+/// The migrate function of the contract.
+/// It receives the old version of the contract and returns the new version.
+pub type MigrateFn<'a, D, E> =
+    dyn Fn(Context, Option<ContractVersion>, D) -> Result<ContractVersion, E> + 'a;
+
+/// returns true if the contract has been instantiated.
+pub fn is_instantiated(storage: &dyn ReadOnlyStorage) -> Result<bool, Error> {
+    let item = StorageItem::<u32, _>::from_slot(storage, &INSTANTIATED);
+    Ok(item.may_load()? == Some(INSTANTIATED_MAGIC))
+}
+
+/// returns `NotInstantiated` error if the contract has not been instantiated.
+pub fn ensure_instantiated(storage: &dyn ReadOnlyStorage) -> Result<(), Error> {
+    match is_instantiated(storage)? {
+        true => Ok(()),
+        false => Err(Error::NotInstantiated),
+    }
+}
+
+/// returns the version of the contract that is stored in the reserved region.
+/// It returns None if the contract never stored its version.
+pub fn get_version(storage: &dyn ReadOnlyStorage) -> Result<Option<ContractVersion>, Error> {
+    match StorageItem::<VersionRecord, _>::from_slot(storage, &VERSION).may_load()? {
+        Some(record) => Ok(Some(record.decode()?)),
+        None => Ok(None),
+    }
+}
+
+/// stores the version of the contract in the reserved region.
+/// Contracts usually call it inside their instantiate function.
+/// It returns `OutOfCapacity` error if the encoded version is too long.
+pub fn set_version(storage: &dyn Storage, version: &ContractVersion) -> Result<(), Error> {
+    StorageItem::<VersionRecord>::from_slot(storage, &VERSION).save(&CborValue::encode(version)?)
+}
+
+/// runs the contract's instantiate function.
+/// It returns `AlreadyInstantiated` error if the contract has been instantiated before.
+/// The contract is marked as instantiated only if the instantiate function succeeds.
+pub fn instantiate<D, E: From<Error>>(
+    instantiate_fn: &dyn Fn(Context, D) -> Result<(), E>,
+    ctx: Context,
+    msg: D,
+) -> Result<(), E> {
+    let storage: &dyn Storage = ctx.api;
+    if is_instantiated(storage)? {
+        return Err(Error::AlreadyInstantiated.into());
+    }
+    instantiate_fn(ctx, msg)?;
+    StorageItem::<u32>::from_slot(storage, &INSTANTIATED).save(&INSTANTIATED_MAGIC)?;
+    Ok(())
+}
+
+/// runs the contract's process_msg function.
+/// It returns `NotInstantiated` error if the contract has not been instantiated.
+pub fn process_msg<D, E: From<Error>>(
+    process_msg_fn: &dyn Fn(Context, D) -> Result<(), E>,
+    ctx: Context,
+    msg: D,
+) -> Result<(), E> {
+    ensure_instantiated(ctx.api)?;
+    process_msg_fn(ctx, msg)
+}
+
+/// runs the contract's query function.
+/// It returns `NotInstantiated` error if the contract has not been instantiated.
+/// The query function receives a read-only context.
+pub fn query<D, R, E: From<Error>>(
+    query_fn: &dyn Fn(QueryContext, D) -> Result<R, E>,
+    ctx: QueryContext,
+    msg: D,
+) -> Result<R, E> {
+    ensure_instantiated(ctx.api)?;
+    query_fn(ctx, msg)
+}
+
+/// runs the contract's migrate function.
+/// The migrate function receives the old version of the contract and returns the new version,
+/// which is stored after a successful migration.
+/// It returns `NotInstantiated` error if the contract has not been instantiated.
+pub fn migrate<D, E: From<Error>>(
+    migrate_fn: &MigrateFn<'_, D, E>,
+    ctx: Context,
+    msg: D,
+) -> Result<(), E> {
+    let storage: &dyn Storage = ctx.api;
+    ensure_instantiated(storage)?;
+    let old_version = get_version(storage)?;
+    let new_version = migrate_fn(ctx, old_version, msg)?;
+    set_version(storage, &new_version)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_context, mock_migration};
+    use kelk_lib::collections::bst::tree::StorageBST;
+    use kelk_lib::collections::vector::vec::StorageVec;
+
+    fn instantiate_fn(ctx: Context, value: i32) -> Result<(), Error> {
+        if value < 0 {
+            return Err(Error::OutOfRange);
+        }
+        ctx.api.swrite_i32(DATA_OFFSET, value)?;
+        Ok(())
+    }
+
+    fn process_msg_fn(ctx: Context, value: i32) -> Result<(), Error> {
+        ctx.api.swrite_i32(DATA_OFFSET, value)?;
+        Ok(())
+    }
+
+    fn query_fn(ctx: QueryContext, _: ()) -> Result<i32, Error> {
+        Ok(ctx.api.sread_i32(DATA_OFFSET)?)
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let ctx = mock_context(256);
+        assert!(!is_instantiated(&ctx.api).unwrap());
+
+        instantiate(&instantiate_fn, ctx.as_ref(), 1).unwrap();
+        assert!(is_instantiated(&ctx.api).unwrap());
+        assert_eq!(query(&query_fn, ctx.as_query(), ()), Ok(1));
+
+        assert_eq!(
+            instantiate(&instantiate_fn, ctx.as_ref(), 2),
+            Err(Error::AlreadyInstantiated)
+        );
+        assert_eq!(query(&query_fn, ctx.as_query(), ()), Ok(1));
+    }
+
+    #[test]
+    fn test_failed_instantiate() {
+        let ctx = mock_context(256);
+        assert_eq!(
+            instantiate(&instantiate_fn, ctx.as_ref(), -1),
+            Err(Error::OutOfRange)
+        );
+        assert!(!is_instantiated(&ctx.api).unwrap());
+
+        instantiate(&instantiate_fn, ctx.as_ref(), 1).unwrap();
+        assert!(is_instantiated(&ctx.api).unwrap());
+    }
+
+    #[test]
+    fn test_not_instantiated() {
+        let ctx = mock_context(256);
+        assert_eq!(
+            process_msg(&process_msg_fn, ctx.as_ref(), 1),
+            Err(Error::NotInstantiated)
+        );
+        assert_eq!(
+            query(&query_fn, ctx.as_query(), ()),
+            Err(Error::NotInstantiated)
+        );
+
+        instantiate(&instantiate_fn, ctx.as_ref(), 0).unwrap();
+        process_msg(&process_msg_fn, ctx.as_ref(), 5).unwrap();
+        assert_eq!(query(&query_fn, ctx.as_query(), ()), Ok(5));
+    }
+
+    #[test]
+    fn test_reserved_region() {
+        let end = VERSION.offset + StorageItem::<VersionRecord>::SIZE;
+        assert!(end <= RESERVED_OFFSET + RESERVED_SIZE);
+    }
+
+    #[test]
+    fn test_version() {
+        let ctx = mock_context(256);
+        assert_eq!(get_version(&ctx.api), Ok(None));
+
+        let version = ContractVersion::new("token", 1, 2, 3);
+        set_version(&ctx.api, &version).unwrap();
+        assert_eq!(get_version(&ctx.api), Ok(Some(version.clone())));
+        assert_eq!(kelk_lib::alloc::format!("{}", version), "token v1.2.3");
+
+        let long_name = "x".repeat(VERSION_CAPACITY);
+        assert_eq!(
+            set_version(&ctx.api, &ContractVersion::new(&long_name, 1, 0, 0)),
+            Err(Error::OutOfCapacity)
+        );
+        assert_eq!(get_version(&ctx.api), Ok(Some(version)));
+    }
+
+    #[test]
+    fn test_migrate_not_instantiated() {
+        let ctx = mock_context(256);
+        let migrate_fn = |_: Context, _: Option<ContractVersion>, _: ()| {
+            Ok::<_, Error>(ContractVersion::new("test", 2, 0, 0))
+        };
+        assert_eq!(
+            migrate(&migrate_fn, ctx.as_ref(), ()),
+            Err(Error::NotInstantiated)
+        );
+    }
+
+    // The old code keeps the balances in a vector, indexed by the account number.
+    // The new code keeps them in a tree, so the accounts can be looked up by their numbers.
+    const OLD_BALANCES: u32 = DATA_OFFSET;
+    const NEW_BALANCES: u32 = DATA_OFFSET + 1024;
+
+    fn old_code(ctx: Context) -> Result<(), Error> {
+        let instantiate_fn = |ctx: Context, _: ()| -> Result<(), Error> {
+            set_version(ctx.api, &ContractVersion::new("bank", 1, 0, 0))?;
+            StorageVec::<u64>::create(ctx.api, OLD_BALANCES, 16)?;
+            Ok(())
+        };
+        let api = ctx.api;
+        instantiate(&instantiate_fn, ctx, ())?;
+
+        let mut balances = StorageVec::<u64>::lazy_load(api, OLD_BALANCES)?;
+        for balance in [10, 20, 30] {
+            balances.push(balance)?;
+        }
+        Ok(())
+    }
+
+    fn migrate_fn(
+        ctx: Context,
+        old_version: Option<ContractVersion>,
+        new_version: ContractVersion,
+    ) -> Result<ContractVersion, Error> {
+        match old_version {
+            Some(v) if v.name == new_version.name && v.major == 1 => {}
+            _ => return Err(Error::InvalidVersion),
+        }
+
+        let old = StorageVec::<u64>::lazy_load(ctx.api, OLD_BALANCES)?;
+        let mut new = StorageBST::<u32, u64>::create(ctx.api, NEW_BALANCES, 16)?;
+        for index in 0..old.len() {
+            new.insert(index, old.get(index)?.ok_or(Error::NotFound)?)?;
+        }
+        Ok(new_version)
+    }
+
+    fn new_code(ctx: Context) -> Result<(), Error> {
+        let balances = StorageBST::<u32, u64>::lazy_load(ctx.api, NEW_BALANCES)?;
+        match balances.find(&2)? {
+            Some(30) => Ok(()),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let new_version = ContractVersion::new("bank", 2, 0, 0);
+        let ctx =
+            mock_migration(4096, &old_code, &migrate_fn, new_version.clone(), &new_code).unwrap();
+        assert_eq!(get_version(&ctx.api), Ok(Some(new_version.clone())));
+
+        // Migrating again from version 2 is rejected by the migrate function
+        assert_eq!(
+            migrate(&migrate_fn, ctx.as_ref(), new_version),
+            Err(Error::InvalidVersion)
+        );
+    }
+
+    #[test]
+    fn test_failed_migrate() {
+        let new_version = ContractVersion::new("other", 2, 0, 0);
+        let res = mock_migration(4096, &old_code, &migrate_fn, new_version, &new_code);
+        assert!(matches!(res, Err(Error::InvalidVersion)));
+    }
+}
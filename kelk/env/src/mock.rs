@@ -1,17 +1,29 @@
 //! Mocking Context for testing contracts
 
 use crate::{
-    context::{ContextAPI, OwnedContext},
+    client,
+    context::{Context, OwnedContext, QueryContextAPI},
+    lifecycle::{self, MigrateFn},
     params::ParamType,
 };
+use core::cell::RefCell;
+use kelk_lib::alloc::string::String;
 use kelk_lib::alloc::vec::Vec;
+use kelk_lib::error::Error;
+use kelk_lib::hash::{self, Hash};
 use kelk_lib::mock::MockStorage;
-use kelk_lib::storage::Storage;
+use kelk_lib::random::Rng;
+use kelk_lib::storage::{ReadOnlyStorage, Storage};
 use kelk_lib::{self, error::HostError};
+use minicbor::{Decode, Encode};
+
+pub use kelk_lib::mock::Fault;
 
 /// `MockContextAPI` mocks the APIs for testing purpose.
 pub struct MockContextAPI {
     storage_mock: MockStorage,
+    debug_lines: RefCell<Vec<String>>,
+    rng: RefCell<Rng>,
 }
 
 impl MockContextAPI {
@@ -19,24 +31,64 @@ impl MockContextAPI {
     pub fn new(size: usize) -> Self {
         MockContextAPI {
             storage_mock: MockStorage::new(size),
+            debug_lines: RefCell::new(Vec::new()),
+            rng: RefCell::new(Rng::seed_from_u64(0)),
         }
     }
+
+    /// returns the mocked storage.
+    /// It can be used for taking snapshots or dumping the storage in tests.
+    pub fn storage(&self) -> &MockStorage {
+        &self.storage_mock
+    }
+
+    /// injects a fault into the mocked storage.
+    pub fn inject(&self, fault: Fault) {
+        self.storage_mock.inject(fault)
+    }
+
+    /// sets the seed of the random bytes, so the tests are reproducible.
+    /// The default seed is zero.
+    pub fn set_seed(&self, seed: u64) {
+        *self.rng.borrow_mut() = Rng::seed_from_u64(seed);
+    }
+
+    /// returns the debug messages sent by the contract, in order.
+    pub fn debug_lines(&self) -> Vec<String> {
+        self.debug_lines.borrow().clone()
+    }
 }
 
 impl Storage for MockContextAPI {
     fn swrite(&self, offset: u32, data: &[u8]) -> Result<(), HostError> {
         self.storage_mock.swrite(offset, data)
     }
+}
 
+impl ReadOnlyStorage for MockContextAPI {
     fn sread(&self, offset: u32, len: u32) -> Result<Vec<u8>, HostError> {
         self.storage_mock.sread(offset, len)
     }
 }
 
-impl ContextAPI for MockContextAPI {
+impl QueryContextAPI for MockContextAPI {
     fn get_param(&self, _param_id: i32) -> Option<ParamType> {
         unimplemented!()
     }
+
+    fn debug(&self, msg: &str) {
+        self.debug_lines.borrow_mut().push(msg.into());
+    }
+
+    fn random_bytes(&self, len: u32) -> Result<Vec<u8>, HostError> {
+        let mut vec = kelk_lib::alloc::vec![0; len as usize];
+        self.rng.borrow_mut().fill_bytes(&mut vec);
+        Ok(vec)
+    }
+
+    fn sha256(&self, data: &[u8]) -> Hash {
+        hash::sha256(data)
+    }
 }
 
 /// makes a mocked context
@@ -44,3 +96,78 @@ pub fn mock_context(storage_size: usize) -> OwnedContext<MockContextAPI> {
     let api = MockContextAPI::new(storage_size);
     OwnedContext { api }
 }
+
+/// runs the "old code, then migrate, then new code" sequence on a mocked context.
+/// The old code usually instantiates the contract and fills the storage with the old layout.
+/// Then the migrate function runs through the lifecycle, like `do_migrate` does,
+/// and the new code runs on the migrated storage.
+/// It returns the mocked context, so the storage can be checked after the sequence.
+pub fn mock_migration<D, E: From<Error>>(
+    storage_size: usize,
+    old_code: &dyn Fn(Context) -> Result<(), E>,
+    migrate_fn: &MigrateFn<'_, D, E>,
+    msg: D,
+    new_code: &dyn Fn(Context) -> Result<(), E>,
+) -> Result<OwnedContext<MockContextAPI>, E> {
+    let ctx = mock_context(storage_size);
+    old_code(ctx.as_ref())?;
+    lifecycle::migrate(migrate_fn, ctx.as_ref(), msg)?;
+    new_code(ctx.as_ref())?;
+    Ok(ctx)
+}
+
+/// executes the entry point function on a context.
+/// It only decodes the message and returns the encoded result,
+/// so it can be used for implementing a client `Transport` in tests.
+/// It doesn't run the lifecycle checks of the host, e.g. the instantiation check
+/// or the read-only context of the queries. Wrap the function in the `lifecycle`
+/// functions, like `lifecycle::process_msg`, to run them.
+pub fn mock_execute<'a, D: Decode<'a>, R: Encode, E: Encode>(
+    func: &dyn Fn(Context, D) -> Result<R, E>,
+    ctx: Context,
+    msg: &'a [u8],
+) -> Result<Vec<u8>, Error> {
+    let msg = client::decode_msg(msg)?;
+    client::encode_result(&func(ctx, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_seed() {
+        let ctx1 = mock_context(0);
+        let ctx2 = mock_context(0);
+        ctx1.api.set_seed(7);
+        ctx2.api.set_seed(7);
+        assert_eq!(
+            ctx1.api.random_bytes(20).unwrap(),
+            ctx2.api.random_bytes(20).unwrap()
+        );
+        assert_eq!(
+            ctx1.api.random_u64().unwrap(),
+            ctx2.api.random_u64().unwrap()
+        );
+        assert_eq!(
+            ctx1.api.rng().unwrap().next_u64(),
+            ctx2.api.rng().unwrap().next_u64()
+        );
+
+        ctx2.api.set_seed(8);
+        assert_ne!(
+            ctx1.api.random_u64().unwrap(),
+            ctx2.api.random_u64().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hasher() {
+        use kelk_lib::hash::Hasher;
+
+        let ctx = mock_context(0);
+        let ctx = ctx.as_ref();
+        assert_eq!(ctx.api.hash(b"abc"), hash::sha256(b"abc"));
+        assert_eq!(ctx.as_query().api.hash(b"abc"), hash::sha256(b"abc"));
+    }
+}
@@ -1,10 +1,11 @@
 //! Parameter types in Kelk
 
+use kelk_lib::U256;
 use minicbor::{Decode, Encode};
 
-///
+/// The address of the caller
 pub const PARAM_CALLER_ADDRESS: i32 = 0x0010;
-///
+/// The identifier of the caller
 pub const PARAM_CALLER_ID: i32 = 0x0011;
 
 /// Parameter value types
@@ -24,11 +25,29 @@ pub enum ParamType {
         #[n(0)]
         value: i64,
     },
-    // #[n(2)]
-    // I128 {
-    //     #[n(0)]
-    //     value: i128,
-    // },
+    /// A 128-bit integer.
+    #[n(2)]
+    I128 {
+        #[doc(hidden)]
+        #[n(0)]
+        #[cbor(with = "kelk_lib::cbor::i128")]
+        value: i128,
+    },
+    /// A 128-bit unsigned integer.
+    #[n(3)]
+    U128 {
+        #[doc(hidden)]
+        #[n(0)]
+        #[cbor(with = "kelk_lib::cbor::u128")]
+        value: u128,
+    },
+    /// A 256-bit unsigned integer.
+    #[n(4)]
+    U256 {
+        #[doc(hidden)]
+        #[n(0)]
+        value: U256,
+    },
     // #[n(10)]
     // Address {
     //     #[n(0)]
@@ -0,0 +1,81 @@
+//! Runtime setup for contracts.
+//!
+//! A `#![no_std]` contract needs a global allocator and a panic handler.
+//! The `setup!` macro defines both of them for the wasm32 target:
+//! the allocator is `wee_alloc` and the panic handler forwards the panic message
+//! to the host through the `abort` import, so the host can report why the contract trapped.
+//!
+//! Allocation failures are reported as panics by the default allocation error handler,
+//! therefore contracts don't need any nightly feature.
+//!
+//! The standard library is still available on the other targets,
+//! so the contract can be built and tested natively:
+//!
+//! ```ignore
+//! #![cfg_attr(target_arch = "wasm32", no_std)]
+//!
+//! kelk_env::setup!();
+//! ```
+
+#[cfg(target_arch = "wasm32")]
+pub use wee_alloc::WeeAlloc;
+
+/// The maximum length of the panic message that is sent to the host.
+/// Longer messages are truncated.
+#[cfg(target_arch = "wasm32")]
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// A fixed-size buffer for formatting the panic message.
+/// It doesn't allocate, so it works even if the panic is caused by running out of memory.
+#[cfg(target_arch = "wasm32")]
+struct MessageBuffer {
+    buf: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl core::fmt::Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > MAX_MESSAGE_LEN {
+                return Err(core::fmt::Error);
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+/// handles the panic by sending the panic message to the host and aborting the execution.
+#[cfg(target_arch = "wasm32")]
+pub fn handle_panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    let mut msg = MessageBuffer {
+        buf: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+    // The message is truncated if it doesn't fit in the buffer.
+    let _ = write!(msg, "{}", info);
+    crate::import::abort_with_message(&msg.buf[..msg.len])
+}
+
+/// Defines the global allocator and the panic handler of the contract.
+/// It should be called once, in the root of the contract crate.
+/// The definitions are only enabled for the wasm32 target, so the contract can be tested natively.
+#[macro_export]
+macro_rules! setup {
+    () => {
+        #[cfg(target_arch = "wasm32")]
+        #[global_allocator]
+        static ALLOC: $crate::setup::WeeAlloc = $crate::setup::WeeAlloc::INIT;
+
+        #[cfg(target_arch = "wasm32")]
+        #[panic_handler]
+        fn panic(info: &::core::panic::PanicInfo) -> ! {
+            $crate::setup::handle_panic(info)
+        }
+    };
+}
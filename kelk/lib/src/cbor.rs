@@ -0,0 +1,204 @@
+//! CBOR codecs for the integer types that are not supported by `minicbor` natively.
+//!
+//! Integers that fit in 64 bits are encoded as CBOR integers,
+//! otherwise they are encoded as CBOR bignums (tag 2 for positive and tag 3 for negative numbers).
+//! The `u128` and `i128` modules can be used with `#[cbor(with = "...")]` attribute.
+
+use minicbor::data::{Tag, Type};
+use minicbor::{decode, encode, Decoder, Encoder};
+
+/// encodes a big unsigned integer given in big-endian bytes.
+/// If `negative` is true, the encoded value is `-1 - value`.
+pub(crate) fn encode_bignum<W: encode::Write>(
+    e: &mut Encoder<W>,
+    negative: bool,
+    bytes: &[u8],
+) -> Result<(), encode::Error<W::Error>> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let bytes = &bytes[start..];
+
+    if bytes.len() <= 8 {
+        let mut buf = [0; 8];
+        buf[8 - bytes.len()..].copy_from_slice(bytes);
+        let value = core::primitive::u64::from_be_bytes(buf);
+        if !negative {
+            return e.u64(value)?.ok();
+        }
+        if value <= core::primitive::i64::MAX as core::primitive::u64 {
+            return e.i64(-1 - value as core::primitive::i64)?.ok();
+        }
+    }
+
+    match negative {
+        true => e.tag(Tag::NegBignum)?,
+        false => e.tag(Tag::PosBignum)?,
+    };
+    e.bytes(bytes)?.ok()
+}
+
+/// decodes a big integer into a sign and its big-endian magnitude.
+/// If the sign is negative, the decoded value is `-1 - magnitude`.
+pub(crate) fn decode_bignum<const N: usize>(
+    d: &mut Decoder<'_>,
+) -> Result<(bool, [u8; N]), decode::Error> {
+    let mut buf = [0; N];
+    match d.datatype()? {
+        Type::U8 | Type::U16 | Type::U32 | Type::U64 => {
+            buf[N - 8..].copy_from_slice(&d.u64()?.to_be_bytes());
+            Ok((false, buf))
+        }
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 => {
+            let value = d.i64()?;
+            match value < 0 {
+                true => buf[N - 8..].copy_from_slice(&((-1 - value) as u64).to_be_bytes()),
+                false => buf[N - 8..].copy_from_slice(&(value as u64).to_be_bytes()),
+            }
+            Ok((value < 0, buf))
+        }
+        Type::Tag => {
+            let negative = match d.tag()? {
+                Tag::PosBignum => false,
+                Tag::NegBignum => true,
+                _ => return Err(decode::Error::Message("expected bignum tag")),
+            };
+            let bytes = d.bytes()?;
+            let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+            let bytes = &bytes[start..];
+            if bytes.len() > N {
+                return Err(decode::Error::Message("bignum overflows target type"));
+            }
+            buf[N - bytes.len()..].copy_from_slice(bytes);
+            Ok((negative, buf))
+        }
+        t => Err(decode::Error::TypeMismatch(t, "expected integer or bignum")),
+    }
+}
+
+/// CBOR codec for `u128`
+pub mod u128 {
+    use minicbor::{Decoder, Encoder};
+
+    /// encodes `u128` value
+    pub fn encode<W: minicbor::encode::Write>(
+        v: &core::primitive::u128,
+        e: &mut Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        super::encode_bignum(e, false, &v.to_be_bytes())
+    }
+
+    /// decodes `u128` value
+    pub fn decode(d: &mut Decoder<'_>) -> Result<core::primitive::u128, minicbor::decode::Error> {
+        match super::decode_bignum::<16>(d)? {
+            (false, buf) => Ok(core::primitive::u128::from_be_bytes(buf)),
+            (true, _) => Err(minicbor::decode::Error::Message("negative value for u128")),
+        }
+    }
+}
+
+/// CBOR codec for `i128`
+pub mod i128 {
+    use minicbor::{Decoder, Encoder};
+
+    /// encodes `i128` value
+    pub fn encode<W: minicbor::encode::Write>(
+        v: &core::primitive::i128,
+        e: &mut Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match *v < 0 {
+            true => {
+                super::encode_bignum(e, true, &((-1 - *v) as core::primitive::u128).to_be_bytes())
+            }
+            false => super::encode_bignum(e, false, &(*v as core::primitive::u128).to_be_bytes()),
+        }
+    }
+
+    /// decodes `i128` value
+    pub fn decode(d: &mut Decoder<'_>) -> Result<core::primitive::i128, minicbor::decode::Error> {
+        let (negative, buf) = super::decode_bignum::<16>(d)?;
+        let magnitude = core::primitive::u128::from_be_bytes(buf);
+        if magnitude > core::primitive::i128::MAX as core::primitive::u128 {
+            return Err(minicbor::decode::Error::Message("bignum overflows i128"));
+        }
+        match negative {
+            true => Ok(-1 - magnitude as core::primitive::i128),
+            false => Ok(magnitude as core::primitive::i128),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use minicbor::{Decode, Encode};
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Test {
+        #[n(0)]
+        #[cbor(with = "super::u128")]
+        unsigned: u128,
+        #[n(1)]
+        #[cbor(with = "super::i128")]
+        signed: i128,
+    }
+
+    fn encode(v: &Test) -> Vec<u8> {
+        let mut buf = Vec::new();
+        minicbor::encode(v, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_small_values() {
+        let test = Test {
+            unsigned: 1,
+            signed: -1,
+        };
+        let buf = encode(&test);
+        assert_eq!(buf, [0x82, 0x01, 0x20]); // http://cbor.me/?bytes=820120
+        assert_eq!(minicbor::decode::<Test>(&buf).unwrap(), test);
+    }
+
+    #[test]
+    fn test_big_values() {
+        let test = Test {
+            unsigned: u64::MAX as u128 + 1,
+            signed: i64::MIN as i128 - 1,
+        };
+        let buf = encode(&test);
+        // http://cbor.me/?bytes=82c249010000000000000000c3488000000000000000
+        assert_eq!(
+            buf,
+            [
+                0x82, 0xc2, 0x49, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc3, 0x48,
+                0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ]
+        );
+        assert_eq!(minicbor::decode::<Test>(&buf).unwrap(), test);
+    }
+
+    #[test]
+    fn test_limits() {
+        for (unsigned, signed) in [
+            (0, 0),
+            (u128::MAX, i128::MAX),
+            (u128::MAX, i128::MIN),
+            (u64::MAX as u128, i64::MIN as i128),
+            (u64::MAX as u128, i64::MAX as i128 + 1),
+        ] {
+            let test = Test { unsigned, signed };
+            assert_eq!(minicbor::decode::<Test>(&encode(&test)).unwrap(), test);
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        // 17 bytes positive bignum
+        let mut buf = alloc::vec![0x82, 0xc2, 0x51, 0x01];
+        buf.extend_from_slice(&[0; 16]);
+        buf.push(0x00);
+        assert!(minicbor::decode::<Test>(&buf).is_err());
+
+        // negative value for u128
+        assert!(minicbor::decode::<Test>(&[0x82, 0x20, 0x00]).is_err());
+    }
+}
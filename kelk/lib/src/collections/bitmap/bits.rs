@@ -0,0 +1,319 @@
+//! Storage Bitmap, is a fixed-size set of bits that are packed into 64-bit storage words.
+//!
+//! Reading or changing a bit costs one word read, and one word write if the bit changes.
+//! `count_ones` and `find_first_zero` read the words in chunks, and `create` clears them
+//! in chunks, so they need one host call per `CHUNK_WORDS` words.
+
+use super::header::Header;
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec;
+use core::mem::size_of;
+use core::result::Result;
+
+/// The number of words that are read or cleared in one host call.
+const CHUNK_WORDS: u32 = 32;
+const WORD_BITS: u32 = 64;
+const WORD_LEN: u32 = size_of::<u64>() as u32;
+
+/// The instance of Storage Bitmap.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageBitmap<'a, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    header: Header,
+}
+
+impl<'a, S> StorageBitmap<'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Bitmap
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.word_len as u32 != WORD_LEN {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        Ok(StorageBitmap {
+            storage,
+            offset,
+            header,
+        })
+    }
+
+    /// Returns the number of bits in the bitmap.
+    pub fn len(&self) -> u32 {
+        self.header.len
+    }
+
+    /// Returns true if the bitmap has no bits.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value of the bit at the given index.
+    /// It returns `OutOfRange` error if the index is out of bounds.
+    pub fn get(&self, index: u32) -> Result<bool, Error> {
+        let (word, mask) = self.locate(index)?;
+        Ok(self.read_word(word)? & mask != 0)
+    }
+
+    /// Returns the number of the bits that are set.
+    pub fn count_ones(&self) -> Result<u32, Error> {
+        let mut count = 0;
+        self.scan(|_, word| {
+            count += word.count_ones();
+            false
+        })?;
+        Ok(count)
+    }
+
+    /// Returns the index of the first bit that is not set, or None if all the bits are set.
+    pub fn find_first_zero(&self) -> Result<Option<u32>, Error> {
+        let mut found = None;
+        let len = self.header.len;
+        self.scan(|index, word| {
+            if word != u64::MAX {
+                let bit = index * WORD_BITS + (!word).trailing_zeros();
+                // The unused bits of the last word are never set, so they are ignored here.
+                if bit < len {
+                    found = Some(bit);
+                }
+                return true;
+            }
+            false
+        })?;
+        Ok(found)
+    }
+
+    /// Returns the number of the words that keep the bits.
+    fn word_count(&self) -> u32 {
+        self.header.len.div_ceil(WORD_BITS)
+    }
+
+    fn word_offset(&self, word: u32) -> u32 {
+        self.offset + size_of::<Header>() as u32 + word * WORD_LEN
+    }
+
+    /// Returns the word index and the bit mask of the given bit.
+    fn locate(&self, index: u32) -> Result<(u32, u64), Error> {
+        if index >= self.header.len {
+            return Err(Error::OutOfRange);
+        }
+        Ok((index / WORD_BITS, 1 << (index % WORD_BITS)))
+    }
+
+    fn read_word(&self, word: u32) -> Result<u64, Error> {
+        Ok(self.storage.sread_u64(self.word_offset(word))?)
+    }
+
+    /// Reads the words in chunks and calls `f` with the index and the value of each word,
+    /// until `f` returns true.
+    fn scan(&self, mut f: impl FnMut(u32, u64) -> bool) -> Result<(), Error> {
+        let count = self.word_count();
+        let mut start = 0;
+        while start < count {
+            let words = (count - start).min(CHUNK_WORDS);
+            let data = self
+                .storage
+                .sread(self.word_offset(start), words * WORD_LEN)?;
+            for (i, bytes) in data.chunks_exact(WORD_LEN as usize).enumerate() {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(bytes);
+                if f(start + i as u32, u64::from_be_bytes(buf)) {
+                    return Ok(());
+                }
+            }
+            start += words;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S> StorageBitmap<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Bitmap at the given offset.
+    /// All the bits are cleared.
+    pub fn create(storage: &'a S, offset: u32, len: u32) -> Result<Self, Error> {
+        let header = Header::new(len);
+        let bitmap = StorageBitmap {
+            storage,
+            offset,
+            header,
+        };
+
+        // The words are cleared before writing the header,
+        // so a failed write never leaves a loadable bitmap with garbage bits.
+        let count = bitmap.word_count();
+        let zeros = vec![0; (count.min(CHUNK_WORDS) * WORD_LEN) as usize];
+        let mut start = 0;
+        while start < count {
+            let words = (count - start).min(CHUNK_WORDS);
+            storage.swrite(
+                bitmap.word_offset(start),
+                &zeros[..(words * WORD_LEN) as usize],
+            )?;
+            start += words;
+        }
+        swrite_struct(storage, offset, &bitmap.header)?;
+
+        Ok(bitmap)
+    }
+
+    /// Sets the bit at the given index.
+    /// It returns `OutOfRange` error if the index is out of bounds.
+    pub fn set(&mut self, index: u32) -> Result<(), Error> {
+        self.update(index, true)
+    }
+
+    /// Clears the bit at the given index.
+    /// It returns `OutOfRange` error if the index is out of bounds.
+    pub fn clear(&mut self, index: u32) -> Result<(), Error> {
+        self.update(index, false)
+    }
+
+    /// Sets the bit at the given index to `value`.
+    /// The storage is not written if the bit doesn't change.
+    pub fn update(&mut self, index: u32, value: bool) -> Result<(), Error> {
+        let (word, mask) = self.locate(index)?;
+        let old = self.read_word(word)?;
+        let new = match value {
+            true => old | mask,
+            false => old & !mask,
+        };
+        if new != old {
+            self.storage.swrite_u64(self.word_offset(word), new)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+
+    use super::*;
+    use core::mem::size_of;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(12, size_of::<Header>());
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageBitmap::create(&storage, 512, 100).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.boom, 0xb3000000);
+        assert_eq!(header.reserved, 0);
+        assert_eq!(header.word_len, 8);
+        assert_eq!(header.len, 100);
+    }
+
+    #[test]
+    fn test_bitmap() {
+        let storage = mock_storage(1024);
+        let mut bitmap = StorageBitmap::create(&storage, 0, 130).unwrap();
+        assert_eq!(bitmap.len(), 130);
+        assert_eq!(bitmap.count_ones().unwrap(), 0);
+        assert_eq!(bitmap.find_first_zero().unwrap(), Some(0));
+
+        bitmap.set(0).unwrap();
+        bitmap.set(63).unwrap();
+        bitmap.set(64).unwrap();
+        bitmap.set(129).unwrap();
+        assert!(bitmap.get(0).unwrap());
+        assert!(!bitmap.get(1).unwrap());
+        assert!(bitmap.get(63).unwrap());
+        assert!(bitmap.get(64).unwrap());
+        assert!(bitmap.get(129).unwrap());
+        assert_eq!(bitmap.count_ones().unwrap(), 4);
+        assert_eq!(bitmap.find_first_zero().unwrap(), Some(1));
+
+        bitmap.clear(63).unwrap();
+        bitmap.clear(62).unwrap();
+        assert!(!bitmap.get(63).unwrap());
+        assert_eq!(bitmap.count_ones().unwrap(), 3);
+
+        assert!(matches!(bitmap.get(130), Err(Error::OutOfRange)));
+        assert!(matches!(bitmap.set(130), Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn test_find_first_zero() {
+        let storage = mock_storage(4096);
+        // Longer than one chunk
+        let len = CHUNK_WORDS * WORD_BITS + 3;
+        let mut bitmap = StorageBitmap::create(&storage, 0, len).unwrap();
+        for i in 0..len - 1 {
+            bitmap.set(i).unwrap();
+        }
+        assert_eq!(bitmap.find_first_zero().unwrap(), Some(len - 1));
+        assert_eq!(bitmap.count_ones().unwrap(), len - 1);
+
+        bitmap.set(len - 1).unwrap();
+        assert_eq!(bitmap.find_first_zero().unwrap(), None);
+
+        bitmap.clear(100).unwrap();
+        assert_eq!(bitmap.find_first_zero().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let storage = mock_storage(1024);
+        let mut bitmap = StorageBitmap::create(&storage, 0, 64).unwrap();
+        let writes = storage.write_count();
+
+        bitmap.set(5).unwrap();
+        assert_eq!(storage.write_count(), writes + 1);
+
+        // The bit is set already
+        bitmap.set(5).unwrap();
+        assert_eq!(storage.write_count(), writes + 1);
+
+        // Two full chunks and one word are cleared, then the header is written
+        let storage = mock_storage(8 * 1024);
+        let writes = storage.write_count();
+        let len = 2 * CHUNK_WORDS * WORD_BITS + 1;
+        let bitmap = StorageBitmap::create(&storage, 0, len).unwrap();
+        assert_eq!(storage.write_count(), writes + 4);
+        assert_eq!(bitmap.count_ones().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(1024);
+        let mut bitmap = StorageBitmap::create(&storage, 512, 10).unwrap();
+        bitmap.set(7).unwrap();
+
+        let bitmap = StorageBitmap::lazy_load(&storage, 512).unwrap();
+        assert_eq!(bitmap.len(), 10);
+        assert!(bitmap.get(7).unwrap());
+        assert_eq!(bitmap.count_ones().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_failed_write() {
+        let storage = mock_storage(1024);
+        let mut bitmap = StorageBitmap::create(&storage, 0, 10).unwrap();
+
+        storage.inject(Fault::WriteRange {
+            range: 12..20,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert!(matches!(
+            bitmap.set(1),
+            Err(Error::HostError(HostErrorKind::OutOfGas))
+        ));
+        assert!(!bitmap.get(1).unwrap());
+    }
+}
@@ -0,0 +1,20 @@
+use core::mem::size_of;
+
+#[repr(C)]
+pub(super) struct Header {
+    pub boom: u32,
+    pub reserved: u16,
+    pub word_len: u16,
+    pub len: u32,
+}
+
+impl Header {
+    pub fn new(len: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            reserved: 0,
+            word_len: size_of::<u64>() as u16,
+            len,
+        }
+    }
+}
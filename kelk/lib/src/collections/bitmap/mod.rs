@@ -0,0 +1,7 @@
+//! Storage Bitmap
+
+pub mod bits;
+
+mod header;
+
+pub use bits::StorageBitmap;
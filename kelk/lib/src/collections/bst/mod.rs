@@ -1,9 +1,15 @@
 //! Storage Binary Search Tree
 
-pub mod error;
+/// The errors of the collections are unified into `kelk_lib::Error`.
+#[deprecated(note = "use `kelk_lib::Error` instead")]
+pub mod error {
+    pub use crate::error::Error;
+}
+pub mod set;
 pub mod tree;
 
 mod header;
 mod node;
 
+pub use set::StorageSet;
 pub use tree::StorageBST;
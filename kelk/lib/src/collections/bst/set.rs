@@ -0,0 +1,331 @@
+//! Storage Set, is an ordered set of keys that is built on top of the Storage Binary Search Tree.
+//! The keys are kept in the tree nodes with a zero-sized value.
+
+use super::tree::{self, StorageBST};
+use crate::error::Error;
+use crate::storage::{ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::result::Result;
+
+/// The instance of Storage Set.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageSet<'a, K, S = dyn Storage + 'a>
+where
+    K: Sized + Ord,
+    S: ReadOnlyStorage + ?Sized,
+{
+    tree: StorageBST<'a, K, (), S>,
+}
+
+impl<'a, K, S> StorageSet<'a, K, S>
+where
+    K: Sized + Ord,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Set
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        Ok(StorageSet {
+            tree: StorageBST::lazy_load(storage, offset)?,
+        })
+    }
+
+    /// Returns the number of keys in the set.
+    pub fn len(&self) -> u32 {
+        self.tree.len()
+    }
+
+    /// Returns true if the set contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns true if the set contains the key.
+    pub fn contains(&self, key: &K) -> Result<bool, Error> {
+        self.tree.contains_key(key)
+    }
+
+    /// Returns an iterator over the keys of the set, in ascending order.
+    pub fn iter(&self) -> Iter<'_, 'a, K, S> {
+        Iter {
+            inner: self.tree.iter(),
+        }
+    }
+
+    /// Returns true if all the keys of this set are in the other set.
+    pub fn is_subset<S2>(&self, other: &StorageSet<K, S2>) -> Result<bool, Error>
+    where
+        S2: ReadOnlyStorage + ?Sized,
+    {
+        if self.len() > other.len() {
+            return Ok(false);
+        }
+        let mut subset = true;
+        merge(self, other, |ord, _| {
+            if ord == Ordering::Less {
+                subset = false;
+            }
+            subset
+        })?;
+        Ok(subset)
+    }
+
+    /// Returns true if this set has no key in common with the other set.
+    pub fn is_disjoint<S2>(&self, other: &StorageSet<K, S2>) -> Result<bool, Error>
+    where
+        S2: ReadOnlyStorage + ?Sized,
+    {
+        let mut disjoint = true;
+        merge(self, other, |ord, _| {
+            if ord == Ordering::Equal {
+                disjoint = false;
+            }
+            disjoint
+        })?;
+        Ok(disjoint)
+    }
+
+    /// Returns the keys that are in both sets, in ascending order.
+    pub fn intersection<S2>(&self, other: &StorageSet<K, S2>) -> Result<Vec<K>, Error>
+    where
+        S2: ReadOnlyStorage + ?Sized,
+    {
+        let mut keys = Vec::new();
+        merge(self, other, |ord, key| {
+            if ord == Ordering::Equal {
+                keys.push(key);
+            }
+            true
+        })?;
+        Ok(keys)
+    }
+
+    /// Returns the keys that are in either set, in ascending order.
+    pub fn union<S2>(&self, other: &StorageSet<K, S2>) -> Result<Vec<K>, Error>
+    where
+        S2: ReadOnlyStorage + ?Sized,
+    {
+        let mut keys = Vec::new();
+        merge(self, other, |_, key| {
+            keys.push(key);
+            true
+        })?;
+        Ok(keys)
+    }
+}
+
+impl<'a, K, S> StorageSet<'a, K, S>
+where
+    K: Sized + Ord,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Set at the given offset
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        Ok(StorageSet {
+            tree: StorageBST::create(storage, offset, capacity)?,
+        })
+    }
+
+    /// Adds the key to the set.
+    /// It returns true if the set did not have the key.
+    pub fn insert(&mut self, key: K) -> Result<bool, Error> {
+        Ok(self.tree.insert(key, ())?.is_none())
+    }
+
+    /// Removes the key from the set.
+    /// It returns true if the set had the key.
+    pub fn remove(&mut self, key: &K) -> Result<bool, Error> {
+        Ok(self.tree.remove(key)?.is_some())
+    }
+}
+
+/// Walks through the keys of both sets in ascending order.
+/// `f` is called for each distinct key with the ordering of the sets that have it:
+/// `Less` if only the first set has the key, `Greater` if only the second one has it,
+/// and `Equal` if both have it. The walk stops when `f` returns false.
+fn merge<K, S1, S2>(
+    first: &StorageSet<K, S1>,
+    second: &StorageSet<K, S2>,
+    mut f: impl FnMut(Ordering, K) -> bool,
+) -> Result<(), Error>
+where
+    K: Sized + Ord,
+    S1: ReadOnlyStorage + ?Sized,
+    S2: ReadOnlyStorage + ?Sized,
+{
+    let mut iter1 = first.iter();
+    let mut iter2 = second.iter();
+    let mut key1 = iter1.next().transpose()?;
+    let mut key2 = iter2.next().transpose()?;
+    loop {
+        let (ord, key) = match (key1.take(), key2.take()) {
+            (None, None) => return Ok(()),
+            (Some(k1), None) => {
+                key1 = iter1.next().transpose()?;
+                (Ordering::Less, k1)
+            }
+            (None, Some(k2)) => {
+                key2 = iter2.next().transpose()?;
+                (Ordering::Greater, k2)
+            }
+            (Some(k1), Some(k2)) => match k1.cmp(&k2) {
+                Ordering::Less => {
+                    key1 = iter1.next().transpose()?;
+                    key2 = Some(k2);
+                    (Ordering::Less, k1)
+                }
+                Ordering::Greater => {
+                    key1 = Some(k1);
+                    key2 = iter2.next().transpose()?;
+                    (Ordering::Greater, k2)
+                }
+                Ordering::Equal => {
+                    key1 = iter1.next().transpose()?;
+                    key2 = iter2.next().transpose()?;
+                    (Ordering::Equal, k1)
+                }
+            },
+        };
+        if !f(ord, key) {
+            return Ok(());
+        }
+    }
+}
+
+/// An iterator over the keys of a Storage Set, in ascending order.
+pub struct Iter<'b, 'a, K, S>
+where
+    K: Sized + Ord,
+    S: ReadOnlyStorage + ?Sized,
+{
+    inner: tree::Iter<'b, 'a, K, (), S>,
+}
+
+impl<'b, 'a, K, S> Iterator for Iter<'b, 'a, K, S>
+where
+    K: Sized + Ord,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<K, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.inner.next()?.map(|(key, _)| key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::Header;
+    use super::super::node::Node;
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+    use crate::storage::sread_struct;
+    use core::mem::size_of;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(0, size_of::<()>());
+        assert_eq!(12, size_of::<Node<i32, ()>>());
+        assert_eq!(16, size_of::<Node<i64, ()>>());
+        assert_eq!(12, size_of::<Node<u8, ()>>());
+        assert_eq!(40, size_of::<Node<[u8; 32], ()>>());
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageSet::<[u8; 21]>::create(&storage, 512, 16).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.key_len, 21);
+        assert_eq!(header.value_len, 0);
+        assert_eq!(header.size, 0);
+        assert_eq!(header.capacity, 16);
+    }
+
+    #[test]
+    fn test_set() {
+        let storage = mock_storage(1024);
+        let mut set = StorageSet::<u8>::create(&storage, 0, 16).unwrap();
+        assert!(set.is_empty());
+        assert!(!set.contains(&1).unwrap());
+
+        assert!(set.insert(3).unwrap());
+        assert!(set.insert(1).unwrap());
+        assert!(set.insert(2).unwrap());
+        assert!(!set.insert(2).unwrap());
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&1).unwrap());
+        assert!(!set.contains(&4).unwrap());
+
+        let keys: Vec<u8> = set.iter().map(|key| key.unwrap()).collect();
+        assert_eq!(keys, [1, 2, 3]);
+
+        assert!(set.remove(&2).unwrap());
+        assert!(!set.remove(&2).unwrap());
+        assert_eq!(set.len(), 2);
+        assert!(!set.contains(&2).unwrap());
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(1024);
+        let mut set = StorageSet::<u32>::create(&storage, 512, 16).unwrap();
+        set.insert(7).unwrap();
+
+        let set = StorageSet::<u32>::lazy_load(&storage, 512).unwrap();
+        assert_eq!(set.len(), 1);
+        assert!(set.contains(&7).unwrap());
+        assert!(StorageSet::<u64>::lazy_load(&storage, 512).is_err());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let storage = mock_storage(1024);
+        let mut a = StorageSet::<i32>::create(&storage, 0, 16).unwrap();
+        let mut b = StorageSet::<i32>::create(&storage, 512, 16).unwrap();
+        for key in [1, 3, 5] {
+            a.insert(key).unwrap();
+        }
+        for key in [1, 2, 3, 4, 5] {
+            b.insert(key).unwrap();
+        }
+
+        assert!(a.is_subset(&b).unwrap());
+        assert!(!b.is_subset(&a).unwrap());
+        assert!(!a.is_disjoint(&b).unwrap());
+        assert_eq!(a.intersection(&b).unwrap(), [1, 3, 5]);
+        assert_eq!(a.union(&b).unwrap(), [1, 2, 3, 4, 5]);
+
+        b.remove(&1).unwrap();
+        b.remove(&3).unwrap();
+        b.remove(&5).unwrap();
+        b.insert(6).unwrap();
+        assert!(!a.is_subset(&b).unwrap());
+        assert!(a.is_disjoint(&b).unwrap());
+        assert!(a.intersection(&b).unwrap().is_empty());
+        assert_eq!(a.union(&b).unwrap(), [1, 2, 3, 4, 5, 6]);
+
+        let empty = StorageSet::<i32>::create(&storage, 900, 4).unwrap();
+        assert!(empty.is_subset(&a).unwrap());
+        assert!(empty.is_disjoint(&a).unwrap());
+        assert_eq!(empty.union(&a).unwrap(), [1, 3, 5]);
+    }
+
+    #[test]
+    fn test_failed_operation() {
+        let storage = mock_storage(1024);
+        let mut a = StorageSet::<i32>::create(&storage, 0, 16).unwrap();
+        let b = StorageSet::<i32>::create(&storage, 512, 16).unwrap();
+        a.insert(1).unwrap();
+
+        storage.inject(Fault::ReadRange {
+            range: 16..17,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert!(matches!(
+            a.is_disjoint(&b),
+            Err(Error::HostError(HostErrorKind::OutOfGas))
+        ));
+    }
+}
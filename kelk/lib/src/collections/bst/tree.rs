@@ -1,36 +1,49 @@
 //! Storage Binary Search Tree, is a binary search tree or BST that instead of using Random Access Memory,
 //! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
 
-use super::error::Error;
 use super::header::Header;
 use super::node::Node;
-use crate::storage::{sread_struct, swrite_struct, Storage};
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
 use core::marker::PhantomData;
 use core::mem::size_of;
 use core::result::Result;
 
-
-/// The instance of Storage Binary Search Tree
-pub struct StorageBST<'a, K, V>
+/// The instance of Storage Binary Search Tree.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageBST<'a, K, V, S = dyn Storage + 'a>
 where
     K: Sized + Ord,
     V: Sized,
+    S: ReadOnlyStorage + ?Sized,
 {
-    storage: &'a dyn Storage,
+    storage: &'a S,
     offset: u32,
     header: Header,
     _phantom: PhantomData<(K, V)>,
 }
 
-impl<'a, K, V> StorageBST<'a, K, V>
+impl<'a, K, V, S> StorageBST<'a, K, V, S>
 where
     K: Sized + Ord,
     V: Sized,
+    S: ReadOnlyStorage + ?Sized,
 {
-    /// creates and store a new instance of Storage Binary Search Tree at the given offset
-    pub fn create(storage: &'a dyn Storage, offset: u32, capacity: u32) -> Result<Self, Error> {
-        let header = Header::new::<K, V>(capacity);
-        swrite_struct(storage, offset, &header)?;
+    /// load the Storage Binary Search Tree
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        // TODO:
+        // Check boom and reserved field to be correct
+
+        if header.key_len != size_of::<K>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        if header.value_len != size_of::<V>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
 
         Ok(StorageBST {
             storage,
@@ -40,20 +53,108 @@ where
         })
     }
 
-    /// load the Storage Binary Search Tree
-    pub fn lazy_load(storage: &'a dyn Storage, offset: u32) -> Result<Self, Error> {
-        let header = sread_struct::<Header>(storage, offset)?;
+    /// Returns the number of elements in the tree.
+    pub fn len(&self) -> u32 {
+        self.header.size
+    }
 
-        // TODO:
-        // Check boom and reserved field to be correct
+    /// Returns true if the tree contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        if header.key_len != size_of::<K>() as u16 {
-            return Err(Error::InvalidOffset(offset));
+    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
+    pub fn find(&self, key: &K) -> Result<Option<V>, Error> {
+        if self.header.size == 0 {
+            return Ok(None);
         }
 
-        if header.value_len != size_of::<V>() as u16 {
-            return Err(Error::InvalidOffset(offset));
+        let mut offset = self.root_offset();
+        let mut node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+
+        loop {
+            if node.key.eq(key) {
+                return Ok(Some(node.value));
+            } else if node.key.le(key) {
+                if node.left.eq(&0) {
+                    return Ok(None);
+                }
+                offset = node.left;
+            } else {
+                if node.right.eq(&0) {
+                    return Ok(None);
+                }
+                offset = node.right;
+            }
+            node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        }
+    }
+
+    /// Returns true if the tree contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        Ok(self.find(key)?.is_some())
+    }
+
+    /// Returns an iterator over the key-value pairs of the tree, sorted by key.
+    /// Each node is read from the storage when the iterator reaches it.
+    pub fn iter(&self) -> Iter<'_, 'a, K, V, S> {
+        Iter {
+            tree: self,
+            stack: Vec::new(),
+            cursor: match self.header.size {
+                0 => 0,
+                _ => self.root_offset(),
+            },
         }
+    }
+
+    /// The root node is always kept right after the header.
+    fn root_offset(&self) -> u32 {
+        self.offset + size_of::<Header>() as u32
+    }
+
+    /// Returns the offset of the last node of a tree with the given size.
+    fn last_offset(&self, size: u32) -> u32 {
+        // The root is the first node and the next nodes are appended after
+        // the slot number one, therefore the nodes are kept at slots `0, 2, 3, ..., size`.
+        match size {
+            0 | 1 => self.root_offset(),
+            size => self.root_offset() + size * size_of::<Node<K, V>>() as u32,
+        }
+    }
+
+    /// Returns the offset of the parent of the node at `offset` and
+    /// true if the node is the left child of the parent.
+    /// The `key` is the key of the node and the node should not be the root.
+    fn find_parent(&self, key: &K, offset: u32) -> Result<(u32, bool), Error> {
+        let mut parent_offset = self.root_offset();
+        loop {
+            let parent = sread_struct::<Node<K, V>>(self.storage, parent_offset)?;
+            let (next, is_left) = match parent.key.le(key) {
+                true => (parent.left, true),
+                false => (parent.right, false),
+            };
+            if next == offset {
+                return Ok((parent_offset, is_left));
+            }
+            if next == 0 {
+                return Err(Error::NotFound);
+            }
+            parent_offset = next;
+        }
+    }
+}
+
+impl<'a, K, V, S> StorageBST<'a, K, V, S>
+where
+    K: Sized + Ord,
+    V: Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Binary Search Tree at the given offset
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        let header = Header::new::<K, V>(capacity);
+        swrite_struct(storage, offset, &header)?;
 
         Ok(StorageBST {
             storage,
@@ -70,14 +171,10 @@ where
         if self.header.size == 0 {
             // create a root node
             let root = Node::new(key, value);
-            self.header.size = 1;
-
             let root_offset = self.offset + size_of::<Header>() as u32;
-            swrite_struct(self.storage, self.offset, &self.header)?;
             swrite_struct(self.storage, root_offset, &root)?;
+            self.write_size(1)?;
             Ok(None)
-        } else if self.header.size >= self.header.capacity {
-            Err(Error::OutOfCapacity)
         } else {
             let mut offset = self.offset + size_of::<Header>() as u32;
             let mut node = sread_struct::<Node<K, V>>(self.storage, offset)?;
@@ -90,31 +187,15 @@ where
                     return Ok(Some(old_value));
                 } else if node.key.le(&key) {
                     if node.left.eq(&0) {
-                        self.header.size += 1;
-                        let new_offset = self.offset
-                            + size_of::<Header>() as u32
-                            + (self.header.size * size_of::<Node<K, V>>() as u32);
-
-                        swrite_struct(self.storage, self.offset, &self.header)?;
-                        node.left = new_offset;
+                        node.left = self.append_node(key, value)?;
                         swrite_struct(self.storage, offset, &node)?;
-                        let new_node = Node::new(key, value);
-                        swrite_struct(self.storage, new_offset, &new_node)?;
                         return Ok(None);
                     }
                     offset = node.left;
                 } else {
                     if node.right.eq(&0) {
-                        self.header.size += 1;
-                        let new_offset = self.offset
-                            + size_of::<Header>() as u32
-                            + (self.header.size * size_of::<Node<K, V>>() as u32);
-
-                        swrite_struct(self.storage, self.offset, &self.header)?;
-                        node.right = new_offset;
+                        node.right = self.append_node(key, value)?;
                         swrite_struct(self.storage, offset, &node)?;
-                        let new_node = Node::new(key, value);
-                        swrite_struct(self.storage, new_offset, &new_node)?;
                         return Ok(None);
                     }
                     offset = node.right;
@@ -124,45 +205,198 @@ where
         }
     }
 
-    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
-    pub fn find(&self, key: &K) -> Result<Option<V>, Error> {
+    /// Removes the key from the tree and returns its value.
+    /// If the key doesn't exist, it returns None.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
         if self.header.size == 0 {
             return Ok(None);
         }
 
-        let mut offset = self.offset + size_of::<Header>() as u32;
-            //    panic!("{} {}", offset, size_of::<Node<K, V>>());
+        // Finding the node and its parent
+        let mut parent: Option<(u32, bool)> = None;
+        let mut offset = self.root_offset();
         let mut node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        while !node.key.eq(key) {
+            let (next, is_left) = match node.key.le(key) {
+                true => (node.left, true),
+                false => (node.right, false),
+            };
+            if next == 0 {
+                return Ok(None);
+            }
+            parent = Some((offset, is_left));
+            offset = next;
+            node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        }
 
+        let (value, free_offset) = if node.left != 0 && node.right != 0 {
+            // The node has two children. It is replaced by the largest key
+            // of its right subtree, which has no left child.
+            let mut max_parent = offset;
+            let mut max_offset = node.right;
+            let mut max = sread_struct::<Node<K, V>>(self.storage, max_offset)?;
+            while max.left != 0 {
+                max_parent = max_offset;
+                max_offset = max.left;
+                max = sread_struct::<Node<K, V>>(self.storage, max_offset)?;
+            }
 
-
-        loop {
-            if node.key.eq(key) {
-                return Ok(Some(node.value));
-            } else if node.key.le(key) {
-                if node.left.eq(&0) {
-                    return Ok(None);
+            let right = match max_parent == offset {
+                true => max.right,
+                false => node.right,
+            };
+            let replaced = Node {
+                left: node.left,
+                right,
+                key: max.key,
+                value: max.value,
+            };
+            swrite_struct(self.storage, offset, &replaced)?;
+            if max_parent != offset {
+                self.set_link(max_parent, true, max.right)?;
+            }
+            (node.value, max_offset)
+        } else {
+            let child = match node.left {
+                0 => node.right,
+                left => left,
+            };
+            match parent {
+                Some((parent_offset, is_left)) => {
+                    self.set_link(parent_offset, is_left, child)?;
+                    (node.value, offset)
                 }
-                offset = node.left;
-            } else {
-                if node.left.eq(&0) {
-                    return Ok(None);
+                // The root should stay at its place, so its only child is moved to the root.
+                None if child != 0 => {
+                    let child_node = sread_struct::<Node<K, V>>(self.storage, child)?;
+                    swrite_struct(self.storage, offset, &child_node)?;
+                    (node.value, child)
                 }
-                offset = node.right;
+                None => (node.value, offset),
             }
-            node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        };
+
+        self.release_node(free_offset)?;
+        Ok(Some(value))
+    }
+
+    /// updates the left or right link of the node at the given offset.
+    fn set_link(&mut self, offset: u32, is_left: bool, link: u32) -> Result<(), Error> {
+        let mut node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        match is_left {
+            true => node.left = link,
+            false => node.right = link,
         }
+        swrite_struct(self.storage, offset, &node)?;
+        Ok(())
     }
 
-    /// Returns true if the tree contains a value for the specified key.
-    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
-        Ok(self.find(key)?.is_some())
+    /// releases the slot of a node that is unlinked from the tree.
+    /// The last node is moved into the released slot, so the nodes stay contiguous.
+    /// The moved node is written before its parent links to it,
+    /// and the header is written at the end, so a failed write never leaves a broken link.
+    ///
+    /// A failed insert or remove can leave an unlinked node in the last slots,
+    /// like the old copy of a moved node. Such a node has no parent and it is dropped.
+    fn release_node(&mut self, free_offset: u32) -> Result<(), Error> {
+        let mut size = self.header.size;
+        loop {
+            let last_offset = self.last_offset(size);
+            size -= 1;
+            if free_offset == last_offset {
+                break;
+            }
+
+            let last = sread_struct::<Node<K, V>>(self.storage, last_offset)?;
+            let (parent_offset, is_left) = match self.find_parent(&last.key, last_offset) {
+                Ok(parent) => parent,
+                Err(Error::NotFound) => continue,
+                Err(err) => return Err(err),
+            };
+            swrite_struct(self.storage, free_offset, &last)?;
+            self.set_link(parent_offset, is_left, free_offset)?;
+            break;
+        }
+        self.write_size(size)
+    }
+
+    /// writes a new node at the end of the tree and returns its offset.
+    /// The node and the header are written before the parent links to the node,
+    /// so a failed write never leaves a link to an unwritten node.
+    fn append_node(&mut self, key: K, value: V) -> Result<u32, Error> {
+        if self.header.size >= self.header.capacity {
+            return Err(Error::OutOfCapacity);
+        }
+
+        let size = self.header.size + 1;
+        let new_offset =
+            self.offset + size_of::<Header>() as u32 + (size * size_of::<Node<K, V>>() as u32);
+
+        let new_node = Node::new(key, value);
+        swrite_struct(self.storage, new_offset, &new_node)?;
+        self.write_size(size)?;
+        Ok(new_offset)
+    }
+
+    /// updates the size of the tree in the header.
+    /// The in-memory header is updated only if writing into the storage succeeds.
+    fn write_size(&mut self, size: u32) -> Result<(), Error> {
+        let old_size = self.header.size;
+        self.header.size = size;
+        if let Err(err) = swrite_struct(self.storage, self.offset, &self.header) {
+            self.header.size = old_size;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the key-value pairs of a Storage Binary Search Tree, sorted by key.
+pub struct Iter<'b, 'a, K, V, S>
+where
+    K: Sized + Ord,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    tree: &'b StorageBST<'a, K, V, S>,
+    stack: Vec<Node<K, V>>,
+    cursor: u32,
+}
+
+impl<'b, 'a, K, V, S> Iterator for Iter<'b, 'a, K, V, S>
+where
+    K: Sized + Ord,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The smaller keys are kept in the right subtree.
+        while self.cursor != 0 {
+            match sread_struct::<Node<K, V>>(self.tree.storage, self.cursor) {
+                Ok(node) => {
+                    self.cursor = node.right;
+                    self.stack.push(node);
+                }
+                Err(err) => {
+                    // Stops the iteration after an error
+                    self.cursor = 0;
+                    self.stack.clear();
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+        let node = self.stack.pop()?;
+        self.cursor = node.left;
+        Some(Ok((node.key, node.value)))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::mock::mock_storage;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
 
     use super::*;
     use core::mem::size_of;
@@ -224,6 +458,30 @@ mod tests {
         assert_eq!(Some(1), bst.find(&1).unwrap());
     }
 
+    #[test]
+    fn test_failed_insert() {
+        let storage = mock_storage(1024);
+        let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+        bst.insert(1, 1).unwrap();
+
+        // Writes: header(1), root(2), header(3). Failing the header of the second node.
+        storage.inject(Fault::NthWrite {
+            n: 5,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(matches!(
+            bst.insert(2, 2),
+            Err(Error::HostError(HostErrorKind::StorageFull))
+        ));
+        assert_eq!(None, bst.find(&2).unwrap());
+
+        let bst = StorageBST::<i32, i32>::lazy_load(&storage, 0).unwrap();
+        let header = sread_struct::<Header>(&storage, 0).unwrap();
+        assert_eq!(header.size, 1);
+        assert_eq!(Some(1), bst.find(&1).unwrap());
+        assert_eq!(None, bst.find(&2).unwrap());
+    }
+
     #[test]
     fn test_capacity() {
         let storage = mock_storage(1024);
@@ -234,5 +492,167 @@ mod tests {
         assert_eq!(None, bst.insert(3, 3).unwrap());
         assert_eq!(None, bst.insert(4, 4).unwrap());
         assert!(bst.insert(5, 5).is_err());
+        // Updating a key doesn't need a new node
+        assert_eq!(Some(4), bst.insert(4, 40).unwrap());
+    }
+
+    #[test]
+    fn test_find() {
+        let storage = mock_storage(1024);
+        let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+        bst.insert(5, 5).unwrap();
+        bst.insert(3, 3).unwrap();
+
+        // The root has no left child, and the smaller key is not in the tree.
+        assert_eq!(None, bst.find(&4).unwrap());
+        assert_eq!(None, bst.find(&1).unwrap());
+        assert_eq!(None, bst.find(&6).unwrap());
+        assert_eq!(Some(3), bst.find(&3).unwrap());
+    }
+
+    #[test]
+    fn test_iter() {
+        let storage = mock_storage(1024);
+        let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+        assert_eq!(bst.iter().count(), 0);
+
+        for key in [5, 2, 8, 1, 9, 3, 7, 4, 6] {
+            bst.insert(key, key * 10).unwrap();
+        }
+        let items: Vec<(i32, i32)> = bst.iter().map(|item| item.unwrap()).collect();
+        assert_eq!(
+            items,
+            [
+                (1, 10),
+                (2, 20),
+                (3, 30),
+                (4, 40),
+                (5, 50),
+                (6, 60),
+                (7, 70),
+                (8, 80),
+                (9, 90)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_iter() {
+        let storage = mock_storage(1024);
+        let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+        bst.insert(1, 1).unwrap();
+        bst.insert(2, 2).unwrap();
+
+        storage.inject(Fault::ReadRange {
+            range: 16..17,
+            kind: HostErrorKind::OutOfGas,
+        });
+        let mut iter = bst.iter();
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::HostError(HostErrorKind::OutOfGas)))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_remove() {
+        let storage = mock_storage(1024);
+        let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+        assert_eq!(None, bst.remove(&1).unwrap());
+
+        for key in [5, 2, 8, 1, 9, 3, 7, 4, 6] {
+            bst.insert(key, key * 10).unwrap();
+        }
+        assert_eq!(None, bst.remove(&10).unwrap());
+        // A node with two children
+        assert_eq!(Some(20), bst.remove(&2).unwrap());
+        // A leaf
+        assert_eq!(Some(60), bst.remove(&6).unwrap());
+        // The root
+        assert_eq!(Some(50), bst.remove(&5).unwrap());
+        assert_eq!(bst.len(), 6);
+        assert_eq!(None, bst.find(&2).unwrap());
+        assert_eq!(None, bst.find(&5).unwrap());
+
+        let keys: Vec<i32> = bst.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, [1, 3, 4, 7, 8, 9]);
+        for key in keys {
+            assert_eq!(Some(key * 10), bst.find(&key).unwrap());
+        }
+
+        for key in [1, 3, 4, 7, 8, 9] {
+            assert_eq!(Some(key * 10), bst.remove(&key).unwrap());
+        }
+        assert!(bst.is_empty());
+        assert_eq!(bst.iter().count(), 0);
+
+        // The released slots can be used again
+        for key in 0..16 {
+            bst.insert(key, key).unwrap();
+        }
+        assert!(bst.insert(16, 16).is_err());
+    }
+
+    #[test]
+    fn test_remove_random() {
+        use crate::random::Rng;
+
+        let storage = mock_storage(4096);
+        let mut bst = StorageBST::<u32, u32>::create(&storage, 0, 64).unwrap();
+        let mut keys = Vec::new();
+        let mut rng = Rng::seed_from_u64(1);
+        for _ in 0..500 {
+            let key = rng.below(100).unwrap() as u32;
+            if keys.contains(&key) {
+                assert_eq!(Some(key), bst.remove(&key).unwrap());
+                keys.retain(|k| *k != key);
+            } else if keys.len() < 64 {
+                assert_eq!(None, bst.insert(key, key).unwrap());
+                keys.push(key);
+            }
+            assert_eq!(bst.len() as usize, keys.len());
+        }
+
+        keys.sort();
+        let items: Vec<u32> = bst.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(items, keys);
+    }
+
+    #[test]
+    fn test_failed_remove() {
+        // Writes: parent, moved node, its parent, header.
+        for nth in 1..=4 {
+            let storage = mock_storage(1024);
+            let mut bst = StorageBST::<i32, i32>::create(&storage, 0, 16).unwrap();
+            for key in [5, 2, 8] {
+                bst.insert(key, key).unwrap();
+            }
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::OutOfGas,
+            });
+            assert!(matches!(
+                bst.remove(&2),
+                Err(Error::HostError(HostErrorKind::OutOfGas))
+            ));
+            assert_eq!(Some(5), bst.find(&5).unwrap());
+            assert_eq!(Some(8), bst.find(&8).unwrap());
+            storage.clear_faults();
+
+            // The next removes and inserts work on the loaded tree.
+            let mut bst = StorageBST::<i32, i32>::lazy_load(&storage, 0).unwrap();
+            bst.remove(&2).unwrap();
+            assert_eq!(Some(8), bst.remove(&8).unwrap());
+            bst.insert(1, 1).unwrap();
+            let keys: Vec<i32> = bst.iter().map(|item| item.unwrap().0).collect();
+            assert_eq!(keys, [1, 5]);
+
+            for key in [5, 1] {
+                assert_eq!(Some(key), bst.remove(&key).unwrap());
+            }
+            assert!(bst.is_empty());
+        }
     }
 }
@@ -0,0 +1,34 @@
+use core::mem::size_of;
+
+#[repr(C)]
+#[derive(Clone)]
+pub(super) struct Header {
+    pub boom: u32,
+    pub key_len: u16,
+    pub value_len: u16,
+    pub page_size: u32,
+    pub capacity: u32,
+    pub page_count: u32,
+    pub root: u32,
+    pub height: u32,
+    pub len: u32,
+    /// It is set while the pages are changed, and `len` is recounted if it is left set.
+    pub dirty: u32,
+}
+
+impl Header {
+    pub fn new<K: Sized, V: Sized>(page_size: u32, capacity: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            key_len: size_of::<K>() as u16,
+            value_len: size_of::<V>() as u16,
+            page_size,
+            capacity,
+            page_count: 0,
+            root: 0,
+            height: 0,
+            len: 0,
+            dirty: 0,
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Storage B+Tree
+
+pub mod tree;
+
+mod header;
+mod page;
+
+pub use tree::StorageBTree;
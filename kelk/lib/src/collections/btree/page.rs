@@ -0,0 +1,137 @@
+//! Encoding of the B+Tree pages.
+//!
+//! Each page starts with a `PageHeader`. A leaf page keeps the sorted key-value pairs
+//! and the number of the next leaf. A branch page keeps the first child,
+//! followed by the sorted keys, each one with the child that keeps the keys
+//! greater than or equal to it.
+//! Only the used part of a page is written, the rest of the page is ignored.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::slice;
+
+const LEAF: u16 = 1;
+const BRANCH: u16 = 2;
+
+#[repr(C)]
+struct PageHeader {
+    kind: u16,
+    count: u16,
+    /// The number of the next leaf page, or zero if it is the last leaf.
+    next: u32,
+}
+
+pub(super) struct Leaf<K, V> {
+    pub entries: Vec<(K, V)>,
+    pub next: u32,
+}
+
+pub(super) struct Branch<K> {
+    pub keys: Vec<K>,
+    pub children: Vec<u32>,
+}
+
+pub(super) enum Page<K, V> {
+    Leaf(Leaf<K, V>),
+    Branch(Branch<K>),
+}
+
+/// Returns the maximum number of the entries of a leaf page.
+pub(super) fn leaf_capacity<K, V>(page_size: u32) -> u32 {
+    let entry_len = (size_of::<K>() + size_of::<V>()).max(1) as u32;
+    page_size.saturating_sub(size_of::<PageHeader>() as u32) / entry_len
+}
+
+/// Returns the maximum number of the keys of a branch page.
+pub(super) fn branch_capacity<K>(page_size: u32) -> u32 {
+    let entry_len = (size_of::<K>() + size_of::<u32>()) as u32;
+    page_size.saturating_sub((size_of::<PageHeader>() + size_of::<u32>()) as u32) / entry_len
+}
+
+impl<K, V> Page<K, V> {
+    /// decodes a page from its bytes.
+    /// It returns None if the page is corrupted.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        let header: PageHeader = reader.read()?;
+        let count = header.count as usize;
+        match header.kind {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = reader.read()?;
+                    let value = reader.read()?;
+                    entries.push((key, value));
+                }
+                Some(Page::Leaf(Leaf {
+                    entries,
+                    next: header.next,
+                }))
+            }
+            BRANCH => {
+                let mut keys = Vec::with_capacity(count);
+                let mut children = Vec::with_capacity(count + 1);
+                children.push(reader.read()?);
+                for _ in 0..count {
+                    keys.push(reader.read()?);
+                    children.push(reader.read()?);
+                }
+                Some(Page::Branch(Branch { keys, children }))
+            }
+            _ => None,
+        }
+    }
+
+    /// encodes the used part of the page.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            Page::Leaf(leaf) => {
+                let header = PageHeader {
+                    kind: LEAF,
+                    count: leaf.entries.len() as u16,
+                    next: leaf.next,
+                };
+                write(&mut data, &header);
+                for (key, value) in &leaf.entries {
+                    write(&mut data, key);
+                    write(&mut data, value);
+                }
+            }
+            Page::Branch(branch) => {
+                let header = PageHeader {
+                    kind: BRANCH,
+                    count: branch.keys.len() as u16,
+                    next: 0,
+                };
+                write(&mut data, &header);
+                write(&mut data, &branch.children[0]);
+                for (key, child) in branch.keys.iter().zip(&branch.children[1..]) {
+                    write(&mut data, key);
+                    write(&mut data, child);
+                }
+            }
+        }
+        data
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read<T: Sized>(&mut self) -> Option<T> {
+        let end = self.pos + size_of::<T>();
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+}
+
+fn write<T: Sized>(data: &mut Vec<u8>, value: &T) {
+    let p: *const T = value;
+    let p: *const u8 = p as *const u8; // convert between pointer types
+    data.extend_from_slice(unsafe { slice::from_raw_parts(p, size_of::<T>()) });
+}
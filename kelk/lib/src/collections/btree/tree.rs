@@ -0,0 +1,817 @@
+//! Storage B+Tree, is a B+tree that keeps its nodes in pages of the contract's storage.
+//!
+//! Each page is read with one host call, so a lookup takes one read per level of the tree.
+//! With 4 KiB pages, a tree with millions of entries has only 2 or 3 levels.
+//! The leaves are linked together, so a range scan reads one page per leaf.
+//!
+//! Pages are allocated from a fixed number of pages that is set on creation.
+//! The pages are not merged when keys are removed, and the emptied slots
+//! are reused by the later inserts into the same leaf.
+//!
+//! The number of the keys is written with a dirty flag before the pages are changed,
+//! and the flag is cleared after them. If a change fails in between, the keys are
+//! counted again when the tree is loaded. Clearing the flag is the best effort,
+//! since the change is done by then.
+
+use super::header::Header;
+use super::page::{branch_capacity, leaf_capacity, Branch, Leaf, Page};
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{self, size_of};
+use core::ops::{Bound, RangeBounds};
+use core::result::Result;
+
+/// A leaf with its page number, and the branches that are visited to reach it
+/// with the index of the followed child.
+struct LeafPath<K, V> {
+    branches: Vec<(u32, Branch<K>, usize)>,
+    page: u32,
+    leaf: Leaf<K, V>,
+}
+
+/// The instance of Storage B+Tree.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageBTree<'a, K, V, S = dyn Storage + 'a>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    header: Header,
+    _phantom: PhantomData<(K, V)>,
+}
+
+/// Returns true if the pages of the given size can keep at least two entries,
+/// and the number of entries fits in the page header.
+fn is_valid_page_size<K, V>(page_size: u32) -> bool {
+    let leaf = leaf_capacity::<K, V>(page_size);
+    let branch = branch_capacity::<K>(page_size);
+    leaf >= 2 && branch >= 2 && leaf <= u16::MAX as u32 && branch <= u16::MAX as u32
+}
+
+impl<'a, K, V, S> StorageBTree<'a, K, V, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage B+Tree
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.key_len != size_of::<K>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        if header.value_len != size_of::<V>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        if !is_valid_page_size::<K, V>(header.page_size) {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        let mut tree = StorageBTree {
+            storage,
+            offset,
+            header,
+            _phantom: PhantomData,
+        };
+        if tree.header.dirty != 0 {
+            tree.header.len = tree.count()?;
+        }
+        Ok(tree)
+    }
+
+    /// Returns the number of elements in the tree.
+    pub fn len(&self) -> u32 {
+        self.header.len
+    }
+
+    /// Returns true if the tree contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of the levels of the tree, which is the number of reads for a lookup.
+    pub fn height(&self) -> u32 {
+        self.header.height
+    }
+
+    /// Returns the size of the pages in bytes.
+    pub fn page_size(&self) -> u32 {
+        self.header.page_size
+    }
+
+    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
+    pub fn find(&self, key: &K) -> Result<Option<V>, Error> {
+        if self.header.height == 0 {
+            return Ok(None);
+        }
+
+        let mut leaf = self.find_leaf(key)?.leaf;
+        match leaf.entries.binary_search_by(|(k, _)| k.cmp(key)) {
+            Ok(index) => Ok(Some(leaf.entries.swap_remove(index).1)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns true if the tree contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        Ok(self.find(key)?.is_some())
+    }
+
+    /// Returns an iterator over the key-value pairs of the tree, sorted by key.
+    pub fn iter(&self) -> Iter<'_, 'a, K, V, S> {
+        self.range(..)
+    }
+
+    /// Returns an iterator over the key-value pairs in the given range of keys, sorted by key.
+    /// The first leaf is found when the iterator is used for the first time.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, 'a, K, V, S> {
+        Iter {
+            tree: self,
+            start: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            entries: Vec::new().into_iter(),
+            next: 0,
+        }
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        self.offset + size_of::<Header>() as u32 + (page - 1) * self.header.page_size
+    }
+
+    fn read_page(&self, page: u32) -> Result<Page<K, V>, Error> {
+        let offset = self.page_offset(page);
+        let data = self.storage.sread(offset, self.header.page_size)?;
+        Page::decode(&data).ok_or(Error::InvalidOffset(offset))
+    }
+
+    fn read_leaf(&self, page: u32) -> Result<Leaf<K, V>, Error> {
+        match self.read_page(page)? {
+            Page::Leaf(leaf) => Ok(leaf),
+            Page::Branch(_) => Err(Error::InvalidOffset(self.page_offset(page))),
+        }
+    }
+
+    fn read_branch(&self, page: u32) -> Result<Branch<K>, Error> {
+        match self.read_page(page)? {
+            Page::Branch(branch) => Ok(branch),
+            Page::Leaf(_) => Err(Error::InvalidOffset(self.page_offset(page))),
+        }
+    }
+
+    /// Returns the leaf that should keep the key.
+    /// The tree should not be empty.
+    fn find_leaf(&self, key: &K) -> Result<LeafPath<K, V>, Error> {
+        let mut branches = Vec::with_capacity(self.header.height as usize);
+        let mut page = self.header.root;
+        for _ in 1..self.header.height {
+            let branch = self.read_branch(page)?;
+            let index = branch.keys.partition_point(|k| k <= key);
+            let child = branch.children[index];
+            branches.push((page, branch, index));
+            page = child;
+        }
+        Ok(LeafPath {
+            branches,
+            page,
+            leaf: self.read_leaf(page)?,
+        })
+    }
+
+    /// Counts the keys by reading all the leaves.
+    fn count(&self) -> Result<u32, Error> {
+        if self.header.height == 0 {
+            return Ok(0);
+        }
+        let mut leaf = self.start_leaf(&Bound::Unbounded)?;
+        let mut count = leaf.entries.len() as u32;
+        while leaf.next != 0 {
+            leaf = self.read_leaf(leaf.next)?;
+            count += leaf.entries.len() as u32;
+        }
+        Ok(count)
+    }
+
+    /// Returns the first leaf that may keep the keys after the given bound.
+    fn start_leaf(&self, start: &Bound<K>) -> Result<Leaf<K, V>, Error> {
+        match start {
+            Bound::Included(key) | Bound::Excluded(key) => Ok(self.find_leaf(key)?.leaf),
+            Bound::Unbounded => {
+                let mut page = self.header.root;
+                for _ in 1..self.header.height {
+                    page = self.read_branch(page)?.children[0];
+                }
+                self.read_leaf(page)
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S> StorageBTree<'a, K, V, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage B+Tree at the given offset.
+    /// The tree can allocate up to `capacity` pages of `page_size` bytes after the header.
+    /// It returns `OutOfRange` error if a page can't keep at least two entries.
+    pub fn create(
+        storage: &'a S,
+        offset: u32,
+        page_size: u32,
+        capacity: u32,
+    ) -> Result<Self, Error> {
+        if !is_valid_page_size::<K, V>(page_size) {
+            return Err(Error::OutOfRange);
+        }
+
+        let header = Header::new::<K, V>(page_size, capacity);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageBTree {
+            storage,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Inserts a key-value pair into the tree.
+    /// If the map did not have this key present, None is returned.
+    /// If the map did have this key present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+        let page_size = self.header.page_size;
+        let mut header = self.header.clone();
+
+        if header.height == 0 {
+            if header.capacity == 0 {
+                return Err(Error::OutOfCapacity);
+            }
+            let root = Leaf {
+                entries: vec![(key, value)],
+                next: 0,
+            };
+            self.write_page(1, &Page::Leaf(root))?;
+            header.page_count = 1;
+            header.root = 1;
+            header.height = 1;
+            header.len = 1;
+            return self.write_header(header).map(|_| None);
+        }
+
+        let LeafPath {
+            mut branches,
+            page: leaf_page,
+            mut leaf,
+        } = self.find_leaf(&key)?;
+        let index = match leaf.entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+            Ok(index) => {
+                let old_value = mem::replace(&mut leaf.entries[index].1, value);
+                self.write_page(leaf_page, &Page::Leaf(leaf))?;
+                return Ok(Some(old_value));
+            }
+            Err(index) => index,
+        };
+        leaf.entries.insert(index, (key, value));
+
+        // The new pages, and the changed pages from the leaf up to the root
+        let mut new_pages = Vec::new();
+        let mut changed_pages = Vec::new();
+
+        // The separator key and the page that should be linked into the parent
+        let mut split = None;
+        if leaf.entries.len() as u32 > leaf_capacity::<K, V>(page_size) {
+            let entries = leaf.entries.split_off(leaf.entries.len() / 2);
+            header.page_count += 1;
+            split = Some((entries[0].0, header.page_count));
+            let right = Leaf {
+                entries,
+                next: leaf.next,
+            };
+            leaf.next = header.page_count;
+            new_pages.push((header.page_count, Page::Leaf(right)));
+        }
+        changed_pages.push((leaf_page, Page::Leaf(leaf)));
+
+        while let Some((key, child)) = split.take() {
+            match branches.pop() {
+                Some((page, mut branch, index)) => {
+                    branch.keys.insert(index, key);
+                    branch.children.insert(index + 1, child);
+                    if branch.keys.len() as u32 > branch_capacity::<K>(page_size) {
+                        let mid = branch.keys.len() / 2;
+                        let keys = branch.keys.split_off(mid + 1);
+                        let children = branch.children.split_off(mid + 1);
+                        // The middle key moves up into the parent
+                        let middle = branch.keys.pop().ok_or(Error::OutOfRange)?;
+                        header.page_count += 1;
+                        split = Some((middle, header.page_count));
+                        new_pages
+                            .push((header.page_count, Page::Branch(Branch { keys, children })));
+                    }
+                    changed_pages.push((page, Page::Branch(branch)));
+                }
+                None => {
+                    // The root is split, so the tree grows by one level.
+                    header.page_count += 1;
+                    let root = Branch {
+                        keys: vec![key],
+                        children: vec![header.root, child],
+                    };
+                    new_pages.push((header.page_count, Page::Branch(root)));
+                    header.root = header.page_count;
+                    header.height += 1;
+                }
+            }
+        }
+
+        if header.page_count > header.capacity {
+            return Err(Error::OutOfCapacity);
+        }
+
+        // The new pages are written first, since nothing links to them yet.
+        // Then the header keeps the allocated pages, the new root, if any, and the new length.
+        // The changed pages are written from the root down to the leaf,
+        // so a failed write never leaves a link to a missing key.
+        for (page, data) in &new_pages {
+            self.write_page(*page, data)?;
+        }
+        header.len += 1;
+        header.dirty = 1;
+        self.write_header(header.clone())?;
+        for (page, data) in changed_pages.iter().rev() {
+            self.write_page(*page, data)
+                .or_else(|err| self.recount(err))?;
+        }
+        self.clear_dirty(header);
+
+        Ok(None)
+    }
+
+    /// Removes the key from the tree and returns its value.
+    /// If the key doesn't exist, it returns None.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
+        if self.header.height == 0 {
+            return Ok(None);
+        }
+
+        let LeafPath {
+            page: leaf_page,
+            mut leaf,
+            ..
+        } = self.find_leaf(key)?;
+        let index = match leaf.entries.binary_search_by(|(k, _)| k.cmp(key)) {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+        let (_, value) = leaf.entries.remove(index);
+
+        let mut header = self.header.clone();
+        header.len -= 1;
+        header.dirty = 1;
+        self.write_header(header.clone())?;
+        self.write_page(leaf_page, &Page::Leaf(leaf))
+            .or_else(|err| self.recount(err))?;
+        self.clear_dirty(header);
+        Ok(Some(value))
+    }
+
+    /// Clears the dirty flag after the pages are changed.
+    /// If it fails, the keys are only counted again on the next load.
+    fn clear_dirty(&mut self, mut header: Header) {
+        header.dirty = 0;
+        let _ = self.write_header(header);
+    }
+
+    /// Counts the keys again after a page is failed to be written, and returns the error.
+    /// The stored length is fixed by the next change or when the tree is loaded.
+    fn recount(&mut self, err: Error) -> Result<(), Error> {
+        if let Ok(count) = self.count() {
+            self.header.len = count;
+        }
+        Err(err)
+    }
+
+    fn write_page(&self, page: u32, data: &Page<K, V>) -> Result<(), Error> {
+        self.storage
+            .swrite(self.page_offset(page), &data.encode())?;
+        Ok(())
+    }
+
+    /// writes the header into the storage.
+    /// The in-memory header is updated only if writing into the storage succeeds.
+    fn write_header(&mut self, header: Header) -> Result<(), Error> {
+        swrite_struct(self.storage, self.offset, &header)?;
+        self.header = header;
+        Ok(())
+    }
+}
+
+/// An iterator over the key-value pairs of a Storage B+Tree, sorted by key.
+/// It reads one page for each leaf.
+pub struct Iter<'b, 'a, K, V, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    tree: &'b StorageBTree<'a, K, V, S>,
+    /// The start bound, until the first leaf is read
+    start: Option<Bound<K>>,
+    end: Bound<K>,
+    entries: vec::IntoIter<(K, V)>,
+    /// The page number of the next leaf, or zero if there is no more leaf
+    next: u32,
+}
+
+impl<'b, 'a, K, V, S> Iter<'b, 'a, K, V, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    fn load(&mut self, leaf: Result<Leaf<K, V>, Error>) -> Result<(), Error> {
+        match leaf {
+            Ok(leaf) => {
+                self.entries = leaf.entries.into_iter();
+                self.next = leaf.next;
+                Ok(())
+            }
+            Err(err) => {
+                // Stops the iteration after an error
+                self.entries = Vec::new().into_iter();
+                self.next = 0;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<'b, 'a, K, V, S> Iterator for Iter<'b, 'a, K, V, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            if self.tree.header.height == 0 {
+                return None;
+            }
+            if let Err(err) = self.load(self.tree.start_leaf(&start)) {
+                return Some(Err(err));
+            }
+            // Only the first leaf can keep the keys before the start bound.
+            let mut entries: Vec<(K, V)> = mem::take(&mut self.entries).collect();
+            entries.retain(|(key, _)| match &start {
+                Bound::Included(start) => key >= start,
+                Bound::Excluded(start) => key > start,
+                Bound::Unbounded => true,
+            });
+            self.entries = entries.into_iter();
+        }
+
+        loop {
+            if let Some((key, value)) = self.entries.next() {
+                let in_range = match &self.end {
+                    Bound::Included(end) => key <= *end,
+                    Bound::Excluded(end) => key < *end,
+                    Bound::Unbounded => true,
+                };
+                if !in_range {
+                    self.entries = Vec::new().into_iter();
+                    self.next = 0;
+                    return None;
+                }
+                return Some(Ok((key, value)));
+            }
+            if self.next == 0 {
+                return None;
+            }
+            if let Err(err) = self.load(self.tree.read_leaf(self.next)) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+    use crate::random::Rng;
+
+    use super::*;
+    use core::mem::size_of;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(36, size_of::<Header>());
+        assert_eq!(255, leaf_capacity::<u64, u64>(4096));
+        assert_eq!(340, branch_capacity::<u64>(4096));
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageBTree::<i32, i64>::create(&storage, 512, 128, 4).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.boom, 0xb3000000);
+        assert_eq!(header.key_len, 4);
+        assert_eq!(header.value_len, 8);
+        assert_eq!(header.page_size, 128);
+        assert_eq!(header.capacity, 4);
+        assert_eq!(header.page_count, 0);
+        assert_eq!(header.root, 0);
+        assert_eq!(header.height, 0);
+        assert_eq!(header.len, 0);
+        assert_eq!(header.dirty, 0);
+    }
+
+    #[test]
+    fn test_page_size() {
+        let storage = mock_storage(1024);
+        // The header of a page is 8 bytes, and each leaf entry is 16 bytes.
+        assert!(matches!(
+            StorageBTree::<u64, u64>::create(&storage, 0, 39, 4),
+            Err(Error::OutOfRange)
+        ));
+        assert!(StorageBTree::<u64, u64>::create(&storage, 0, 40, 4).is_ok());
+        assert!(matches!(
+            StorageBTree::<u8, ()>::create(&storage, 0, 0x10_0000, 4),
+            Err(Error::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_btree() {
+        let storage = mock_storage(1024);
+        let mut tree = StorageBTree::<i32, i32>::create(&storage, 0, 64, 8).unwrap();
+        assert_eq!(None, tree.find(&0).unwrap());
+        assert_eq!(None, tree.remove(&0).unwrap());
+        assert_eq!(tree.iter().count(), 0);
+
+        assert_eq!(None, tree.insert(3, 30).unwrap());
+        assert_eq!(None, tree.insert(2, 20).unwrap());
+        assert_eq!(None, tree.insert(1, 10).unwrap());
+        assert_eq!(Some(10), tree.insert(1, 100).unwrap());
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.height(), 1);
+
+        assert_eq!(Some(100), tree.find(&1).unwrap());
+        assert_eq!(Some(30), tree.find(&3).unwrap());
+        assert!(tree.contains_key(&2).unwrap());
+        assert!(!tree.contains_key(&4).unwrap());
+
+        assert_eq!(Some(20), tree.remove(&2).unwrap());
+        assert_eq!(None, tree.remove(&2).unwrap());
+        assert_eq!(tree.len(), 2);
+        let items: Vec<(i32, i32)> = tree.iter().map(|item| item.unwrap()).collect();
+        assert_eq!(items, [(1, 100), (3, 30)]);
+    }
+
+    #[test]
+    fn test_split() {
+        let storage = mock_storage(64 * 1024);
+        // 7 entries in each leaf and 6 keys in each branch
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 512).unwrap();
+        for key in 0..1000 {
+            assert_eq!(None, tree.insert(key, key * 2).unwrap());
+        }
+        assert_eq!(tree.len(), 1000);
+        assert!(tree.height() > 3);
+
+        for key in 0..1000 {
+            assert_eq!(Some(key * 2), tree.find(&key).unwrap());
+        }
+        assert_eq!(None, tree.find(&1000).unwrap());
+
+        let keys: Vec<u32> = tree.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, (0..1000).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_random() {
+        let storage = mock_storage(64 * 1024);
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 512).unwrap();
+        let mut keys = Vec::new();
+        let mut rng = Rng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let key = rng.below(500).unwrap() as u32;
+            if keys.contains(&key) && rng.below(2) == Some(0) {
+                assert_eq!(Some(key), tree.remove(&key).unwrap());
+                keys.retain(|k| *k != key);
+            } else if keys.contains(&key) {
+                assert_eq!(Some(key), tree.insert(key, key).unwrap());
+            } else {
+                assert_eq!(None, tree.insert(key, key).unwrap());
+                keys.push(key);
+            }
+            assert_eq!(tree.len() as usize, keys.len());
+        }
+
+        keys.sort();
+        let items: Vec<u32> = tree.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(items, keys);
+    }
+
+    #[test]
+    fn test_range() {
+        let storage = mock_storage(64 * 1024);
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 512).unwrap();
+        for key in (0..200).map(|k| k * 2) {
+            tree.insert(key, key).unwrap();
+        }
+        let range = |r: (Bound<u32>, Bound<u32>)| -> Vec<u32> {
+            tree.range(r).map(|item| item.unwrap().0).collect()
+        };
+
+        assert_eq!(
+            range((Bound::Included(10), Bound::Excluded(16))),
+            [10, 12, 14]
+        );
+        assert_eq!(
+            range((Bound::Excluded(10), Bound::Included(16))),
+            [12, 14, 16]
+        );
+        assert_eq!(range((Bound::Included(11), Bound::Included(15))), [12, 14]);
+        assert_eq!(range((Bound::Unbounded, Bound::Excluded(5))), [0, 2, 4]);
+        assert_eq!(
+            range((Bound::Included(394), Bound::Unbounded)),
+            [394, 396, 398]
+        );
+        assert!(range((Bound::Included(500), Bound::Unbounded)).is_empty());
+        assert_eq!(tree.range(100..300).count(), 100);
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let storage = mock_storage(1024 * 1024);
+        let mut tree = StorageBTree::<u64, u64>::create(&storage, 0, 4096, 200).unwrap();
+        for key in 0..10_000 {
+            tree.insert(key, key).unwrap();
+        }
+        assert_eq!(tree.height(), 2);
+
+        storage.reset_counters();
+        assert_eq!(Some(1234), tree.find(&1234).unwrap());
+        assert_eq!(storage.read_count(), 2);
+
+        // An update rewrites only the leaf
+        tree.insert(1234, 0).unwrap();
+        assert_eq!(storage.write_count(), 1);
+
+        // Scanning 1000 entries reads about 8 leaves
+        storage.reset_counters();
+        assert_eq!(tree.range(2000..3000).count(), 1000);
+        assert!(storage.read_count() <= 10);
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(4096);
+        let mut tree = StorageBTree::<i32, i32>::create(&storage, 512, 64, 32).unwrap();
+        for key in 0..20 {
+            tree.insert(key, key).unwrap();
+        }
+
+        let tree = StorageBTree::<i32, i32>::lazy_load(&storage, 512).unwrap();
+        assert_eq!(tree.len(), 20);
+        assert_eq!(tree.page_size(), 64);
+        assert_eq!(Some(7), tree.find(&7).unwrap());
+        assert!(StorageBTree::<i64, i32>::lazy_load(&storage, 512).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let storage = mock_storage(4096);
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 1).unwrap();
+        for key in 0..7 {
+            tree.insert(key, key).unwrap();
+        }
+        let writes = storage.write_count();
+        // Splitting the leaf needs two more pages.
+        assert!(matches!(tree.insert(7, 7), Err(Error::OutOfCapacity)));
+        assert_eq!(storage.write_count(), writes);
+        assert_eq!(Some(6), tree.insert(6, 6).unwrap());
+        assert_eq!(tree.len(), 7);
+    }
+
+    #[test]
+    fn test_failed_split() {
+        let storage = mock_storage(4096);
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 8).unwrap();
+        for key in 0..7 {
+            tree.insert(key, key).unwrap();
+        }
+
+        // Writes of a root split: right leaf, root, header, left leaf, header.
+        // Each failing write must leave a consistent tree with the right length.
+        for n in 1..=5 {
+            let snapshot = storage.snapshot();
+            storage.reset_counters();
+            storage.inject(Fault::CrashAfter {
+                writes: n - 1,
+                kind: HostErrorKind::OutOfGas,
+            });
+            let mut crashed = StorageBTree::<u32, u32>::lazy_load(&storage, 0).unwrap();
+            // The key is inserted before the last write, which clears the dirty flag.
+            assert_eq!(crashed.insert(7, 7).is_ok(), n == 5);
+
+            storage.clear_faults();
+            let mut loaded = StorageBTree::<u32, u32>::lazy_load(&storage, 0).unwrap();
+            let keys: Vec<u32> = loaded.iter().map(|item| item.unwrap().0).collect();
+            // Only the last write of the header fails after the leaf is linked.
+            if n == 5 {
+                assert_eq!(keys, [0, 1, 2, 3, 4, 5, 6, 7]);
+            } else {
+                assert_eq!(keys, [0, 1, 2, 3, 4, 5, 6]);
+            }
+            assert_eq!(loaded.len(), keys.len() as u32);
+
+            // The length is right after the next change
+            assert_eq!(None, loaded.insert(8, 8).unwrap());
+            assert_eq!(loaded.len(), keys.len() as u32 + 1);
+            let loaded = StorageBTree::<u32, u32>::lazy_load(&storage, 0).unwrap();
+            assert_eq!(loaded.len(), keys.len() as u32 + 1);
+            storage.restore(&snapshot);
+        }
+
+        tree.insert(7, 7).unwrap();
+        assert_eq!(tree.height(), 2);
+        assert_eq!(tree.len(), 8);
+    }
+
+    #[test]
+    fn test_failed_len() {
+        // Failing each write of an insert and a remove, with the tree that has seen the failure
+        // and the tree that is loaded after it
+        for nth in 1..=3 {
+            for remove in [false, true] {
+                let storage = mock_storage(4096);
+                let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 8).unwrap();
+                for key in 0..3 {
+                    tree.insert(key, key).unwrap();
+                }
+
+                storage.inject(Fault::NthWrite {
+                    n: storage.write_count() + nth,
+                    kind: HostErrorKind::StorageFull,
+                });
+                let result = match remove {
+                    true => tree.remove(&1).map(|_| ()),
+                    false => tree.insert(3, 3).map(|_| ()),
+                };
+                assert_eq!(result.is_ok(), nth == 3);
+                storage.clear_faults();
+
+                // The tree that has seen the failure, and the tree that is loaded after it
+                let snapshot = storage.snapshot();
+                let loaded = StorageBTree::<u32, u32>::lazy_load(&snapshot, 0).unwrap();
+                for mut tree in [tree, loaded] {
+                    assert_eq!(tree.len() as usize, tree.iter().count());
+                    tree.insert(3, 3).unwrap();
+                    tree.remove(&1).unwrap();
+                    assert_eq!(tree.len(), 3);
+                    let keys: Vec<u32> = tree.iter().map(|item| item.unwrap().0).collect();
+                    assert_eq!(keys, [0, 2, 3]);
+                }
+                let loaded = StorageBTree::<u32, u32>::lazy_load(&storage, 0).unwrap();
+                assert_eq!(loaded.len(), 3);
+                let loaded = StorageBTree::<u32, u32>::lazy_load(&snapshot, 0).unwrap();
+                assert_eq!(loaded.len(), 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_failed_read() {
+        let storage = mock_storage(4096);
+        let mut tree = StorageBTree::<u32, u32>::create(&storage, 0, 64, 8).unwrap();
+        for key in 0..20 {
+            tree.insert(key, key).unwrap();
+        }
+
+        storage.inject(Fault::ReadRange {
+            range: 0..4096,
+            kind: HostErrorKind::OutOfGas,
+        });
+        let mut iter = tree.iter();
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::HostError(HostErrorKind::OutOfGas)))
+        ));
+        assert!(iter.next().is_none());
+    }
+}
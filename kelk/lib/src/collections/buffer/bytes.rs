@@ -0,0 +1,267 @@
+//! Storage Bytes, is a byte buffer that instead of using Random Access Memory,
+//! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
+
+use super::header::Header;
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::result::Result;
+
+/// The instance of Storage Bytes.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageBytes<'a, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    header: Header,
+}
+
+impl<'a, S> StorageBytes<'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Bytes
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.boom != 0xb3000000 || header.size > header.capacity {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        Ok(StorageBytes {
+            storage,
+            offset,
+            header,
+        })
+    }
+
+    /// Returns the number of bytes in the buffer.
+    pub fn len(&self) -> u32 {
+        self.header.size
+    }
+
+    /// Returns true if the buffer contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of bytes that can be stored in the buffer.
+    pub fn capacity(&self) -> u32 {
+        self.header.capacity
+    }
+
+    /// Returns the number of bytes that can be appended before the buffer is full.
+    pub fn remaining(&self) -> u32 {
+        self.header.capacity - self.header.size
+    }
+
+    /// Returns all the bytes in the buffer.
+    pub fn get(&self) -> Result<Vec<u8>, Error> {
+        self.read_range(0, self.header.size)
+    }
+
+    /// Returns `len` bytes of the buffer starting from `start`.
+    /// It returns `OutOfRange` error if the range is not inside the buffer.
+    pub fn read_range(&self, start: u32, len: u32) -> Result<Vec<u8>, Error> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.header.size => {}
+            _ => return Err(Error::OutOfRange),
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        Ok(self.storage.sread(self.data_offset() + start, len)?)
+    }
+
+    fn data_offset(&self) -> u32 {
+        self.offset + size_of::<Header>() as u32
+    }
+}
+
+impl<'a, S> StorageBytes<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Bytes at the given offset.
+    /// The capacity is the maximum number of bytes that can be stored.
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        let header = Header::new(capacity);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageBytes {
+            storage,
+            offset,
+            header,
+        })
+    }
+
+    /// Replaces the content of the buffer with the given data.
+    pub fn set(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_at(0, data)
+    }
+
+    /// Appends the given data to the end of the buffer.
+    pub fn append(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_at(self.header.size, data)
+    }
+
+    /// Shortens the buffer to the given length.
+    /// It has no effect if `len` is greater than the buffer's current length.
+    pub fn truncate(&mut self, len: u32) -> Result<(), Error> {
+        if len >= self.header.size {
+            return Ok(());
+        }
+        self.write_size(len)
+    }
+
+    /// Clears the buffer, removing all the bytes.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.truncate(0)
+    }
+
+    /// writes the data at the given position and updates the size.
+    /// The data is written before the header, so a failed write never leaves
+    /// the size covering unwritten bytes.
+    fn write_at(&mut self, pos: u32, data: &[u8]) -> Result<(), Error> {
+        let size = match u32::try_from(data.len())
+            .ok()
+            .and_then(|len| pos.checked_add(len))
+        {
+            Some(size) if size <= self.header.capacity => size,
+            _ => return Err(Error::OutOfCapacity),
+        };
+
+        if !data.is_empty() {
+            self.storage.swrite(self.data_offset() + pos, data)?;
+        }
+        self.write_size(size)
+    }
+
+    /// updates the size of the buffer in the header.
+    /// The in-memory header is updated only if writing into the storage succeeds.
+    fn write_size(&mut self, size: u32) -> Result<(), Error> {
+        let old_size = self.header.size;
+        self.header.size = size;
+        if let Err(err) = swrite_struct(self.storage, self.offset, &self.header) {
+            self.header.size = old_size;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+
+    #[test]
+    fn test_size() {
+        assert_eq!(16, size_of::<Header>());
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageBytes::create(&storage, 512, 64).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.boom, 0xb3000000);
+        assert_eq!(header.reserved, 0);
+        assert_eq!(header.size, 0);
+        assert_eq!(header.capacity, 64);
+    }
+
+    #[test]
+    fn test_bytes() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 512, 16).unwrap();
+        assert!(bytes.is_empty());
+        assert!(bytes.get().unwrap().is_empty());
+
+        bytes.set(b"foo").unwrap();
+        assert_eq!(bytes.get().unwrap(), b"foo");
+        bytes.append(b"bar").unwrap();
+        assert_eq!(bytes.get().unwrap(), b"foobar");
+        assert_eq!(bytes.len(), 6);
+        assert_eq!(bytes.remaining(), 10);
+
+        bytes.set(b"zoo").unwrap();
+        assert_eq!(bytes.get().unwrap(), b"zoo");
+    }
+
+    #[test]
+    fn test_read_range() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 0, 16).unwrap();
+        bytes.set(b"kelk-lib").unwrap();
+
+        assert_eq!(bytes.read_range(0, 4).unwrap(), b"kelk");
+        assert_eq!(bytes.read_range(5, 3).unwrap(), b"lib");
+        assert!(bytes.read_range(8, 0).unwrap().is_empty());
+        assert_eq!(bytes.read_range(5, 4), Err(Error::OutOfRange));
+        assert_eq!(bytes.read_range(u32::MAX, 2), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 0, 16).unwrap();
+        bytes.set(b"kelk-lib").unwrap();
+
+        bytes.truncate(16).unwrap();
+        assert_eq!(bytes.len(), 8);
+        bytes.truncate(4).unwrap();
+        assert_eq!(bytes.get().unwrap(), b"kelk");
+        bytes.clear().unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 512, 16).unwrap();
+        bytes.set(b"kelk").unwrap();
+
+        let bytes = StorageBytes::lazy_load(&storage, 512).unwrap();
+        assert_eq!(bytes.capacity(), 16);
+        assert_eq!(bytes.get().unwrap(), b"kelk");
+
+        assert!(StorageBytes::lazy_load(&storage, 0).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 0, 4).unwrap();
+
+        bytes.set(b"kelk").unwrap();
+        assert_eq!(bytes.append(b"!"), Err(Error::OutOfCapacity));
+        assert_eq!(bytes.set(b"kelk!"), Err(Error::OutOfCapacity));
+        assert_eq!(bytes.get().unwrap(), b"kelk");
+    }
+
+    #[test]
+    fn test_failed_append() {
+        let storage = mock_storage(1024);
+        let mut bytes = StorageBytes::create(&storage, 0, 16).unwrap();
+        bytes.set(b"foo").unwrap();
+
+        storage.inject(Fault::WriteRange {
+            range: 0..16,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert_eq!(
+            bytes.append(b"bar"),
+            Err(Error::HostError(HostErrorKind::StorageFull))
+        );
+        assert_eq!(bytes.get().unwrap(), b"foo");
+
+        let bytes = StorageBytes::lazy_load(&storage, 0).unwrap();
+        assert_eq!(bytes.get().unwrap(), b"foo");
+    }
+}
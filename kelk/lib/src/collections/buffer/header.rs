@@ -0,0 +1,18 @@
+#[repr(C)]
+pub(super) struct Header {
+    pub boom: u32,
+    pub reserved: u32,
+    pub size: u32,
+    pub capacity: u32,
+}
+
+impl Header {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            reserved: 0,
+            size: 0,
+            capacity,
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Storage Bytes and Storage String
+
+pub mod bytes;
+pub mod string;
+
+mod header;
+
+pub use bytes::StorageBytes;
+pub use string::StorageString;
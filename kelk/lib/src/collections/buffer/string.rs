@@ -0,0 +1,133 @@
+//! Storage String, is a UTF-8 string that instead of using Random Access Memory,
+//! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
+
+use super::bytes::StorageBytes;
+use crate::error::Error;
+use crate::storage::{ReadOnlyStorage, Storage};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::result::Result;
+
+/// The instance of Storage String.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageString<'a, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    bytes: StorageBytes<'a, S>,
+}
+
+impl<'a, S> StorageString<'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage String
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        Ok(StorageString {
+            bytes: StorageBytes::lazy_load(storage, offset)?,
+        })
+    }
+
+    /// Returns the length of the string in bytes.
+    pub fn len(&self) -> u32 {
+        self.bytes.len()
+    }
+
+    /// Returns true if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns the maximum length of the string in bytes.
+    pub fn capacity(&self) -> u32 {
+        self.bytes.capacity()
+    }
+
+    /// Returns the string.
+    pub fn get(&self) -> Result<String, Error> {
+        to_string(self.bytes.get()?)
+    }
+
+    /// Returns `len` bytes of the string starting from `start`.
+    /// It returns `InvalidUtf8` error if the range is not on the char boundaries.
+    pub fn read_range(&self, start: u32, len: u32) -> Result<String, Error> {
+        to_string(self.bytes.read_range(start, len)?)
+    }
+}
+
+impl<'a, S> StorageString<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage String at the given offset.
+    /// The capacity is the maximum length of the string in bytes.
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        Ok(StorageString {
+            bytes: StorageBytes::create(storage, offset, capacity)?,
+        })
+    }
+
+    /// Replaces the content of the string.
+    pub fn set(&mut self, value: &str) -> Result<(), Error> {
+        self.bytes.set(value.as_bytes())
+    }
+
+    /// Appends the given string to the end of this string.
+    pub fn append(&mut self, value: &str) -> Result<(), Error> {
+        self.bytes.append(value.as_bytes())
+    }
+
+    /// Clears the string, removing all the contents.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.bytes.clear()
+    }
+}
+
+fn to_string(data: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(data).map_err(|_| Error::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_storage;
+
+    #[test]
+    fn test_string() {
+        let storage = mock_storage(1024);
+        let mut string = StorageString::create(&storage, 512, 32).unwrap();
+        assert!(string.is_empty());
+        assert_eq!(string.get().unwrap(), "");
+
+        string.set("Zarb").unwrap();
+        string.append(" token").unwrap();
+        assert_eq!(string.get().unwrap(), "Zarb token");
+        assert_eq!(string.read_range(5, 5).unwrap(), "token");
+
+        let string = StorageString::lazy_load(&storage, 512).unwrap();
+        assert_eq!(string.len(), 10);
+        assert_eq!(string.get().unwrap(), "Zarb token");
+    }
+
+    #[test]
+    fn test_char_boundary() {
+        let storage = mock_storage(1024);
+        let mut string = StorageString::create(&storage, 0, 32).unwrap();
+        string.set("زرب").unwrap();
+
+        assert_eq!(string.len(), 6);
+        assert_eq!(string.read_range(0, 2).unwrap(), "ز");
+        assert_eq!(string.read_range(0, 3), Err(Error::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let storage = mock_storage(1024);
+        let mut string = StorageString::create(&storage, 0, 4).unwrap();
+
+        string.set("Zarb").unwrap();
+        assert_eq!(string.append("!"), Err(Error::OutOfCapacity));
+        string.clear().unwrap();
+        assert!(string.is_empty());
+    }
+}
@@ -0,0 +1,7 @@
+//! Storage Deque
+
+pub mod queue;
+
+mod header;
+
+pub use queue::StorageDeque;
@@ -0,0 +1,421 @@
+//! Storage Deque, is a double-ended queue that instead of using Random Access Memory,
+//! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
+//!
+//! The elements are kept in a ring buffer with a fixed capacity.
+//! Pushing and popping at both ends costs one value and one header access.
+//!
+//! The `head` and `tail` indices in the header are kept modulo `2 * capacity`,
+//! so a full deque (`tail - head == capacity`) can be told apart from an empty one (`tail == head`).
+
+use super::header::Header;
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::result::Result;
+
+/// The instance of Storage Deque.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageDeque<'a, V, S = dyn Storage + 'a>
+where
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    header: Header,
+    _phantom: PhantomData<V>,
+}
+
+impl<'a, V, S> StorageDeque<'a, V, S>
+where
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Deque
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.value_len != size_of::<V>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        Ok(StorageDeque {
+            storage,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns the number of elements in the deque.
+    pub fn len(&self) -> u32 {
+        self.distance(self.header.head, self.header.tail)
+    }
+
+    /// Returns true if the deque contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.header.head == self.header.tail
+    }
+
+    /// Returns the maximum number of elements that the deque can hold.
+    pub fn capacity(&self) -> u32 {
+        self.header.capacity
+    }
+
+    /// Returns the element at the given index, counting from the front, or None if out of bounds.
+    pub fn get(&self, index: u32) -> Result<Option<V>, Error> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+
+        let pos = self.advance(self.header.head, index);
+        let val: V = sread_struct(self.storage, self.slot_offset(pos))?;
+        Ok(Some(val))
+    }
+
+    /// Returns the first element, or None if the deque is empty.
+    pub fn front(&self) -> Result<Option<V>, Error> {
+        self.get(0)
+    }
+
+    /// Returns the last element, or None if the deque is empty.
+    pub fn back(&self) -> Result<Option<V>, Error> {
+        match self.len() {
+            0 => Ok(None),
+            len => self.get(len - 1),
+        }
+    }
+
+    /// Returns an iterator over the elements, from the front to the back.
+    /// Each element is read from the storage when the iterator reaches it.
+    pub fn iter(&self) -> Iter<'_, 'a, V, S> {
+        Iter {
+            deque: self,
+            front: 0,
+            back: self.len(),
+        }
+    }
+
+    /// Moves the position `n` steps forward, modulo `2 * capacity`.
+    fn advance(&self, pos: u32, n: u32) -> u32 {
+        let period = 2 * self.header.capacity as u64;
+        ((pos as u64 + n as u64) % period) as u32
+    }
+
+    /// Moves the position one step backward, modulo `2 * capacity`.
+    fn retreat(&self, pos: u32) -> u32 {
+        let period = 2 * self.header.capacity as u64;
+        ((pos as u64 + period - 1) % period) as u32
+    }
+
+    /// Returns the number of steps from `from` to `to`.
+    fn distance(&self, from: u32, to: u32) -> u32 {
+        let period = 2 * self.header.capacity as u64;
+        ((to as u64 + period - from as u64) % period) as u32
+    }
+
+    fn slot_offset(&self, pos: u32) -> u32 {
+        let slot = pos % self.header.capacity;
+        self.offset + size_of::<Header>() as u32 + (slot * self.header.value_len as u32)
+    }
+}
+
+impl<'a, V, S> StorageDeque<'a, V, S>
+where
+    V: Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Deque at the given offset.
+    /// The capacity should be at least one and less than `2^31`.
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        if capacity == 0 || capacity > u32::MAX / 2 {
+            return Err(Error::OutOfCapacity);
+        }
+
+        let header = Header::new::<V>(capacity);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageDeque {
+            storage,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Appends an element to the back of the deque.
+    pub fn push_back(&mut self, value: V) -> Result<(), Error> {
+        if self.len() >= self.header.capacity {
+            return Err(Error::OutOfCapacity);
+        }
+
+        // The value is written before the header, so a failed write never leaves
+        // the indices pointing to an unwritten element.
+        swrite_struct(self.storage, self.slot_offset(self.header.tail), &value)?;
+        let tail = self.advance(self.header.tail, 1);
+        self.write_indices(self.header.head, tail)
+    }
+
+    /// Prepends an element to the front of the deque.
+    pub fn push_front(&mut self, value: V) -> Result<(), Error> {
+        if self.len() >= self.header.capacity {
+            return Err(Error::OutOfCapacity);
+        }
+
+        let head = self.retreat(self.header.head);
+        swrite_struct(self.storage, self.slot_offset(head), &value)?;
+        self.write_indices(head, self.header.tail)
+    }
+
+    /// Removes the first element and returns it, or None if the deque is empty.
+    pub fn pop_front(&mut self) -> Result<Option<V>, Error> {
+        let value = match self.front()? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let head = self.advance(self.header.head, 1);
+        self.write_indices(head, self.header.tail)?;
+        Ok(Some(value))
+    }
+
+    /// Removes the last element and returns it, or None if the deque is empty.
+    pub fn pop_back(&mut self) -> Result<Option<V>, Error> {
+        let value = match self.back()? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let tail = self.retreat(self.header.tail);
+        self.write_indices(self.header.head, tail)?;
+        Ok(Some(value))
+    }
+
+    /// Writes the new indices into the header.
+    /// The in-memory header is updated only if the write succeeds.
+    fn write_indices(&mut self, head: u32, tail: u32) -> Result<(), Error> {
+        let (old_head, old_tail) = (self.header.head, self.header.tail);
+        self.header.head = head;
+        self.header.tail = tail;
+        if let Err(err) = swrite_struct(self.storage, self.offset, &self.header) {
+            self.header.head = old_head;
+            self.header.tail = old_tail;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the elements of a Storage Deque.
+pub struct Iter<'b, 'a, V, S>
+where
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    deque: &'b StorageDeque<'a, V, S>,
+    front: u32,
+    back: u32,
+}
+
+impl<'b, 'a, V, S> Iterator for Iter<'b, 'a, V, S>
+where
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<V, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let item = self.deque.get(self.front).transpose();
+        self.front += 1;
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.back - self.front) as usize;
+        (len, Some(len))
+    }
+}
+
+impl<'b, 'a, V, S> DoubleEndedIterator for Iter<'b, 'a, V, S>
+where
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.deque.get(self.back).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+
+    use super::*;
+    use alloc::vec::Vec;
+    use core::mem::size_of;
+
+    fn collect(deque: &StorageDeque<i32>) -> Vec<i32> {
+        deque.iter().map(|v| v.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(20, size_of::<Header>());
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageDeque::<i32>::create(&storage, 512, 16).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.boom, 0xb3000000);
+        assert_eq!(header.reserved, 0);
+        assert_eq!(header.value_len, 4);
+        assert_eq!(header.head, 0);
+        assert_eq!(header.tail, 0);
+        assert_eq!(header.capacity, 16);
+    }
+
+    #[test]
+    fn test_invalid_capacity() {
+        let storage = mock_storage(1024);
+        assert!(matches!(
+            StorageDeque::<i32>::create(&storage, 0, 0),
+            Err(Error::OutOfCapacity)
+        ));
+        assert!(matches!(
+            StorageDeque::<i32>::create(&storage, 0, u32::MAX),
+            Err(Error::OutOfCapacity)
+        ));
+    }
+
+    #[test]
+    fn test_deque() {
+        let storage = mock_storage(1024);
+        let mut deque = StorageDeque::<i32>::create(&storage, 512, 4).unwrap();
+        assert!(deque.is_empty());
+        assert_eq!(None, deque.front().unwrap());
+        assert_eq!(None, deque.back().unwrap());
+        assert_eq!(None, deque.pop_front().unwrap());
+        assert_eq!(None, deque.pop_back().unwrap());
+
+        deque.push_back(2).unwrap();
+        deque.push_back(3).unwrap();
+        deque.push_front(1).unwrap();
+        deque.push_front(0).unwrap();
+        assert_eq!(4, deque.len());
+        assert!(matches!(deque.push_back(4), Err(Error::OutOfCapacity)));
+        assert!(matches!(deque.push_front(-1), Err(Error::OutOfCapacity)));
+
+        assert_eq!(collect(&deque), [0, 1, 2, 3]);
+        assert_eq!(Some(2), deque.get(2).unwrap());
+        assert_eq!(None, deque.get(4).unwrap());
+
+        assert_eq!(Some(0), deque.pop_front().unwrap());
+        assert_eq!(Some(3), deque.pop_back().unwrap());
+        assert_eq!(collect(&deque), [1, 2]);
+        assert_eq!(Some(1), deque.front().unwrap());
+        assert_eq!(Some(2), deque.back().unwrap());
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let storage = mock_storage(1024);
+        let mut deque = StorageDeque::<i32>::create(&storage, 0, 3).unwrap();
+
+        // Moves the head and tail around the ring several times
+        for i in 0..20 {
+            deque.push_back(i).unwrap();
+            deque.push_back(i + 100).unwrap();
+            assert_eq!(Some(i), deque.pop_front().unwrap());
+            assert_eq!(Some(i + 100), deque.pop_front().unwrap());
+        }
+        assert!(deque.is_empty());
+
+        for i in 0..20 {
+            deque.push_front(i).unwrap();
+            if deque.len() == 3 {
+                assert_eq!(Some(i - 2), deque.pop_back().unwrap());
+            }
+        }
+        assert_eq!(collect(&deque), [19, 18]);
+        assert_eq!(
+            deque.iter().rev().map(|v| v.unwrap()).collect::<Vec<_>>(),
+            [18, 19]
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(1024);
+        let mut deque = StorageDeque::<i32>::create(&storage, 512, 8).unwrap();
+        deque.push_back(1).unwrap();
+        deque.push_front(0).unwrap();
+
+        let deque = StorageDeque::<i32, _>::lazy_load(&storage, 512).unwrap();
+        assert_eq!(deque.len(), 2);
+        assert_eq!(deque.capacity(), 8);
+        assert_eq!(Some(0), deque.front().unwrap());
+        assert_eq!(Some(1), deque.back().unwrap());
+
+        assert!(matches!(
+            StorageDeque::<i64>::lazy_load(&storage, 512),
+            Err(Error::InvalidOffset(512))
+        ));
+    }
+
+    #[test]
+    fn test_failed_push() {
+        let storage = mock_storage(1024);
+        let mut deque = StorageDeque::<i32>::create(&storage, 0, 4).unwrap();
+        deque.push_back(1).unwrap();
+
+        // Failing the header write
+        storage.inject(Fault::NthWrite {
+            n: 5,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(deque.push_front(0).is_err());
+        assert_eq!(deque.len(), 1);
+        assert_eq!(collect(&deque), [1]);
+
+        let deque = StorageDeque::<i32>::lazy_load(&storage, 0).unwrap();
+        assert_eq!(collect(&deque), [1]);
+    }
+
+    #[test]
+    fn test_failed_pop() {
+        let storage = mock_storage(1024);
+        let mut deque = StorageDeque::<i32>::create(&storage, 0, 4).unwrap();
+        deque.push_back(1).unwrap();
+        deque.push_back(2).unwrap();
+
+        storage.inject(Fault::WriteRange {
+            range: 0..20,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert!(deque.pop_front().is_err());
+        assert!(deque.pop_back().is_err());
+        assert_eq!(collect(&deque), [1, 2]);
+
+        storage.clear_faults();
+        storage.inject(Fault::ReadRange {
+            range: 20..24,
+            kind: HostErrorKind::OutOfGas,
+        });
+        let mut iter = deque.iter();
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::HostError(HostErrorKind::OutOfGas)))
+        ));
+        assert!(matches!(iter.next(), Some(Ok(2))));
+        assert!(iter.next().is_none());
+    }
+}
@@ -0,0 +1,402 @@
+//! Secondary indexes of the Indexed Map.
+//!
+//! An index keeps the primary keys by an index key, which is derived from the key and
+//! the value of each entry by a function. Each index is a Storage B+Tree at its own offset.
+
+use crate::collections::btree::tree::Iter;
+use crate::collections::btree::StorageBTree;
+use crate::error::Error;
+use crate::storage::{ReadOnlyStorage, Storage};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem::MaybeUninit;
+use core::ops::Bound;
+use core::result::Result;
+
+/// `Index` is a secondary index that is updated by the Indexed Map.
+pub trait Index<K, V> {
+    /// checks if the entry can be added into the index, before any change is written.
+    fn check(&self, key: &K, value: &V) -> Result<(), Error>;
+
+    /// updates the index when the value of the key changes from `old` to `new`.
+    /// `old` is None for a new key, and `new` is None for a removed key.
+    /// If it fails, the index should be left unchanged, so the Indexed Map can revert
+    /// the other indexes.
+    fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>) -> Result<(), Error>;
+}
+
+/// `IndexList` is the list of the indexes of an Indexed Map.
+/// It is implemented for the tuples of indexes, and it can be implemented
+/// for a struct that keeps the indexes as named fields.
+pub trait IndexList<K, V> {
+    /// returns the indexes
+    fn indexes(&mut self) -> Vec<&mut dyn Index<K, V>>;
+}
+
+macro_rules! impl_index_list {
+    ($($index:ident),*) => {
+        impl<K, V, $($index: Index<K, V>),*> IndexList<K, V> for ($($index,)*) {
+            #[allow(non_snake_case)]
+            fn indexes(&mut self) -> Vec<&mut dyn Index<K, V>> {
+                let ($($index,)*) = self;
+                vec![$($index),*]
+            }
+        }
+    };
+}
+
+impl_index_list!();
+impl_index_list!(A);
+impl_index_list!(A, B);
+impl_index_list!(A, B, C);
+impl_index_list!(A, B, C, D);
+
+/// The index that keeps at most one primary key for each index key.
+/// Adding a second key with the same index key fails with `DuplicateKey` error.
+pub struct UniqueIndex<'a, IK, K, V, S = dyn Storage + 'a>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    tree: StorageBTree<'a, IK, K, S>,
+    index_key: fn(&K, &V) -> IK,
+}
+
+impl<'a, IK, K, V, S> UniqueIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Unique Index. `index_key` returns the index key of an entry.
+    pub fn lazy_load(
+        storage: &'a S,
+        offset: u32,
+        index_key: fn(&K, &V) -> IK,
+    ) -> Result<Self, Error> {
+        Ok(UniqueIndex {
+            tree: StorageBTree::lazy_load(storage, offset)?,
+            index_key,
+        })
+    }
+
+    /// Returns the number of the entries in the index.
+    pub fn len(&self) -> u32 {
+        self.tree.len()
+    }
+
+    /// Returns true if the index has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the primary key of the given index key. If it doesn't exist, it returns None.
+    pub fn find(&self, index_key: &IK) -> Result<Option<K>, Error> {
+        self.tree.find(index_key)
+    }
+
+    /// Returns an iterator over the index keys and the primary keys, sorted by the index key.
+    pub fn iter(&self) -> Iter<'_, 'a, IK, K, S> {
+        self.tree.iter()
+    }
+}
+
+impl<'a, IK, K, V, S> UniqueIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Unique Index at the given offset.
+    /// `page_size` and `capacity` are the parameters of its Storage B+Tree.
+    pub fn create(
+        storage: &'a S,
+        offset: u32,
+        page_size: u32,
+        capacity: u32,
+        index_key: fn(&K, &V) -> IK,
+    ) -> Result<Self, Error> {
+        Ok(UniqueIndex {
+            tree: StorageBTree::create(storage, offset, page_size, capacity)?,
+            index_key,
+        })
+    }
+}
+
+impl<'a, IK, K, V, S> Index<K, V> for UniqueIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Eq + Copy,
+    S: Storage + ?Sized,
+{
+    fn check(&self, key: &K, value: &V) -> Result<(), Error> {
+        match self.tree.find(&(self.index_key)(key, value))? {
+            Some(other) if other != *key => Err(Error::DuplicateKey),
+            _ => Ok(()),
+        }
+    }
+
+    fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>) -> Result<(), Error> {
+        let old = old.map(|value| (self.index_key)(key, value));
+        let new = new.map(|value| (self.index_key)(key, value));
+        if old == new {
+            return Ok(());
+        }
+        // The new entry is added before the old one is removed, so if the index is out of
+        // capacity, nothing is changed.
+        if let Some(new) = new {
+            self.tree.insert(new, *key)?;
+        }
+        if let Some(old) = old {
+            if let Err(err) = self.tree.remove(&old) {
+                if let Some(new) = new {
+                    let _ = self.tree.remove(&new);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The index that keeps any number of primary keys for each index key.
+/// The primary keys of an index key are sorted.
+pub struct MultiIndex<'a, IK, K, V, S = dyn Storage + 'a>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: ReadOnlyStorage + ?Sized,
+{
+    tree: StorageBTree<'a, MultiKey<IK, K>, (), S>,
+    index_key: fn(&K, &V) -> IK,
+}
+
+impl<'a, IK, K, V, S> MultiIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Multi Index. `index_key` returns the index key of an entry.
+    pub fn lazy_load(
+        storage: &'a S,
+        offset: u32,
+        index_key: fn(&K, &V) -> IK,
+    ) -> Result<Self, Error> {
+        Ok(MultiIndex {
+            tree: StorageBTree::lazy_load(storage, offset)?,
+            index_key,
+        })
+    }
+
+    /// Returns the number of the entries in the index.
+    pub fn len(&self) -> u32 {
+        self.tree.len()
+    }
+
+    /// Returns true if the index has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns true if the index has the primary key for the index key.
+    pub fn contains(&self, index_key: &IK, key: &K) -> Result<bool, Error> {
+        self.tree.contains_key(&MultiKey::new(*index_key, *key))
+    }
+
+    /// Returns an iterator over the primary keys of the index key, in ascending order.
+    pub fn prefix(&self, index_key: &IK) -> Prefix<'_, 'a, IK, K, S> {
+        let start = MultiKey::start(*index_key);
+        Prefix {
+            inner: self.tree.range((Bound::Included(start), Bound::Unbounded)),
+            index_key: *index_key,
+            done: false,
+        }
+    }
+}
+
+impl<'a, IK, K, V, S> MultiIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Multi Index at the given offset.
+    /// `page_size` and `capacity` are the parameters of its Storage B+Tree.
+    pub fn create(
+        storage: &'a S,
+        offset: u32,
+        page_size: u32,
+        capacity: u32,
+        index_key: fn(&K, &V) -> IK,
+    ) -> Result<Self, Error> {
+        Ok(MultiIndex {
+            tree: StorageBTree::create(storage, offset, page_size, capacity)?,
+            index_key,
+        })
+    }
+}
+
+impl<'a, IK, K, V, S> Index<K, V> for MultiIndex<'a, IK, K, V, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: Storage + ?Sized,
+{
+    fn check(&self, _key: &K, _value: &V) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn update(&mut self, key: &K, old: Option<&V>, new: Option<&V>) -> Result<(), Error> {
+        let old = old.map(|value| (self.index_key)(key, value));
+        let new = new.map(|value| (self.index_key)(key, value));
+        if old == new {
+            return Ok(());
+        }
+        // The new entry is added before the old one is removed, so if the index is out of
+        // capacity, nothing is changed.
+        if let Some(new) = new {
+            self.tree.insert(MultiKey::new(new, *key), ())?;
+        }
+        if let Some(old) = old {
+            if let Err(err) = self.tree.remove(&MultiKey::new(old, *key)) {
+                if let Some(new) = new {
+                    let _ = self.tree.remove(&MultiKey::new(new, *key));
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The key of the entries of a Multi Index.
+/// It is packed, so it has no padding bytes, and the primary key is zeroed if it is absent.
+/// The key without a primary key is smaller than the keys with a primary key,
+/// so it is the start of the entries of the index key.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct MultiKey<IK, K> {
+    index_key: IK,
+    present: u8,
+    key: MaybeUninit<K>,
+}
+
+impl<IK: Copy, K: Copy> MultiKey<IK, K> {
+    fn new(index_key: IK, key: K) -> Self {
+        MultiKey {
+            index_key,
+            present: 1,
+            key: MaybeUninit::new(key),
+        }
+    }
+
+    fn start(index_key: IK) -> Self {
+        MultiKey {
+            index_key,
+            present: 0,
+            key: MaybeUninit::zeroed(),
+        }
+    }
+
+    fn index_key(&self) -> IK {
+        self.index_key
+    }
+
+    fn key(&self) -> Option<K> {
+        match self.present {
+            // SAFETY: The primary key is initialized if it is present.
+            1 => Some(unsafe { self.key.assume_init() }),
+            _ => None,
+        }
+    }
+}
+
+impl<IK: Ord + Copy, K: Ord + Copy> Ord for MultiKey<IK, K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.index_key()
+            .cmp(&other.index_key())
+            .then_with(|| self.key().cmp(&other.key()))
+    }
+}
+
+impl<IK: Ord + Copy, K: Ord + Copy> PartialOrd for MultiKey<IK, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<IK: Ord + Copy, K: Ord + Copy> PartialEq for MultiKey<IK, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<IK: Ord + Copy, K: Ord + Copy> Eq for MultiKey<IK, K> {}
+
+/// An iterator over the primary keys of an index key in a Multi Index.
+pub struct Prefix<'b, 'a, IK, K, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: ReadOnlyStorage + ?Sized,
+{
+    inner: Iter<'b, 'a, MultiKey<IK, K>, (), S>,
+    index_key: IK,
+    done: bool,
+}
+
+impl<'b, 'a, IK, K, S> Iterator for Prefix<'b, 'a, IK, K, S>
+where
+    IK: Sized + Ord + Copy,
+    K: Sized + Ord + Copy,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<K, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some(Ok((entry, ()))) if entry.index_key() == self.index_key => entry.key().map(Ok),
+            Some(Err(err)) => {
+                self.done = true;
+                Some(Err(err))
+            }
+            _ => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::size_of;
+    use core::{ptr, slice};
+
+    fn bytes_of<T>(value: &T) -> &[u8] {
+        unsafe { slice::from_raw_parts(ptr::from_ref(value).cast(), size_of::<T>()) }
+    }
+
+    #[test]
+    fn test_multi_key() {
+        assert_eq!(13, size_of::<MultiKey<u32, u64>>());
+        assert_eq!(4, size_of::<MultiKey<u16, u8>>());
+
+        let start = MultiKey::<u32, u64>::start(7);
+        assert_eq!(bytes_of(&start)[4..], [0; 9]);
+        assert_eq!(None, start.key());
+        assert_eq!(Some(1), MultiKey::new(7u32, 1u64).key());
+
+        assert!(start < MultiKey::new(7, 0));
+        assert!(MultiKey::new(7, u64::MAX) < MultiKey::start(8));
+        assert!(MultiKey::new(7, 1) < MultiKey::new(7, 2));
+        assert!(MultiKey::new(7, 1) == MultiKey::new(7, 1));
+    }
+}
@@ -0,0 +1,464 @@
+//! Indexed Map, is a Storage B+Tree that keeps its secondary indexes in sync.
+//!
+//! Inserting or removing an entry updates all the indexes of the map.
+//! The indexes are checked before any change is written, so adding a duplicate key
+//! into a unique index fails without changing the map or the other indexes.
+//! The primary map is updated before the indexes, and if updating an index fails,
+//! the changes of the primary map and the other indexes are reverted.
+//! An index adds the new entry of a key before removing the old one, so the index that fails
+//! is left unchanged. Reverting is the best effort: if a revert fails too, the first error
+//! is returned and the map may be out of sync.
+
+use super::index::IndexList;
+use crate::collections::btree::tree::Iter;
+use crate::collections::btree::StorageBTree;
+use crate::error::Error;
+use crate::storage::{ReadOnlyStorage, Storage};
+use core::ops::RangeBounds;
+use core::result::Result;
+
+/// The instance of Indexed Map.
+/// The primary map and the indexes are created or loaded by the caller.
+pub struct IndexedMap<'a, K, V, I, S = dyn Storage + 'a>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    primary: StorageBTree<'a, K, V, S>,
+    indexes: I,
+}
+
+impl<'a, K, V, I, S> IndexedMap<'a, K, V, I, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// creates an Indexed Map from the primary map and its indexes.
+    /// The indexes should be in sync with the primary map, e.g. they are all empty.
+    pub fn new(primary: StorageBTree<'a, K, V, S>, indexes: I) -> Self {
+        IndexedMap { primary, indexes }
+    }
+
+    /// Returns the primary map.
+    pub fn primary(&self) -> &StorageBTree<'a, K, V, S> {
+        &self.primary
+    }
+
+    /// Returns the indexes, e.g. for finding the primary keys by an index.
+    pub fn indexes(&self) -> &I {
+        &self.indexes
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> u32 {
+        self.primary.len()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.primary.is_empty()
+    }
+
+    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
+    pub fn find(&self, key: &K) -> Result<Option<V>, Error> {
+        self.primary.find(key)
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        self.primary.contains_key(key)
+    }
+
+    /// Returns an iterator over the key-value pairs of the map, sorted by key.
+    pub fn iter(&self) -> Iter<'_, 'a, K, V, S> {
+        self.primary.iter()
+    }
+
+    /// Returns an iterator over the key-value pairs in the given range of keys, sorted by key.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, 'a, K, V, S> {
+        self.primary.range(range)
+    }
+}
+
+impl<'a, K, V, I, S> IndexedMap<'a, K, V, I, S>
+where
+    K: Sized + Ord + Copy,
+    V: Sized + Copy,
+    I: IndexList<K, V>,
+    S: Storage + ?Sized,
+{
+    /// Inserts a key-value pair into the map and updates the indexes.
+    /// If the map did not have this key present, None is returned.
+    /// If the map did have this key present, the value is updated, and the old value is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+        for index in self.indexes.indexes() {
+            index.check(&key, &value)?;
+        }
+
+        // The primary map is updated first, so if it is out of capacity,
+        // no index is changed.
+        let old_value = self.primary.insert(key, value)?;
+        let mut indexes = self.indexes.indexes();
+        for i in 0..indexes.len() {
+            if let Err(err) = indexes[i].update(&key, old_value.as_ref(), Some(&value)) {
+                // Reverting the changes. It is the best effort and the first error is returned.
+                for index in indexes[..i].iter_mut() {
+                    let _ = index.update(&key, Some(&value), old_value.as_ref());
+                }
+                let _ = match old_value {
+                    Some(old_value) => self.primary.insert(key, old_value),
+                    None => self.primary.remove(&key),
+                };
+                return Err(err);
+            }
+        }
+        Ok(old_value)
+    }
+
+    /// Removes the key from the map and the indexes, and returns its value.
+    /// If the key doesn't exist, it returns None.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
+        let old_value = match self.primary.remove(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut indexes = self.indexes.indexes();
+        for i in 0..indexes.len() {
+            if let Err(err) = indexes[i].update(key, Some(&old_value), None) {
+                // Reverting the changes. It is the best effort and the first error is returned.
+                for index in indexes[..i].iter_mut() {
+                    let _ = index.update(key, None, Some(&old_value));
+                }
+                let _ = self.primary.insert(*key, old_value);
+                return Err(err);
+            }
+        }
+        Ok(Some(old_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::{MultiIndex, UniqueIndex};
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
+    use alloc::vec::Vec;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[repr(C)]
+    struct Order {
+        owner: u32,
+        serial: u32,
+        amount: u64,
+    }
+
+    fn order(owner: u32, serial: u32) -> Order {
+        Order {
+            owner,
+            serial,
+            amount: 100,
+        }
+    }
+
+    type OrderIndexes<'a, S = dyn Storage + 'a> = (
+        MultiIndex<'a, u32, u64, Order, S>,
+        UniqueIndex<'a, u32, u64, Order, S>,
+    );
+
+    fn create(storage: &'_ dyn Storage) -> IndexedMap<'_, u64, Order, OrderIndexes<'_>> {
+        let primary = StorageBTree::create(storage, 0, 256, 16).unwrap();
+        let by_owner = MultiIndex::create(storage, 8192, 128, 16, |_, o: &Order| o.owner).unwrap();
+        let by_serial =
+            UniqueIndex::create(storage, 16384, 128, 16, |_, o: &Order| o.serial).unwrap();
+        IndexedMap::new(primary, (by_owner, by_serial))
+    }
+
+    fn owned(map: &IndexedMap<'_, u64, Order, OrderIndexes<'_>>, owner: u32) -> Vec<u64> {
+        map.indexes()
+            .0
+            .prefix(&owner)
+            .map(|key| key.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_indexed_map() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+
+        assert_eq!(None, map.insert(3, order(1, 30)).unwrap());
+        assert_eq!(None, map.insert(1, order(1, 10)).unwrap());
+        assert_eq!(None, map.insert(2, order(2, 20)).unwrap());
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(owned(&map, 1), [1, 3]);
+        assert_eq!(owned(&map, 2), [2]);
+        assert!(owned(&map, 0).is_empty());
+        assert!(owned(&map, 3).is_empty());
+        assert_eq!(Some(2), map.indexes().1.find(&20).unwrap());
+        assert_eq!(None, map.indexes().1.find(&40).unwrap());
+        assert!(map.indexes().0.contains(&1, &3).unwrap());
+    }
+
+    #[test]
+    fn test_update() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+        map.insert(1, order(1, 10)).unwrap();
+        map.insert(2, order(1, 20)).unwrap();
+
+        // Moving the order to another owner and another serial
+        assert_eq!(Some(order(1, 10)), map.insert(1, order(2, 11)).unwrap());
+        assert_eq!(owned(&map, 1), [2]);
+        assert_eq!(owned(&map, 2), [1]);
+        assert_eq!(None, map.indexes().1.find(&10).unwrap());
+        assert_eq!(Some(1), map.indexes().1.find(&11).unwrap());
+
+        // The indexes don't change if the index keys don't change
+        let mut changed = order(2, 11);
+        changed.amount = 5;
+        map.insert(1, changed).unwrap();
+        assert_eq!(map.indexes().0.len(), 2);
+        assert_eq!(map.indexes().1.len(), 2);
+        assert_eq!(Some(changed), map.find(&1).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+        map.insert(1, order(1, 10)).unwrap();
+        map.insert(2, order(2, 20)).unwrap();
+        let snapshot = storage.snapshot();
+
+        assert!(matches!(
+            map.insert(3, order(3, 10)),
+            Err(Error::DuplicateKey)
+        ));
+        assert!(matches!(
+            map.insert(2, order(2, 10)),
+            Err(Error::DuplicateKey)
+        ));
+        // Nothing is written
+        assert!(storage.diff(&snapshot).is_empty());
+        assert_eq!(map.len(), 2);
+        assert!(owned(&map, 3).is_empty());
+    }
+
+    #[test]
+    fn test_out_of_capacity() {
+        // A primary map with one leaf page
+        let storage = mock_storage(32 * 1024);
+        let primary = StorageBTree::create(&storage, 0, 64, 1).unwrap();
+        let by_owner = MultiIndex::create(&storage, 8192, 128, 16, |_, o: &Order| o.owner).unwrap();
+        let by_serial =
+            UniqueIndex::create(&storage, 16384, 128, 16, |_, o: &Order| o.serial).unwrap();
+        let mut map = IndexedMap::new(primary, (by_owner, by_serial));
+
+        let mut key: u64 = 0;
+        let err = loop {
+            match map.insert(key, order(1, key as u32)) {
+                Ok(_) => key += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::OutOfCapacity));
+        assert!(key > 0);
+
+        // The indexes have no entries of the failed key.
+        assert_eq!(map.len(), key as u32);
+        assert_eq!(map.indexes().0.len(), key as u32);
+        assert_eq!(map.indexes().1.len(), key as u32);
+        assert!(!map.indexes().0.contains(&1, &key).unwrap());
+        assert_eq!(None, map.indexes().1.find(&(key as u32)).unwrap());
+
+        // Updating an existing key still works.
+        map.insert(0, order(2, 100)).unwrap();
+        assert!(map.indexes().0.contains(&2, &0).unwrap());
+    }
+
+    #[test]
+    fn test_failed_index() {
+        // An index with one leaf page
+        let storage = mock_storage(32 * 1024);
+        let primary = StorageBTree::create(&storage, 0, 256, 16).unwrap();
+        let by_owner = MultiIndex::create(&storage, 8192, 128, 16, |_, o: &Order| o.owner).unwrap();
+        let by_serial =
+            UniqueIndex::create(&storage, 16384, 64, 1, |_, o: &Order| o.serial).unwrap();
+        let mut map = IndexedMap::new(primary, (by_owner, by_serial));
+
+        let mut key: u64 = 0;
+        let err = loop {
+            match map.insert(key, order(1, key as u32)) {
+                Ok(_) => key += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::OutOfCapacity));
+
+        // The primary map and the other index are reverted.
+        assert_eq!(map.len(), key as u32);
+        assert_eq!(None, map.find(&key).unwrap());
+        assert_eq!(map.indexes().0.len(), key as u32);
+        assert!(!map.indexes().0.contains(&1, &key).unwrap());
+    }
+
+    /// Checks that the indexes have exactly the entries of the primary map.
+    fn check_sync<S: Storage + ?Sized>(map: &IndexedMap<'_, u64, Order, OrderIndexes<'_, S>, S>) {
+        for entry in map.iter() {
+            let (key, value) = entry.unwrap();
+            assert!(map.indexes().0.contains(&value.owner, &key).unwrap());
+            assert_eq!(Some(key), map.indexes().1.find(&value.serial).unwrap());
+        }
+        assert_eq!(map.indexes().0.len(), map.len());
+        assert_eq!(map.indexes().1.len(), map.len());
+    }
+
+    #[test]
+    fn test_update_full_index() {
+        // The indexes with one leaf page
+        let storage = mock_storage(32 * 1024);
+        let primary = StorageBTree::create(&storage, 0, 256, 16).unwrap();
+        let by_owner = MultiIndex::create(&storage, 8192, 64, 1, |_, o: &Order| o.owner).unwrap();
+        let by_serial =
+            UniqueIndex::create(&storage, 16384, 64, 1, |_, o: &Order| o.serial).unwrap();
+        let mut map = IndexedMap::new(primary, (by_owner, by_serial));
+
+        let mut key: u64 = 0;
+        while map.insert(key, order(1, key as u32)).is_ok() {
+            key += 1;
+        }
+        check_sync(&map);
+
+        // Moving an existing key to another owner or another serial in the full index
+        assert!(matches!(
+            map.insert(0, order(2, 0)),
+            Err(Error::OutOfCapacity)
+        ));
+        assert!(matches!(
+            map.insert(0, order(1, 100)),
+            Err(Error::OutOfCapacity)
+        ));
+        assert_eq!(Some(order(1, 0)), map.find(&0).unwrap());
+        assert!(!map.indexes().0.contains(&2, &0).unwrap());
+        check_sync(&map);
+
+        // It works after removing a key
+        map.remove(&1).unwrap();
+        map.insert(0, order(2, 0)).unwrap();
+        assert!(map.indexes().0.contains(&2, &0).unwrap());
+        check_sync(&map);
+    }
+
+    #[test]
+    fn test_failed_remove() {
+        // Failing each write of removing the key from the primary map and the indexes,
+        // until the remove has no more writes to fail
+        for nth in 1.. {
+            let storage = mock_storage(32 * 1024);
+            let mut map = create(&storage);
+            for key in 0..10 {
+                map.insert(key, order(key as u32 % 3, key as u32)).unwrap();
+            }
+
+            let writes = storage.write_count();
+            storage.inject(Fault::NthWrite {
+                n: writes + nth,
+                kind: HostErrorKind::StorageFull,
+            });
+            let result = map.remove(&4);
+            let done = storage.write_count() < writes + nth;
+            storage.clear_faults();
+
+            check_sync(&map);
+            match result {
+                Ok(value) => {
+                    assert_eq!(Some(order(1, 4)), value);
+                    assert_eq!(map.len(), 9);
+                }
+                Err(_) => {
+                    assert_eq!(Some(order(1, 4)), map.find(&4).unwrap());
+                    assert_eq!(map.len(), 10);
+                }
+            }
+            if done {
+                assert!(nth > 3);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+        for key in 0..10 {
+            map.insert(key, order(key as u32 % 3, key as u32)).unwrap();
+        }
+        assert_eq!(owned(&map, 1), [1, 4, 7]);
+
+        assert_eq!(Some(order(1, 4)), map.remove(&4).unwrap());
+        assert_eq!(None, map.remove(&4).unwrap());
+        assert_eq!(owned(&map, 1), [1, 7]);
+        assert_eq!(None, map.indexes().1.find(&4).unwrap());
+        assert_eq!(map.indexes().0.len(), 9);
+        assert_eq!(map.indexes().1.len(), 9);
+
+        // The serial can be used again
+        map.insert(10, order(0, 4)).unwrap();
+        assert_eq!(Some(10), map.indexes().1.find(&4).unwrap());
+    }
+
+    #[test]
+    fn test_many() {
+        let storage = mock_storage(64 * 1024);
+        let primary = StorageBTree::create(&storage, 0, 256, 64).unwrap();
+        let by_owner =
+            MultiIndex::create(&storage, 32768, 256, 64, |_, o: &Order| o.owner).unwrap();
+        let mut map = IndexedMap::new(primary, (by_owner,));
+        for key in 0..200 {
+            map.insert(key, order(key as u32 % 5, 0)).unwrap();
+        }
+
+        let keys: Vec<u64> = map.indexes().0.prefix(&2).map(|key| key.unwrap()).collect();
+        assert_eq!(keys, (0..200).filter(|k| k % 5 == 2).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+        map.insert(1, order(7, 10)).unwrap();
+
+        let primary = StorageBTree::<u64, Order>::lazy_load(&storage, 0).unwrap();
+        let by_owner =
+            MultiIndex::<u32, u64, Order>::lazy_load(&storage, 8192, |_, o| o.owner).unwrap();
+        let by_serial =
+            UniqueIndex::<u32, u64, Order>::lazy_load(&storage, 16384, |_, o| o.serial).unwrap();
+        let mut map = IndexedMap::new(primary, (by_owner, by_serial));
+        assert_eq!(owned(&map, 7), [1]);
+
+        map.remove(&1).unwrap();
+        assert!(owned(&map, 7).is_empty());
+        assert!(map.indexes().1.is_empty());
+    }
+
+    #[test]
+    fn test_index_list() {
+        let storage = mock_storage(32 * 1024);
+        let mut map = create(&storage);
+        map.insert(1, order(1, 10)).unwrap();
+
+        let indexes: &mut dyn IndexList<u64, Order> = &mut map.indexes;
+        let list = indexes.indexes();
+        assert_eq!(list.len(), 2);
+        assert!(matches!(
+            list[1].check(&2, &order(1, 10)),
+            Err(Error::DuplicateKey)
+        ));
+        assert!(list[1].check(&1, &order(1, 10)).is_ok());
+    }
+}
@@ -0,0 +1,7 @@
+//! Storage Indexed Map
+
+pub mod index;
+pub mod map;
+
+pub use index::{Index, IndexList, MultiIndex, UniqueIndex};
+pub use map::IndexedMap;
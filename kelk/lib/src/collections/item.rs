@@ -0,0 +1,400 @@
+//! Storage Item, is a single value that instead of using Random Access Memory,
+//! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
+//!
+//! The value is stored after a one byte presence tag,
+//! so an item that has never been written can be distinguished from a zero value.
+//!
+//! `StorageItem` copies the memory of the value, so it only keeps `AnyBitPattern` types,
+//! like integers, arrays and plain `#[repr(C)]` structs without padding.
+//! The other types, like `bool`, enums, `String` or `Vec`, can be kept in `StorageCborItem`,
+//! which encodes them in CBOR.
+
+use crate::error::Error;
+use crate::storage::{
+    encode_cbor, sread_struct, swrite_cbor_data, swrite_struct, AnyBitPattern, CborStorage,
+    ReadOnlyStorage, Storage,
+};
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::result::Result;
+use minicbor::{Decode, Encode};
+
+const TAG_EMPTY: u8 = 0;
+const TAG_PRESENT: u8 = 1;
+
+/// `Slot` is a location in the storage file.
+/// Defining slots as constants keeps the storage layout of a contract in one place.
+pub struct Slot {
+    /// The offset of the slot in the storage file
+    pub offset: u32,
+}
+
+impl Slot {
+    /// defines a new slot
+    pub const fn new(offset: u32) -> Self {
+        Self { offset }
+    }
+}
+
+/// The instance of Storage Item.
+/// If it is bound to a read-only storage, only the read methods are available.
+pub struct StorageItem<'a, T, S = dyn Storage + 'a>
+where
+    T: AnyBitPattern,
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T, S> StorageItem<'a, T, S>
+where
+    T: AnyBitPattern,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// Number of bytes that the item occupies in the storage file.
+    pub const SIZE: u32 = 1 + size_of::<T>() as u32;
+
+    /// binds a new instance of Storage Item to the given offset.
+    pub fn new(storage: &'a S, offset: u32) -> Self {
+        StorageItem {
+            storage,
+            offset,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// binds a new instance of Storage Item to the given slot.
+    pub fn from_slot(storage: &'a S, slot: &Slot) -> Self {
+        Self::new(storage, slot.offset)
+    }
+
+    /// Returns true if the item has been saved and not removed.
+    pub fn exists(&self) -> Result<bool, Error> {
+        Ok(self.storage.sread_u8(self.offset)? == TAG_PRESENT)
+    }
+
+    /// Returns the value of the item. If the item doesn't exist, it returns `NotFound` error.
+    pub fn load(&self) -> Result<T, Error> {
+        self.may_load()?.ok_or(Error::NotFound)
+    }
+
+    /// Returns the value of the item. If the item doesn't exist, it returns None.
+    pub fn may_load(&self) -> Result<Option<T>, Error> {
+        if !self.exists()? {
+            return Ok(None);
+        }
+        Ok(Some(sread_struct(self.storage, self.offset + 1)?))
+    }
+}
+
+impl<'a, T, S> StorageItem<'a, T, S>
+where
+    T: AnyBitPattern,
+    S: Storage + ?Sized,
+{
+    /// Saves the value of the item.
+    pub fn save(&self, value: &T) -> Result<(), Error> {
+        // The value is written before the tag, so a failed write never marks
+        // an unwritten value as present.
+        swrite_struct(self.storage, self.offset + 1, value)?;
+        self.storage.swrite_u8(self.offset, TAG_PRESENT)?;
+        Ok(())
+    }
+
+    /// Loads the value, applies the given function on it and saves the result.
+    /// If the item doesn't exist, it returns `NotFound` error.
+    pub fn update<E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(T) -> Result<T, E>,
+        E: From<Error>,
+    {
+        let value = f(self.load()?)?;
+        self.save(&value)?;
+        Ok(value)
+    }
+
+    /// Removes the item. The next `may_load` returns None.
+    pub fn remove(&self) -> Result<(), Error> {
+        self.storage.swrite_u8(self.offset, TAG_EMPTY)?;
+        Ok(())
+    }
+}
+
+/// The instance of Storage CBOR Item.
+/// The value is encoded in CBOR and it is kept with its length, like `CborStorage`.
+/// The item doesn't exist if the length is zero, which is the case for an unwritten storage.
+/// If it is bound to a read-only storage, only the read methods are available.
+pub struct StorageCborItem<'a, T, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    capacity: u32,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T, S> StorageCborItem<'a, T, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// binds a new instance of Storage CBOR Item to the given offset.
+    /// `capacity` is the maximum length of the encoded value.
+    pub fn new(storage: &'a S, offset: u32, capacity: u32) -> Self {
+        StorageCborItem {
+            storage,
+            offset,
+            capacity,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// binds a new instance of Storage CBOR Item to the given slot.
+    pub fn from_slot(storage: &'a S, slot: &Slot, capacity: u32) -> Self {
+        Self::new(storage, slot.offset, capacity)
+    }
+
+    /// Number of bytes that the item occupies in the storage file.
+    pub fn size(&self) -> u32 {
+        4 + self.capacity
+    }
+
+    /// Returns true if the item has been saved and not removed.
+    pub fn exists(&self) -> Result<bool, Error> {
+        Ok(self.storage.sread_u32(self.offset)? != 0)
+    }
+
+    /// Returns the value of the item. If the item doesn't exist, it returns `NotFound` error.
+    pub fn load(&self) -> Result<T, Error>
+    where
+        T: for<'b> Decode<'b>,
+    {
+        self.may_load()?.ok_or(Error::NotFound)
+    }
+
+    /// Returns the value of the item. If the item doesn't exist, it returns None.
+    pub fn may_load(&self) -> Result<Option<T>, Error>
+    where
+        T: for<'b> Decode<'b>,
+    {
+        if !self.exists()? {
+            return Ok(None);
+        }
+        Ok(Some(self.storage.sread_cbor(self.offset)?))
+    }
+}
+
+impl<'a, T, S> StorageCborItem<'a, T, S>
+where
+    S: Storage + ?Sized,
+{
+    /// Saves the value of the item.
+    /// It returns `OutOfCapacity` error if the encoded value is longer than the capacity.
+    pub fn save(&self, value: &T) -> Result<(), Error>
+    where
+        T: Encode,
+    {
+        let data = encode_cbor(value)?;
+        if data.len() > self.capacity as usize {
+            return Err(Error::OutOfCapacity);
+        }
+
+        swrite_cbor_data(self.storage, self.offset, &data)
+    }
+
+    /// Loads the value, applies the given function on it and saves the result.
+    /// If the item doesn't exist, it returns `NotFound` error.
+    pub fn update<E, F>(&self, f: F) -> Result<T, E>
+    where
+        T: Encode + for<'b> Decode<'b>,
+        F: FnOnce(T) -> Result<T, E>,
+        E: From<Error>,
+    {
+        let value = f(self.load()?)?;
+        self.save(&value)?;
+        Ok(value)
+    }
+
+    /// Removes the item. The next `may_load` returns None.
+    pub fn remove(&self) -> Result<(), Error> {
+        self.storage.swrite_u32(self.offset, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::hash::NoPadding;
+    use crate::mock::{mock_storage, Fault};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(5, StorageItem::<i32>::SIZE);
+        assert_eq!(9, StorageItem::<u64>::SIZE);
+    }
+
+    #[test]
+    fn test_item() {
+        let storage = mock_storage(64);
+        let item = StorageItem::<i32>::new(&storage, 8);
+        assert!(!item.exists().unwrap());
+        assert_eq!(None, item.may_load().unwrap());
+        assert_eq!(Err(Error::NotFound), item.load());
+
+        item.save(&0).unwrap();
+        assert!(item.exists().unwrap());
+        assert_eq!(Some(0), item.may_load().unwrap());
+
+        item.save(&-7).unwrap();
+        assert_eq!(-7, item.load().unwrap());
+
+        item.remove().unwrap();
+        assert_eq!(None, item.may_load().unwrap());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let storage = mock_storage(64);
+        StorageItem::<u64>::new(&storage, 0).save(&7).unwrap();
+
+        let read_only: &dyn ReadOnlyStorage = &storage;
+        let item = StorageItem::<u64, _>::new(read_only, 0);
+        assert!(item.exists().unwrap());
+        assert_eq!(7, item.load().unwrap());
+    }
+
+    #[test]
+    fn test_struct() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(C)]
+        struct Config {
+            owner: [u8; 4],
+            fee: u16,
+            flags: u16,
+        }
+
+        unsafe impl NoPadding for Config {}
+        unsafe impl AnyBitPattern for Config {}
+
+        const CONFIG: Slot = Slot::new(16);
+
+        let storage = mock_storage(64);
+        let item = StorageItem::<Config>::from_slot(&storage, &CONFIG);
+        let config = Config {
+            owner: [1, 2, 3, 4],
+            fee: 30,
+            flags: 1,
+        };
+        item.save(&config).unwrap();
+
+        let item = StorageItem::<Config>::new(&storage, CONFIG.offset);
+        assert_eq!(config, item.load().unwrap());
+    }
+
+    #[test]
+    fn test_update() {
+        let storage = mock_storage(64);
+        let item = StorageItem::<u32>::new(&storage, 0);
+
+        let res = item.update(|v| Ok::<u32, Error>(v + 1));
+        assert_eq!(Err(Error::NotFound), res);
+
+        item.save(&1).unwrap();
+        assert_eq!(2, item.update(|v| Ok::<u32, Error>(v + 1)).unwrap());
+        assert_eq!(2, item.load().unwrap());
+
+        let res = item.update(|_| Err(Error::OutOfRange));
+        assert_eq!(Err(Error::OutOfRange), res);
+        assert_eq!(2, item.load().unwrap());
+    }
+
+    #[test]
+    fn test_failed_save() {
+        let storage = mock_storage(64);
+        let item = StorageItem::<u32>::new(&storage, 0);
+        item.save(&1).unwrap();
+
+        // Writes: value(1), tag(2), value(3), tag(4)
+        storage.inject(Fault::NthWrite {
+            n: 3,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert_eq!(
+            Err(Error::HostError(HostErrorKind::OutOfGas)),
+            item.save(&2)
+        );
+        assert_eq!(1, item.load().unwrap());
+    }
+
+    #[test]
+    fn test_cbor_item() {
+        use alloc::string::String;
+
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        struct Profile {
+            #[n(0)]
+            name: String,
+            #[n(1)]
+            tags: Vec<u32>,
+        }
+
+        let storage = mock_storage(128);
+        let item = StorageCborItem::<Profile>::new(&storage, 8, 32);
+        assert_eq!(36, item.size());
+        assert!(!item.exists().unwrap());
+        assert_eq!(None, item.may_load().unwrap());
+        assert_eq!(Err(Error::NotFound), item.load());
+
+        let profile = Profile {
+            name: "alice".into(),
+            tags: alloc::vec![1, 2, 3],
+        };
+        item.save(&profile).unwrap();
+        assert_eq!(profile, item.load().unwrap());
+
+        let profile = item
+            .update(|mut p| {
+                p.tags.push(4);
+                Ok::<Profile, Error>(p)
+            })
+            .unwrap();
+        assert_eq!(profile.tags, [1, 2, 3, 4]);
+
+        let long = Profile {
+            name: "a name that doesn't fit in the capacity".into(),
+            tags: Vec::new(),
+        };
+        assert_eq!(Err(Error::OutOfCapacity), item.save(&long));
+        assert_eq!(profile, item.load().unwrap());
+
+        // An empty string is encoded in one byte, and it still exists.
+        let name = StorageCborItem::<String>::new(&storage, 64, 16);
+        name.save(&String::new()).unwrap();
+        assert!(name.exists().unwrap());
+
+        item.remove().unwrap();
+        assert_eq!(None, item.may_load().unwrap());
+    }
+
+    #[test]
+    fn test_failed_cbor_save() {
+        let storage = mock_storage(64);
+        let item = StorageCborItem::<u64>::new(&storage, 0, 16);
+        item.save(&1).unwrap();
+
+        storage.inject(Fault::NthWrite {
+            n: storage.write_count() + 1,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert_eq!(
+            Err(Error::HostError(HostErrorKind::OutOfGas)),
+            item.save(&u64::MAX)
+        );
+        assert_eq!(1, item.load().unwrap());
+    }
+}
@@ -0,0 +1,22 @@
+use super::key::MAX_KEY_LEN;
+
+#[repr(C)]
+#[derive(Clone)]
+pub(super) struct Header {
+    pub boom: u32,
+    pub key_len: u16,
+    pub reserved: u16,
+    /// The offset of the B+Tree of the keys
+    pub index: u32,
+}
+
+impl Header {
+    pub fn new(index: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            key_len: MAX_KEY_LEN as u16,
+            reserved: 0,
+            index,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::error::Error;
+use core::cmp::Ordering;
+
+/// The maximum length of the keys in bytes.
+pub const MAX_KEY_LEN: usize = 63;
+
+/// A key with its length, kept in a fixed size buffer.
+/// The keys are compared by their bytes, so the keys with the same prefix are next to each other.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct Key {
+    len: u8,
+    bytes: [u8; MAX_KEY_LEN],
+}
+
+impl Key {
+    /// It returns `OutOfRange` error if the key is longer than `MAX_KEY_LEN`.
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(Error::OutOfRange);
+        }
+        let mut bytes = [0; MAX_KEY_LEN];
+        bytes[..key.len()].copy_from_slice(key);
+        Ok(Key {
+            len: key.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+/// The location of a value in the heap.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Block {
+    pub offset: u32,
+    pub len: u32,
+}
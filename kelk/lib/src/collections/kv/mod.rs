@@ -0,0 +1,11 @@
+//! Storage Key-Value
+
+pub mod namespace;
+pub mod store;
+
+mod header;
+mod key;
+
+pub use key::MAX_KEY_LEN;
+pub use namespace::{Item, Map, Vector};
+pub use store::StorageKV;
@@ -0,0 +1,406 @@
+//! Namespaced collections, are typed collections that keep their values in a Storage Key-Value.
+//!
+//! Each collection has a namespace that is prepended to its keys, so several collections
+//! can share one store. The collections don't hold the store, so they can be defined as constants,
+//! for example `const BALANCES: Map<u64> = Map::new(b"balances");`.
+//! The collections with different names never share a key, but each collection should have
+//! its own name: for example an `Item` and the length of a `Vector` with the same name share a key.
+//! The values are encoded in CBOR.
+
+use super::store::{Prefix, StorageKV};
+use crate::error::Error;
+use crate::storage::{encode_cbor, ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::result::Result;
+use minicbor::{Decode, Encode};
+
+/// Returns the key of an entry inside a namespace: the length of the namespace,
+/// the namespace and the key. The length keeps the namespaces that are prefixes
+/// of each other apart.
+fn namespaced(namespace: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + namespace.len() + key.len());
+    buf.push(namespace.len() as u8);
+    buf.extend_from_slice(namespace);
+    buf.extend_from_slice(key);
+    buf
+}
+
+fn decode<T>(data: Option<Vec<u8>>) -> Result<Option<T>, Error>
+where
+    T: for<'b> Decode<'b>,
+{
+    match data {
+        Some(data) => minicbor::decode(&data)
+            .map(Some)
+            .map_err(|_| Error::InvalidCbor),
+        None => Ok(None),
+    }
+}
+
+/// `Item` is a single value that is kept under a key.
+/// The key is namespaced like the keys of a `Map`, so it doesn't collide with them.
+pub struct Item<T> {
+    key: &'static [u8],
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Item<T> {
+    /// defines a new item with the given key.
+    pub const fn new(key: &'static [u8]) -> Self {
+        Item {
+            key,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn key(&self) -> Vec<u8> {
+        namespaced(self.key, &[])
+    }
+
+    /// loads the value of the item. If the item is not saved, it returns None.
+    pub fn load<S>(&self, kv: &StorageKV<'_, S>) -> Result<Option<T>, Error>
+    where
+        T: for<'b> Decode<'b>,
+        S: ReadOnlyStorage + ?Sized,
+    {
+        decode(kv.get(&self.key())?)
+    }
+
+    /// saves the value of the item.
+    pub fn save<S>(&self, kv: &mut StorageKV<'_, S>, value: &T) -> Result<(), Error>
+    where
+        T: Encode,
+        S: Storage + ?Sized,
+    {
+        kv.set(&self.key(), &encode_cbor(value)?)
+    }
+
+    /// removes the item. It returns true if the item was saved.
+    pub fn remove<S>(&self, kv: &mut StorageKV<'_, S>) -> Result<bool, Error>
+    where
+        S: Storage + ?Sized,
+    {
+        kv.remove(&self.key())
+    }
+}
+
+/// `Map` keeps the values by their keys inside a namespace.
+pub struct Map<T> {
+    namespace: &'static [u8],
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Map<T> {
+    /// defines a new map with the given namespace.
+    pub const fn new(namespace: &'static [u8]) -> Self {
+        Map {
+            namespace,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// loads the value of the key. If the key doesn't exists, it returns None.
+    pub fn load<S>(&self, kv: &StorageKV<'_, S>, key: &[u8]) -> Result<Option<T>, Error>
+    where
+        T: for<'b> Decode<'b>,
+        S: ReadOnlyStorage + ?Sized,
+    {
+        decode(kv.get(&namespaced(self.namespace, key))?)
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key<S>(&self, kv: &StorageKV<'_, S>, key: &[u8]) -> Result<bool, Error>
+    where
+        S: ReadOnlyStorage + ?Sized,
+    {
+        kv.contains_key(&namespaced(self.namespace, key))
+    }
+
+    /// saves the value of the key.
+    pub fn save<S>(&self, kv: &mut StorageKV<'_, S>, key: &[u8], value: &T) -> Result<(), Error>
+    where
+        T: Encode,
+        S: Storage + ?Sized,
+    {
+        kv.set(&namespaced(self.namespace, key), &encode_cbor(value)?)
+    }
+
+    /// removes the key from the map. It returns true if the key was present in the map.
+    pub fn remove<S>(&self, kv: &mut StorageKV<'_, S>, key: &[u8]) -> Result<bool, Error>
+    where
+        S: Storage + ?Sized,
+    {
+        kv.remove(&namespaced(self.namespace, key))
+    }
+
+    /// Returns an iterator over the key-value pairs of the map, sorted by key.
+    pub fn iter<'b, 'a, S>(&self, kv: &'b StorageKV<'a, S>) -> Result<Entries<'b, 'a, T, S>, Error>
+    where
+        S: ReadOnlyStorage + ?Sized,
+    {
+        self.prefix(kv, &[])
+    }
+
+    /// Returns an iterator over the key-value pairs that their keys start with the prefix,
+    /// sorted by key.
+    pub fn prefix<'b, 'a, S>(
+        &self,
+        kv: &'b StorageKV<'a, S>,
+        prefix: &[u8],
+    ) -> Result<Entries<'b, 'a, T, S>, Error>
+    where
+        S: ReadOnlyStorage + ?Sized,
+    {
+        Ok(Entries {
+            entries: kv.prefix(&namespaced(self.namespace, prefix))?,
+            skip: 1 + self.namespace.len(),
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// An iterator over the key-value pairs of a `Map`, sorted by key.
+/// The keys are returned without the namespace.
+pub struct Entries<'b, 'a, T, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    entries: Prefix<'b, 'a, S>,
+    skip: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<'b, 'a, T, S> Iterator for Entries<'b, 'a, T, S>
+where
+    T: for<'c> Decode<'c>,
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<(Vec<u8>, T), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut key, data) = match self.entries.next()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        let value = match minicbor::decode(&data) {
+            Ok(value) => value,
+            Err(_) => return Some(Err(Error::InvalidCbor)),
+        };
+        key.drain(..self.skip);
+        Some(Ok((key, value)))
+    }
+}
+
+/// `Vector` is a list of values inside a namespace.
+/// The length is kept under the namespace, and each element is kept under its index.
+pub struct Vector<T> {
+    namespace: &'static [u8],
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Vector<T> {
+    /// defines a new vector with the given namespace.
+    pub const fn new(namespace: &'static [u8]) -> Self {
+        Vector {
+            namespace,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn key(&self, index: u32) -> Vec<u8> {
+        namespaced(self.namespace, &index.to_be_bytes())
+    }
+
+    /// Returns the number of elements in the vector.
+    pub fn len<S>(&self, kv: &StorageKV<'_, S>) -> Result<u32, Error>
+    where
+        S: ReadOnlyStorage + ?Sized,
+    {
+        Ok(decode(kv.get(&namespaced(self.namespace, &[]))?)?.unwrap_or(0))
+    }
+
+    /// Returns true if the vector contains no elements.
+    pub fn is_empty<S>(&self, kv: &StorageKV<'_, S>) -> Result<bool, Error>
+    where
+        S: ReadOnlyStorage + ?Sized,
+    {
+        Ok(self.len(kv)? == 0)
+    }
+
+    /// Returns the element at the given index.
+    /// If the index is out of bounds, it returns None.
+    pub fn get<S>(&self, kv: &StorageKV<'_, S>, index: u32) -> Result<Option<T>, Error>
+    where
+        T: for<'b> Decode<'b>,
+        S: ReadOnlyStorage + ?Sized,
+    {
+        if index >= self.len(kv)? {
+            return Ok(None);
+        }
+        decode(kv.get(&self.key(index))?)
+    }
+
+    fn set_len<S>(&self, kv: &mut StorageKV<'_, S>, len: u32) -> Result<(), Error>
+    where
+        S: Storage + ?Sized,
+    {
+        kv.set(&namespaced(self.namespace, &[]), &encode_cbor(&len)?)
+    }
+
+    /// Replaces the element at the given index.
+    /// It returns `OutOfRange` error if the index is out of bounds.
+    pub fn set<S>(&self, kv: &mut StorageKV<'_, S>, index: u32, value: &T) -> Result<(), Error>
+    where
+        T: Encode,
+        S: Storage + ?Sized,
+    {
+        if index >= self.len(kv)? {
+            return Err(Error::OutOfRange);
+        }
+        kv.set(&self.key(index), &encode_cbor(value)?)
+    }
+
+    /// Appends an element to the back of the vector.
+    pub fn push<S>(&self, kv: &mut StorageKV<'_, S>, value: &T) -> Result<(), Error>
+    where
+        T: Encode,
+        S: Storage + ?Sized,
+    {
+        let len = self.len(kv)?;
+        if len == u32::MAX {
+            return Err(Error::OutOfCapacity);
+        }
+        // The element is written before the length, so a failed push doesn't change the vector.
+        kv.set(&self.key(len), &encode_cbor(value)?)?;
+        self.set_len(kv, len + 1)
+    }
+
+    /// Removes the last element from the vector and returns it, or None if it is empty.
+    pub fn pop<S>(&self, kv: &mut StorageKV<'_, S>) -> Result<Option<T>, Error>
+    where
+        T: for<'b> Decode<'b>,
+        S: Storage + ?Sized,
+    {
+        let len = self.len(kv)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let value = decode(kv.get(&self.key(len - 1))?)?;
+        self.set_len(kv, len - 1)?;
+        kv.remove(&self.key(len - 1))?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_storage;
+
+    const CONFIG: Item<u32> = Item::new(b"config");
+    const BALANCES: Map<u64> = Map::new(b"balances");
+    const BALANCE_LOG: Map<u64> = Map::new(b"balance");
+    const ORDERS: Vector<u32> = Vector::new(b"orders");
+
+    #[test]
+    fn test_item() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 1024, 8).unwrap();
+
+        assert_eq!(None, CONFIG.load(&kv).unwrap());
+        CONFIG.save(&mut kv, &7).unwrap();
+        assert_eq!(Some(7), CONFIG.load(&kv).unwrap());
+        CONFIG.save(&mut kv, &1_000_000).unwrap();
+        assert_eq!(Some(1_000_000), CONFIG.load(&kv).unwrap());
+        assert!(CONFIG.remove(&mut kv).unwrap());
+        assert_eq!(None, CONFIG.load(&kv).unwrap());
+
+        // A value with another type can't be decoded
+        kv.set(b"\x06config", b"\x60").unwrap();
+        assert!(matches!(CONFIG.load(&kv), Err(Error::InvalidCbor)));
+    }
+
+    #[test]
+    fn test_map() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 1024, 8).unwrap();
+
+        BALANCES.save(&mut kv, b"bob", &20).unwrap();
+        BALANCES.save(&mut kv, b"alice", &10).unwrap();
+        BALANCE_LOG.save(&mut kv, b"s", &1).unwrap();
+        CONFIG.save(&mut kv, &3).unwrap();
+
+        assert_eq!(Some(10), BALANCES.load(&kv, b"alice").unwrap());
+        assert_eq!(None, BALANCES.load(&kv, b"carol").unwrap());
+        assert!(BALANCES.contains_key(&kv, b"bob").unwrap());
+        // "balances" starts with "balance", but the namespaces don't overlap.
+        assert!(!BALANCE_LOG.contains_key(&kv, b"sbob").unwrap());
+
+        let entries: Vec<(Vec<u8>, u64)> =
+            BALANCES.iter(&kv).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, [(b"alice".to_vec(), 10), (b"bob".to_vec(), 20)]);
+        let entries: Vec<(Vec<u8>, u64)> =
+            BALANCE_LOG.iter(&kv).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, [(b"s".to_vec(), 1)]);
+        let entries: Vec<(Vec<u8>, u64)> = BALANCES
+            .prefix(&kv, b"b")
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(entries, [(b"bob".to_vec(), 20)]);
+
+        assert!(BALANCES.remove(&mut kv, b"bob").unwrap());
+        assert!(!BALANCES.remove(&mut kv, b"bob").unwrap());
+        assert_eq!(BALANCES.iter(&kv).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 1024, 8).unwrap();
+
+        // The item key is the length of the namespace "a" followed by "a" and "b"
+        const ITEM: Item<u32> = Item::new(b"\x01ab");
+        const MAP: Map<u32> = Map::new(b"a");
+        ITEM.save(&mut kv, &1).unwrap();
+        MAP.save(&mut kv, b"b", &2).unwrap();
+        assert_eq!(Some(1), ITEM.load(&kv).unwrap());
+        assert_eq!(Some(2), MAP.load(&kv, b"b").unwrap());
+
+        // An item that its name starts with the name of a vector
+        const ORDERS_ITEM: Item<u32> = Item::new(b"orders\x00\x00\x00\x00");
+        ORDERS.push(&mut kv, &3).unwrap();
+        ORDERS_ITEM.save(&mut kv, &4).unwrap();
+        CONFIG.save(&mut kv, &5).unwrap();
+        assert_eq!(ORDERS.len(&kv).unwrap(), 1);
+        assert_eq!(Some(3), ORDERS.get(&kv, 0).unwrap());
+        assert_eq!(Some(4), ORDERS_ITEM.load(&kv).unwrap());
+        assert_eq!(kv.len(), 6);
+    }
+
+    #[test]
+    fn test_vector() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 1024, 8).unwrap();
+
+        assert!(ORDERS.is_empty(&kv).unwrap());
+        assert_eq!(None, ORDERS.pop(&mut kv).unwrap());
+        for value in 0..5 {
+            ORDERS.push(&mut kv, &(value * 10)).unwrap();
+        }
+        assert_eq!(ORDERS.len(&kv).unwrap(), 5);
+        assert_eq!(Some(20), ORDERS.get(&kv, 2).unwrap());
+        assert_eq!(None, ORDERS.get(&kv, 5).unwrap());
+
+        ORDERS.set(&mut kv, 2, &25).unwrap();
+        assert_eq!(Some(25), ORDERS.get(&kv, 2).unwrap());
+        assert!(matches!(ORDERS.set(&mut kv, 5, &1), Err(Error::OutOfRange)));
+
+        assert_eq!(Some(40), ORDERS.pop(&mut kv).unwrap());
+        assert_eq!(ORDERS.len(&kv).unwrap(), 4);
+        assert_eq!(None, ORDERS.get(&kv, 4).unwrap());
+        // The length and the elements
+        assert_eq!(kv.len(), 5);
+    }
+}
@@ -0,0 +1,584 @@
+//! Storage Key-Value, is a key-value store on top of the flat storage file of the contract.
+//!
+//! The keys are byte strings of up to `MAX_KEY_LEN` bytes, kept sorted in a Storage B+Tree,
+//! so the keys with the same prefix can be iterated together.
+//! The values are byte strings of any length, kept in a Storage Heap after the header.
+//! The heap reuses the blocks of the removed values, and it can be compacted to put
+//! all the free space together.
+//! The store only uses `salloc`, `sfree`, `compact` and `moving` of the heap, so the heap
+//! can be replaced without changing the rest of the store. Until the first release, the layout
+//! of the store and its heap is not stable, and a store that is created by an earlier version
+//! may not load.
+//!
+//! Changing the length of a value writes the new value into a new block and then points
+//! the key to it, so a failed write never leaves a key with a partially written value.
+//!
+//! If a compaction fails after moving a value and before pointing its key to it,
+//! the key still points to the old block, which is free. The reads follow the move
+//! that is recorded in the heap, and the key is updated before the next change.
+
+use super::header::Header;
+use super::key::{Block, Key, MAX_KEY_LEN};
+use crate::collections::btree::tree::Iter;
+use crate::collections::btree::StorageBTree;
+use crate::error::Error;
+use crate::heap::StorageHeap;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::Bound;
+use core::result::Result;
+
+/// The instance of Storage Key-Value.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageKV<'a, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    heap: StorageHeap<'a, S>,
+    index: StorageBTree<'a, Key, Block, S>,
+}
+
+impl<'a, S> StorageKV<'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Key-Value
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.key_len != MAX_KEY_LEN as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        let heap = StorageHeap::lazy_load(storage, offset + size_of::<Header>() as u32)?;
+        let index = StorageBTree::lazy_load(storage, header.index)?;
+
+        Ok(StorageKV {
+            storage,
+            heap,
+            index,
+        })
+    }
+
+    /// Returns the number of the keys in the store.
+    pub fn len(&self) -> u32 {
+        self.index.len()
+    }
+
+    /// Returns true if the store contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
+    /// It returns `OutOfRange` error if the key is longer than `MAX_KEY_LEN`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match self.index.find(&Key::new(key)?)? {
+            Some(block) => {
+                let offset = moved(self.heap.moving()?, block.offset);
+                Ok(Some(self.storage.sread(offset, block.len)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if the store contains a value for the specified key.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, Error> {
+        self.index.contains_key(&Key::new(key)?)
+    }
+
+    /// Returns an iterator over the key-value pairs of the store, sorted by key.
+    pub fn iter(&self) -> Result<Prefix<'_, 'a, S>, Error> {
+        Ok(Prefix {
+            storage: self.storage,
+            prefix: Key::new(&[]).unwrap(),
+            entries: self.index.iter(),
+            moving: self.heap.moving()?,
+            done: false,
+        })
+    }
+
+    /// Returns an iterator over the key-value pairs that their keys start with the prefix,
+    /// sorted by key.
+    pub fn prefix(&self, prefix: &[u8]) -> Result<Prefix<'_, 'a, S>, Error> {
+        let prefix = Key::new(prefix)?;
+        Ok(Prefix {
+            storage: self.storage,
+            prefix,
+            entries: self
+                .index
+                .range((Bound::Included(prefix), Bound::Unbounded)),
+            moving: self.heap.moving()?,
+            done: false,
+        })
+    }
+}
+
+impl<'a, S> StorageKV<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Key-Value at the given offset.
+    /// The heap of `heap_size` bytes comes after the header, and it is followed by
+    /// a Storage B+Tree with `capacity` pages of `page_size` bytes for the keys.
+    pub fn create(
+        storage: &'a S,
+        offset: u32,
+        heap_size: u32,
+        page_size: u32,
+        capacity: u32,
+    ) -> Result<Self, Error> {
+        let heap = StorageHeap::create(storage, offset + size_of::<Header>() as u32, heap_size)?;
+        let index = StorageBTree::create(storage, heap.end(), page_size, capacity)?;
+        swrite_struct(storage, offset, &Header::new(heap.end()))?;
+
+        Ok(StorageKV {
+            storage,
+            heap,
+            index,
+        })
+    }
+
+    /// Sets the value of the key.
+    /// It returns `OutOfRange` error if the key is longer than `MAX_KEY_LEN`.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let key = Key::new(key)?;
+        let len = u32::try_from(value.len()).map_err(|_| Error::OutOfRange)?;
+        self.repoint()?;
+        let old_block = self.index.find(&key)?;
+
+        // A value with the same length is replaced with one write.
+        if let Some(block) = old_block {
+            if block.len == len {
+                self.storage.swrite(block.offset, value)?;
+                return Ok(());
+            }
+        }
+
+        // The block is taken from the heap before writing into it. If the next writes fail,
+        // the block is freed. It is the best effort: if freeing fails too, the block is lost,
+        // but it is never shared with another key.
+        let offset = self.heap.salloc(len)?;
+        let result = match self.storage.swrite(offset, value) {
+            Ok(()) => self.index.insert(key, Block { offset, len }),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            let _ = self.heap.sfree(offset);
+            return Err(err);
+        }
+
+        if let Some(block) = old_block {
+            self.heap.sfree(block.offset)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the key from the store.
+    /// It returns true if the key was present in the store.
+    pub fn remove(&mut self, key: &[u8]) -> Result<bool, Error> {
+        self.repoint()?;
+        match self.index.remove(&Key::new(key)?)? {
+            Some(block) => {
+                self.heap.sfree(block.offset)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Moves the values to the start of the heap, so all the free space of the heap is together.
+    /// The keys are updated to point to the moved values.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.repoint()?;
+        let mut keys = BTreeMap::new();
+        for entry in self.index.iter() {
+            let (key, block) = entry?;
+            keys.insert(block.offset, (key, block.len));
+        }
+
+        let index = &mut self.index;
+        self.heap.compact(|old, new| {
+            // The blocks that are lost by the failed writes have no key.
+            if let Some((key, len)) = keys.get(&old) {
+                index.insert(
+                    *key,
+                    Block {
+                        offset: new,
+                        len: *len,
+                    },
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Points the key of the value that was moved by a failed compaction to its new offset.
+    /// It should be called before changing the heap, because the old block is free.
+    fn repoint(&mut self) -> Result<(), Error> {
+        let Some((old, new)) = self.heap.moving()? else {
+            return Ok(());
+        };
+        let mut moved = None;
+        for entry in self.index.iter() {
+            let (key, block) = entry?;
+            if block.offset == old {
+                moved = Some((key, block.len));
+                break;
+            }
+        }
+        if let Some((key, len)) = moved {
+            self.index.insert(key, Block { offset: new, len })?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the offset of the value, following the move of a failed compaction.
+fn moved(moving: Option<(u32, u32)>, offset: u32) -> u32 {
+    match moving {
+        Some((old, new)) if old == offset => new,
+        _ => offset,
+    }
+}
+
+/// An iterator over the key-value pairs of a Storage Key-Value that their keys
+/// start with a prefix, sorted by key.
+/// It reads the value of each key when the key is visited.
+pub struct Prefix<'b, 'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    prefix: Key,
+    entries: Iter<'b, 'a, Key, Block, S>,
+    moving: Option<(u32, u32)>,
+    done: bool,
+}
+
+impl<'b, 'a, S> Iterator for Prefix<'b, 'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (key, block) = match self.entries.next()? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        if !key.as_bytes().starts_with(self.prefix.as_bytes()) {
+            // The keys after this one don't start with the prefix either.
+            self.done = true;
+            return None;
+        }
+        match self
+            .storage
+            .sread(moved(self.moving, block.offset), block.len)
+        {
+            Ok(value) => Some(Ok((key.as_bytes().to_vec(), value))),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault, MockStorage};
+
+    fn collect<S: ReadOnlyStorage + ?Sized>(iter: Prefix<'_, '_, S>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        iter.map(|entry| entry.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_set_get() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 512, 16).unwrap();
+        assert!(kv.is_empty());
+
+        kv.set(b"foo", b"bar").unwrap();
+        kv.set(b"", b"empty key").unwrap();
+        kv.set(b"empty", b"").unwrap();
+        assert_eq!(kv.len(), 3);
+        assert_eq!(Some(b"bar".to_vec()), kv.get(b"foo").unwrap());
+        assert_eq!(Some(b"empty key".to_vec()), kv.get(b"").unwrap());
+        assert_eq!(Some(b"".to_vec()), kv.get(b"empty").unwrap());
+        assert_eq!(None, kv.get(b"fo").unwrap());
+        assert!(kv.contains_key(b"foo").unwrap());
+        assert!(!kv.contains_key(b"foo2").unwrap());
+
+        // Changing the value with the same length and with another length
+        kv.set(b"foo", b"baz").unwrap();
+        assert_eq!(Some(b"baz".to_vec()), kv.get(b"foo").unwrap());
+        kv.set(b"foo", &[7; 100]).unwrap();
+        assert_eq!(Some([7; 100].to_vec()), kv.get(b"foo").unwrap());
+        assert_eq!(kv.len(), 3);
+    }
+
+    #[test]
+    fn test_key_len() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 512, 16).unwrap();
+
+        let key = [1; MAX_KEY_LEN];
+        kv.set(&key, b"max").unwrap();
+        assert_eq!(Some(b"max".to_vec()), kv.get(&key).unwrap());
+
+        let key = [1; MAX_KEY_LEN + 1];
+        assert!(matches!(kv.set(&key, b"long"), Err(Error::OutOfRange)));
+        assert!(matches!(kv.get(&key), Err(Error::OutOfRange)));
+        assert!(matches!(kv.prefix(&key), Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn test_remove() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 512, 16).unwrap();
+        kv.set(b"a", b"1").unwrap();
+        kv.set(b"b", b"2").unwrap();
+
+        assert!(kv.remove(b"a").unwrap());
+        assert!(!kv.remove(b"a").unwrap());
+        assert_eq!(None, kv.get(b"a").unwrap());
+        assert_eq!(Some(b"2".to_vec()), kv.get(b"b").unwrap());
+        assert_eq!(kv.len(), 1);
+    }
+
+    #[test]
+    fn test_heap_reuse() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 64, 512, 16).unwrap();
+
+        // The heap has room for two blocks of 32 bytes
+        kv.set(b"a", &[1; 20]).unwrap();
+        kv.set(b"b", &[2; 24]).unwrap();
+        assert!(matches!(kv.set(b"c", &[3; 17]), Err(Error::OutOfCapacity)));
+
+        // The removed and the replaced blocks are reused
+        kv.remove(b"a").unwrap();
+        kv.set(b"c", &[3; 17]).unwrap();
+        assert_eq!(Some([3; 17].to_vec()), kv.get(b"c").unwrap());
+        kv.remove(b"c").unwrap();
+        kv.set(b"b", &[4; 20]).unwrap();
+        kv.set(b"d", &[5; 24]).unwrap();
+        assert_eq!(Some([4; 20].to_vec()), kv.get(b"b").unwrap());
+        assert_eq!(Some([5; 24].to_vec()), kv.get(b"d").unwrap());
+        assert!(matches!(kv.set(b"e", &[6; 1]), Err(Error::OutOfCapacity)));
+    }
+
+    #[test]
+    fn test_compact() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 1024, 512, 16).unwrap();
+        for i in 0..10u8 {
+            kv.set(&[b'k', i], &[i; 40]).unwrap();
+        }
+        for i in [1, 3, 5, 7] {
+            kv.remove(&[b'k', i]).unwrap();
+        }
+
+        // The free space is broken into small pieces
+        assert!(matches!(
+            kv.set(b"big", &[9; 600]),
+            Err(Error::OutOfCapacity)
+        ));
+        kv.compact().unwrap();
+        kv.set(b"big", &[9; 600]).unwrap();
+
+        assert_eq!(kv.len(), 7);
+        for i in [0, 2, 4, 6, 8, 9] {
+            assert_eq!(Some([i; 40].to_vec()), kv.get(&[b'k', i]).unwrap());
+        }
+        assert_eq!(Some([9; 600].to_vec()), kv.get(b"big").unwrap());
+
+        let kv = StorageKV::lazy_load(&storage, 0).unwrap();
+        assert_eq!(Some([4; 40].to_vec()), kv.get(&[b'k', 4]).unwrap());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let storage = mock_storage(64 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 16 * 1024, 512, 32).unwrap();
+        for i in 0..100u8 {
+            kv.set(&[b'k', i / 10, i % 10], &[i]).unwrap();
+        }
+        kv.set(b"a", b"before").unwrap();
+        kv.set(b"z", b"after").unwrap();
+
+        let entries = collect(kv.prefix(&[b'k', 4]).unwrap());
+        assert_eq!(entries.len(), 10);
+        for (i, (key, value)) in entries.into_iter().enumerate() {
+            assert_eq!(key, [b'k', 4, i as u8]);
+            assert_eq!(value, [40 + i as u8]);
+        }
+
+        assert_eq!(collect(kv.prefix(b"k").unwrap()).len(), 100);
+        assert_eq!(collect(kv.prefix(b"z").unwrap()).len(), 1);
+        assert!(collect(kv.prefix(b"b").unwrap()).is_empty());
+        assert!(collect(kv.prefix(b"zz").unwrap()).is_empty());
+
+        let all = collect(kv.iter().unwrap());
+        assert_eq!(all.len(), 102);
+        assert_eq!(all[0], (b"a".to_vec(), b"before".to_vec()));
+        assert_eq!(all[101], (b"z".to_vec(), b"after".to_vec()));
+    }
+
+    #[test]
+    fn test_failed_set() {
+        // Failing each write of allocating, writing the new value,
+        // updating the index and freeing the old block, until the set doesn't fail
+        for nth in 1.. {
+            let storage = mock_storage(16 * 1024);
+            let mut kv = StorageKV::create(&storage, 0, 4096, 512, 16).unwrap();
+            kv.set(b"key", b"old").unwrap();
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::StorageFull,
+            });
+            let result = kv.set(b"key", b"new value");
+            storage.clear_faults();
+
+            let mut kv = StorageKV::lazy_load(&storage, 0).unwrap();
+            let value = kv.get(b"key").unwrap().unwrap();
+            assert!(value == b"old" || value == b"new value");
+
+            kv.set(b"key", b"another value").unwrap();
+            kv.set(b"other", b"value").unwrap();
+            assert_eq!(Some(b"another value".to_vec()), kv.get(b"key").unwrap());
+            if result.is_ok() {
+                assert!(nth > 8);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_index() {
+        // An index with one leaf page
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 2048, 512, 1).unwrap();
+        let mut count = 0u8;
+        while kv.set(&[b'k', count], &[count; 20]).is_ok() {
+            count += 1;
+        }
+        let used = kv.heap.used();
+
+        // The blocks of the failed sets are freed
+        for i in 0..20 {
+            assert!(matches!(
+                kv.set(&[b'n', i], &[i; 100]),
+                Err(Error::OutOfCapacity)
+            ));
+        }
+        assert!(kv.heap.used() <= used + 128);
+        kv.remove(&[b'k', 0]).unwrap();
+        kv.set(b"new", &[1; 150]).unwrap();
+        assert_eq!(Some([1; 150].to_vec()), kv.get(b"new").unwrap());
+        assert_eq!(kv.len(), count as u32);
+    }
+
+    #[test]
+    fn test_failed_alloc() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 0, 4096, 512, 16).unwrap();
+        kv.set(b"a", b"1").unwrap();
+        kv.remove(b"a").unwrap();
+
+        // Failing to take the free block from the heap
+        storage.inject(Fault::NthWrite {
+            n: storage.write_count() + 1,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(matches!(
+            kv.set(b"b", b"2"),
+            Err(Error::HostError(HostErrorKind::StorageFull))
+        ));
+        storage.clear_faults();
+        assert_eq!(None, kv.get(b"b").unwrap());
+
+        kv.set(b"b", b"2").unwrap();
+        kv.set(b"c", b"3").unwrap();
+        assert_eq!(Some(b"2".to_vec()), kv.get(b"b").unwrap());
+        assert_eq!(Some(b"3".to_vec()), kv.get(b"c").unwrap());
+    }
+
+    #[test]
+    fn test_failed_compact() {
+        // Failing each write of the compaction, including the writes of the keys
+        // that point to the moved values, until the compaction doesn't fail
+        for nth in 1.. {
+            let storage = mock_storage(16 * 1024);
+            let mut kv = StorageKV::create(&storage, 0, 1024, 512, 16).unwrap();
+            for i in 0..6u8 {
+                kv.set(&[b'k', i], &[i; 20]).unwrap();
+            }
+            kv.remove(&[b'k', 1]).unwrap();
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::StorageFull,
+            });
+            let result = kv.compact();
+            storage.clear_faults();
+
+            let check = |kv: &StorageKV<'_, MockStorage>| {
+                for i in [0, 2, 3, 4, 5] {
+                    assert_eq!(Some([i; 20].to_vec()), kv.get(&[b'k', i]).unwrap());
+                }
+                let values: Vec<_> = collect(kv.iter().unwrap())
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+                assert_eq!(values, [[0; 20], [2; 20], [3; 20], [4; 20], [5; 20]]);
+            };
+
+            // The store that has seen the failure, and the store that is loaded after it
+            let snapshot = storage.snapshot();
+            let loaded = StorageKV::lazy_load(&snapshot, 0).unwrap();
+            for mut kv in [kv, loaded] {
+                check(&kv);
+
+                // The new values don't take the blocks of the moved values
+                kv.set(b"a", &[6; 20]).unwrap();
+                kv.set(b"b", &[7; 20]).unwrap();
+                kv.remove(b"a").unwrap();
+                kv.remove(b"b").unwrap();
+                check(&kv);
+                kv.compact().unwrap();
+                check(&kv);
+            }
+
+            if result.is_ok() {
+                assert!(nth > 4);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(16 * 1024);
+        let mut kv = StorageKV::create(&storage, 128, 4096, 512, 16).unwrap();
+        kv.set(b"a", b"1").unwrap();
+        kv.set(b"b", b"2").unwrap();
+        kv.remove(b"a").unwrap();
+
+        let mut kv = StorageKV::lazy_load(&storage, 128).unwrap();
+        assert_eq!(kv.len(), 1);
+        assert_eq!(Some(b"2".to_vec()), kv.get(b"b").unwrap());
+        // The free block is reused after loading
+        kv.set(b"c", b"3").unwrap();
+        assert_eq!(Some(b"3".to_vec()), kv.get(b"c").unwrap());
+
+        assert!(StorageKV::lazy_load(&storage, 0).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use crate::hash::{Hash, EMPTY_HASH};
+use core::mem::size_of;
+
+#[repr(C)]
+pub(super) struct Header {
+    pub boom: u32,
+    pub key_len: u16,
+    pub value_len: u16,
+    pub size: u32,
+    pub capacity: u32,
+    pub root_hash: Hash,
+    /// It is set while the nodes are rehashed, and the hashes are recomputed if it is left set.
+    pub dirty: u32,
+}
+
+impl Header {
+    pub fn new<K: Sized, V: Sized>(capacity: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            key_len: size_of::<K>() as u16,
+            value_len: size_of::<V>() as u16,
+            size: 0,
+            capacity,
+            root_hash: EMPTY_HASH,
+            dirty: 0,
+        }
+    }
+}
@@ -0,0 +1,828 @@
+//! Storage Merkle Map, is a binary search tree that authenticates its keys and values
+//! with a Merkle root hash, which is kept in the header.
+//!
+//! The hash of each node covers its key, its value and the hashes of its children.
+//! Inserting or removing a key rehashes the nodes on its path up to the root,
+//! so the root hash changes with any change of the map.
+//! The map can produce the inclusion or exclusion proof of a key, see `Proof`.
+//!
+//! The keys and the values are hashed as they are kept in the storage,
+//! therefore they should not have padding bytes, see `NoPadding`.
+//!
+//! The header is marked dirty before the nodes of a path are rehashed, and the new root hash
+//! clears it. If a change fails in between, some nodes may keep the old hashes of their children,
+//! so the next change recomputes the hashes of the whole tree before changing it.
+//! Until then, the proofs may not verify against the root hash.
+//!
+//! The nodes are ordered by the hashes of their keys, so the tree is balanced on average,
+//! even if the keys are inserted in order, like increasing IDs.
+//! The tree is not rebalanced, and the keys that are chosen to have ordered hashes
+//! can still make a long path. Therefore the depth of the tree is limited to `MAX_DEPTH`,
+//! and inserting a key deeper than that fails with `OutOfCapacity` error.
+
+use super::header::Header;
+use super::node::{cmp_keys, key_hash, Node};
+use super::proof::{Proof, ProofNode};
+use crate::error::Error;
+use crate::hash::{Hash, Hasher, NoPadding, EMPTY_HASH};
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{self, size_of};
+use core::result::Result;
+
+/// The maximum depth of the tree, which bounds the cost of reading a path.
+/// The average depth of a tree with `n` random keys is about `2 * ln(n)`.
+pub const MAX_DEPTH: usize = 128;
+
+/// The nodes from the top to the bottom of a path, with their offsets,
+/// and true if the next node on the path is the left child.
+type Path<K, V> = Vec<(u32, Node<K, V>, bool)>;
+
+/// The instance of Storage Merkle Map.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageMerkleMap<'a, K, V, H, S = dyn Storage + 'a>
+where
+    K: Sized + Ord + NoPadding,
+    V: Sized + NoPadding,
+    H: Hasher + ?Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    hasher: &'a H,
+    offset: u32,
+    header: Header,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, H, S> StorageMerkleMap<'a, K, V, H, S>
+where
+    K: Sized + Ord + NoPadding,
+    V: Sized + NoPadding,
+    H: Hasher + ?Sized,
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Merkle Map
+    pub fn lazy_load(storage: &'a S, hasher: &'a H, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.key_len != size_of::<K>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        if header.value_len != size_of::<V>() as u16 {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        Ok(StorageMerkleMap {
+            storage,
+            hasher,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> u32 {
+        self.header.size
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the root hash of the map.
+    /// The root hash of an empty map is `EMPTY_HASH`.
+    pub fn root_hash(&self) -> &Hash {
+        &self.header.root_hash
+    }
+
+    /// Returns the value corresponding to the key. If the key doesn't exists, it returns None.
+    pub fn find(&self, key: &K) -> Result<Option<V>, Error> {
+        let node = self.path_to(&key_hash(self.hasher, key), key)?.pop();
+        let node = node.map(|(_, node, _)| node);
+        Ok(node.filter(|node| node.key.eq(key)).map(|node| node.value))
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        Ok(self.find(key)?.is_some())
+    }
+
+    /// Returns the proof of the key, which shows that the key is in the map with its value,
+    /// or that the key is not in the map.
+    pub fn prove(&self, key: &K) -> Result<Proof<K, V>, Error> {
+        let path = self
+            .path_to(&key_hash(self.hasher, key), key)?
+            .into_iter()
+            .map(|(_, node, _)| ProofNode {
+                key: node.key,
+                value: node.value,
+                left_hash: node.left_hash,
+                right_hash: node.right_hash,
+            })
+            .collect();
+        Ok(Proof { path })
+    }
+
+    fn node_offset(&self, index: u32) -> u32 {
+        self.offset + size_of::<Header>() as u32 + index * size_of::<Node<K, V>>() as u32
+    }
+
+    /// Returns the path from the root to the key,
+    /// or to the node that has no child for the key.
+    fn path_to(&self, key_hash: &Hash, key: &K) -> Result<Path<K, V>, Error> {
+        let mut path = Vec::new();
+        if self.header.size == 0 {
+            return Ok(path);
+        }
+
+        let mut offset = self.node_offset(0);
+        loop {
+            let node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+            // The greater keys are kept in the left subtree.
+            let ordering = cmp_keys(&node.key_hash, &node.key, key_hash, key);
+            let is_left = ordering.is_lt();
+            let next = match ordering.is_eq() {
+                true => 0,
+                false if is_left => node.left,
+                false => node.right,
+            };
+            path.push((offset, node, is_left));
+            if next == 0 {
+                return Ok(path);
+            }
+            offset = next;
+        }
+    }
+
+    /// Returns the offset of the parent of the node at `offset` and
+    /// true if the node is the left child of the parent.
+    /// The `key` is the key of the node and the node should not be the root.
+    fn find_parent(&self, key_hash: &Hash, key: &K, offset: u32) -> Result<(u32, bool), Error> {
+        let mut parent_offset = self.node_offset(0);
+        loop {
+            let parent = sread_struct::<Node<K, V>>(self.storage, parent_offset)?;
+            let ordering = cmp_keys(&parent.key_hash, &parent.key, key_hash, key);
+            let (next, is_left) = match ordering.is_lt() {
+                true => (parent.left, true),
+                false => (parent.right, false),
+            };
+            if next == offset {
+                return Ok((parent_offset, is_left));
+            }
+            if next == 0 {
+                return Err(Error::NotFound);
+            }
+            parent_offset = next;
+        }
+    }
+}
+
+impl<'a, K, V, H, S> StorageMerkleMap<'a, K, V, H, S>
+where
+    K: Sized + Ord + NoPadding,
+    V: Sized + NoPadding,
+    H: Hasher + ?Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Merkle Map at the given offset
+    pub fn create(
+        storage: &'a S,
+        hasher: &'a H,
+        offset: u32,
+        capacity: u32,
+    ) -> Result<Self, Error> {
+        let header = Header::new::<K, V>(capacity);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageMerkleMap {
+            storage,
+            hasher,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Inserts a key-value pair into the map and updates the root hash.
+    /// If the map did not have this key present, None is returned.
+    /// If the map did have this key present, the value is updated, and the old value is returned.
+    /// It returns `OutOfCapacity` error if the map is full or the key is deeper than `MAX_DEPTH`.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Error> {
+        self.repair()?;
+        let hash = key_hash(self.hasher, &key);
+        let mut path = self.path_to(&hash, &key)?;
+        let mut size = self.header.size;
+        let path_len = path.len();
+
+        let old_value = match path.last_mut() {
+            Some((_, node, _)) if node.key.eq(&key) => {
+                let old_value = mem::replace(&mut node.value, value);
+                self.write_header(size, self.header.root_hash, 1)?;
+                Some(old_value)
+            }
+            last => {
+                if size >= self.header.capacity || path_len >= MAX_DEPTH {
+                    return Err(Error::OutOfCapacity);
+                }
+                // The new node and the size are written before any node links to it,
+                // so a failed write never leaves a link to a slot after the last node.
+                let offset = self.node_offset(size);
+                let node = Node::new(hash, key, value);
+                swrite_struct(self.storage, offset, &node)?;
+                size += 1;
+                self.write_header(size, self.header.root_hash, 1)?;
+
+                match last {
+                    Some((_, parent, is_left)) => {
+                        let hash = node.hash(self.hasher);
+                        match is_left {
+                            true => (parent.left, parent.left_hash) = (offset, hash),
+                            false => (parent.right, parent.right_hash) = (offset, hash),
+                        }
+                    }
+                    // The new node is the root
+                    None => path.push((offset, node, false)),
+                }
+                None
+            }
+        };
+
+        let root_hash = self.rehash(&mut path)?;
+        self.write_header(size, root_hash, 0)?;
+        Ok(old_value)
+    }
+
+    /// Removes the key from the map and updates the root hash.
+    /// It returns the value of the key, or None if the key doesn't exist.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
+        self.repair()?;
+        let mut path = self.path_to(&key_hash(self.hasher, key), key)?;
+        let (offset, mut node, _) = match path.pop() {
+            Some((offset, node, is_left)) if node.key.eq(key) => (offset, node, is_left),
+            _ => return Ok(None),
+        };
+
+        let (value, free_offset) = if node.left != 0 && node.right != 0 {
+            // The node has two children. It is replaced by the greatest key
+            // of its right subtree, which has no left child.
+            let mut subpath: Path<K, V> = Vec::new();
+            let mut max_offset = node.right;
+            let mut max = sread_struct::<Node<K, V>>(self.storage, max_offset)?;
+            while max.left != 0 {
+                let next = max.left;
+                subpath.push((max_offset, max, true));
+                max_offset = next;
+                max = sread_struct::<Node<K, V>>(self.storage, max_offset)?;
+            }
+
+            match subpath.last_mut() {
+                Some((_, parent, _)) => {
+                    (parent.left, parent.left_hash) = (max.right, max.right_hash);
+                }
+                None => {
+                    (node.right, node.right_hash) = (max.right, max.right_hash);
+                }
+            }
+            node.key = max.key;
+            node.key_hash = max.key_hash;
+            let value = mem::replace(&mut node.value, max.value);
+
+            path.push((offset, node, false));
+            path.append(&mut subpath);
+            (value, max_offset)
+        } else {
+            let (child, child_hash) = match node.left {
+                0 => (node.right, node.right_hash),
+                left => (left, node.left_hash),
+            };
+            match path.last_mut() {
+                Some((_, parent, is_left)) => {
+                    match is_left {
+                        true => (parent.left, parent.left_hash) = (child, child_hash),
+                        false => (parent.right, parent.right_hash) = (child, child_hash),
+                    }
+                    (node.value, offset)
+                }
+                // The root should stay at its place, so its only child is moved to the root.
+                None if child != 0 => {
+                    let child_node = sread_struct::<Node<K, V>>(self.storage, child)?;
+                    path.push((offset, child_node, false));
+                    (node.value, child)
+                }
+                None => (node.value, offset),
+            }
+        };
+
+        self.write_header(self.header.size, self.header.root_hash, 1)?;
+        let root_hash = self.rehash(&mut path)?;
+        let size = self.release_node(free_offset)?;
+        self.write_header(size, root_hash, 0)?;
+        Ok(Some(value))
+    }
+
+    /// writes the nodes of the path from the bottom up,
+    /// and updates the hash of the child on the path in each node.
+    /// It returns the hash of the top node, or `EMPTY_HASH` if the path is empty.
+    fn rehash(&self, path: &mut Path<K, V>) -> Result<Hash, Error> {
+        let mut child_hash = None;
+        for (offset, node, is_left) in path.iter_mut().rev() {
+            if let Some(hash) = child_hash {
+                match is_left {
+                    true => node.left_hash = hash,
+                    false => node.right_hash = hash,
+                }
+            }
+            swrite_struct(self.storage, *offset, node)?;
+            child_hash = Some(node.hash(self.hasher));
+        }
+        Ok(child_hash.unwrap_or(EMPTY_HASH))
+    }
+
+    /// recomputes the hashes of the tree if a change failed while rehashing a path,
+    /// and writes the new root hash.
+    fn repair(&mut self) -> Result<(), Error> {
+        if self.header.dirty == 0 {
+            return Ok(());
+        }
+        let root_hash = match self.header.size {
+            0 => EMPTY_HASH,
+            _ => self.repair_node(self.node_offset(0), 0)?,
+        };
+        self.write_header(self.header.size, root_hash, 0)
+    }
+
+    /// recomputes the hashes of the children of the node at `offset`,
+    /// writes the node if they are changed, and returns the hash of the node.
+    fn repair_node(&self, offset: u32, depth: usize) -> Result<Hash, Error> {
+        if depth >= MAX_DEPTH {
+            return Err(Error::InvalidOffset(offset));
+        }
+        let mut node = sread_struct::<Node<K, V>>(self.storage, offset)?;
+        let left_hash = match node.left {
+            0 => EMPTY_HASH,
+            left => self.repair_node(left, depth + 1)?,
+        };
+        let right_hash = match node.right {
+            0 => EMPTY_HASH,
+            right => self.repair_node(right, depth + 1)?,
+        };
+        if node.left_hash != left_hash || node.right_hash != right_hash {
+            node.left_hash = left_hash;
+            node.right_hash = right_hash;
+            swrite_struct(self.storage, offset, &node)?;
+        }
+        Ok(node.hash(self.hasher))
+    }
+
+    /// releases the slot of a node that is unlinked from the tree and returns the new size.
+    /// The last node is moved into the released slot, so the nodes stay contiguous.
+    /// Moving a node doesn't change the hashes, only the link of its parent.
+    ///
+    /// A failed insert or remove can leave an unlinked node in the last slots.
+    /// Such a node has no parent and it is dropped instead of being moved.
+    fn release_node(&self, free_offset: u32) -> Result<u32, Error> {
+        let mut size = self.header.size;
+        loop {
+            let last_offset = self.node_offset(size - 1);
+            size -= 1;
+            if free_offset == last_offset {
+                return Ok(size);
+            }
+
+            let last = sread_struct::<Node<K, V>>(self.storage, last_offset)?;
+            let parent = self.find_parent(&last.key_hash, &last.key, last_offset);
+            let (parent_offset, is_left) = match parent {
+                Ok(parent) => parent,
+                Err(Error::NotFound) => continue,
+                Err(err) => return Err(err),
+            };
+            swrite_struct(self.storage, free_offset, &last)?;
+
+            let mut parent = sread_struct::<Node<K, V>>(self.storage, parent_offset)?;
+            match is_left {
+                true => parent.left = free_offset,
+                false => parent.right = free_offset,
+            }
+            swrite_struct(self.storage, parent_offset, &parent)?;
+            return Ok(size);
+        }
+    }
+
+    /// updates the size, the root hash and the dirty flag in the header.
+    /// The in-memory header is updated only if writing into the storage succeeds.
+    fn write_header(&mut self, size: u32, root_hash: Hash, dirty: u32) -> Result<(), Error> {
+        let old_size = mem::replace(&mut self.header.size, size);
+        let old_hash = mem::replace(&mut self.header.root_hash, root_hash);
+        let old_dirty = mem::replace(&mut self.header.dirty, dirty);
+        if let Err(err) = swrite_struct(self.storage, self.offset, &self.header) {
+            self.header.size = old_size;
+            self.header.root_hash = old_hash;
+            self.header.dirty = old_dirty;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::HostErrorKind;
+    use crate::hash::Sha256;
+    use crate::mock::{mock_storage, Fault};
+    use crate::random::Rng;
+
+    use super::*;
+    use core::mem::size_of;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(52, size_of::<Header>());
+        assert_eq!(112, size_of::<Node<u32, u32>>());
+        assert_eq!(136, size_of::<Node<[u8; 20], u64>>());
+    }
+
+    #[test]
+    fn test_header() {
+        let storage = mock_storage(1024);
+        StorageMerkleMap::<i32, i64, _>::create(&storage, &Sha256, 512, 16).unwrap();
+        let header = sread_struct::<Header>(&storage, 512).unwrap();
+        assert_eq!(header.boom, 0xb3000000);
+        assert_eq!(header.key_len, 4);
+        assert_eq!(header.value_len, 8);
+        assert_eq!(header.size, 0);
+        assert_eq!(header.capacity, 16);
+        assert_eq!(header.root_hash, EMPTY_HASH);
+        assert_eq!(header.dirty, 0);
+    }
+
+    #[test]
+    fn test_map() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+        assert_eq!(map.root_hash(), &EMPTY_HASH);
+        assert_eq!(None, map.find(&1).unwrap());
+
+        assert_eq!(None, map.insert(1, 10).unwrap());
+        let node = Node::new(key_hash(&Sha256, &1u32), 1u32, 10u32);
+        assert_eq!(map.root_hash(), &node.hash(&Sha256));
+
+        assert_eq!(None, map.insert(3, 30).unwrap());
+        assert_eq!(None, map.insert(2, 20).unwrap());
+        assert_eq!(Some(30), map.insert(3, 300).unwrap());
+        assert_eq!(map.len(), 3);
+        assert_eq!(Some(300), map.find(&3).unwrap());
+        assert!(map.contains_key(&2).unwrap());
+        assert!(!map.contains_key(&4).unwrap());
+
+        let root = *map.root_hash();
+        map.insert(3, 30).unwrap();
+        assert_ne!(map.root_hash(), &root);
+        map.insert(3, 300).unwrap();
+        assert_eq!(map.root_hash(), &root);
+    }
+
+    #[test]
+    fn test_same_content() {
+        // The root hash of two maps with the same keys and the same shape are equal.
+        let storage = mock_storage(4096);
+        let mut map1 = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+        let mut map2 =
+            StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 2048, 16).unwrap();
+        for key in [5, 3, 8, 1, 4] {
+            map1.insert(key, key).unwrap();
+        }
+        for key in [5, 3, 8, 1, 4, 9] {
+            map2.insert(key, key).unwrap();
+        }
+        assert_ne!(map1.root_hash(), map2.root_hash());
+        map2.remove(&9).unwrap();
+        assert_eq!(map1.root_hash(), map2.root_hash());
+    }
+
+    #[test]
+    fn test_proof() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+        let root = *map.root_hash();
+        assert!(map.prove(&1).unwrap().verify_exclusion(&Sha256, &root, &1));
+
+        for key in [50, 30, 70, 20, 40, 60, 80] {
+            map.insert(key, key * 10).unwrap();
+        }
+        let root = *map.root_hash();
+
+        for key in [50, 30, 70, 20, 40, 60, 80] {
+            let proof = map.prove(&key).unwrap();
+            assert_eq!(
+                Some(&(key * 10)),
+                proof.verify(&Sha256, &root, &key).unwrap()
+            );
+            assert!(proof.verify_inclusion(&Sha256, &root, &key, &(key * 10)));
+            assert!(!proof.verify_inclusion(&Sha256, &root, &key, &0));
+            assert!(!proof.verify_exclusion(&Sha256, &root, &key));
+        }
+
+        for key in [0, 25, 45, 55, 90] {
+            let proof = map.prove(&key).unwrap();
+            assert_eq!(None, proof.verify(&Sha256, &root, &key).unwrap());
+            assert!(proof.verify_exclusion(&Sha256, &root, &key));
+        }
+    }
+
+    #[test]
+    fn test_invalid_proof() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+        for key in [50, 30, 70, 20, 40] {
+            map.insert(key, key).unwrap();
+        }
+        let root = *map.root_hash();
+
+        // A proof for another key
+        let proof = map.prove(&40).unwrap();
+        assert!(matches!(
+            proof.verify(&Sha256, &root, &20),
+            Err(Error::InvalidProof)
+        ));
+
+        // A changed value
+        let mut forged = proof.clone();
+        forged.path.last_mut().unwrap().value = 41;
+        assert!(matches!(
+            forged.verify(&Sha256, &root, &40),
+            Err(Error::InvalidProof)
+        ));
+
+        // A removed node can't prove the exclusion
+        let mut forged = proof.clone();
+        forged.path.pop();
+        assert!(matches!(
+            forged.verify(&Sha256, &root, &40),
+            Err(Error::InvalidProof)
+        ));
+
+        // An old root
+        map.insert(40, 0).unwrap();
+        assert!(matches!(
+            proof.verify(&Sha256, map.root_hash(), &40),
+            Err(Error::InvalidProof)
+        ));
+
+        let empty = Proof::<u32, u32> { path: Vec::new() };
+        assert!(matches!(
+            empty.verify(&Sha256, &root, &40),
+            Err(Error::InvalidProof)
+        ));
+    }
+
+    #[test]
+    fn test_remove() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+        assert_eq!(None, map.remove(&1).unwrap());
+
+        for key in [50, 30, 70, 20, 40, 60, 80] {
+            map.insert(key, key).unwrap();
+        }
+        // The root, a node with two children
+        assert_eq!(Some(50), map.remove(&50).unwrap());
+        // A leaf
+        assert_eq!(Some(20), map.remove(&20).unwrap());
+        assert_eq!(None, map.remove(&20).unwrap());
+        assert_eq!(map.len(), 5);
+
+        let root = *map.root_hash();
+        for key in [30, 40, 60, 70, 80] {
+            assert_eq!(Some(key), map.find(&key).unwrap());
+            let proof = map.prove(&key).unwrap();
+            assert!(proof.verify_inclusion(&Sha256, &root, &key, &key));
+        }
+        for key in [20, 50] {
+            let proof = map.prove(&key).unwrap();
+            assert!(proof.verify_exclusion(&Sha256, &root, &key));
+        }
+
+        for key in [30, 40, 60, 70, 80] {
+            map.remove(&key).unwrap();
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.root_hash(), &EMPTY_HASH);
+    }
+
+    #[test]
+    fn test_random() {
+        let storage = mock_storage(8192);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 64).unwrap();
+        let mut keys = Vec::new();
+        let mut rng = Rng::seed_from_u64(2);
+        for _ in 0..300 {
+            let key = rng.below(100).unwrap() as u32;
+            if keys.contains(&key) {
+                assert_eq!(Some(key), map.remove(&key).unwrap());
+                keys.retain(|k| *k != key);
+            } else if keys.len() < 64 {
+                assert_eq!(None, map.insert(key, key).unwrap());
+                keys.push(key);
+            }
+            assert_eq!(map.len() as usize, keys.len());
+        }
+
+        // Every node keeps the right hashes of its children.
+        let root = *map.root_hash();
+        for key in 0..100 {
+            let proof = map.prove(&key).unwrap();
+            match keys.contains(&key) {
+                true => assert!(proof.verify_inclusion(&Sha256, &root, &key, &key)),
+                false => assert!(proof.verify_exclusion(&Sha256, &root, &key)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 512, 16).unwrap();
+        map.insert(1, 1).unwrap();
+        let root = *map.root_hash();
+
+        let map = StorageMerkleMap::<u32, u32, _>::lazy_load(&storage, &Sha256, 512).unwrap();
+        assert_eq!(map.root_hash(), &root);
+        assert_eq!(Some(1), map.find(&1).unwrap());
+        assert!(StorageMerkleMap::<u64, u32, _>::lazy_load(&storage, &Sha256, 512).is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let storage = mock_storage(4096);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 2).unwrap();
+        map.insert(1, 1).unwrap();
+        map.insert(2, 2).unwrap();
+        assert!(matches!(map.insert(3, 3), Err(Error::OutOfCapacity)));
+        assert_eq!(Some(2), map.insert(2, 20).unwrap());
+    }
+
+    #[test]
+    fn test_failed_insert() {
+        // Writes: new node, header, root, header.
+        // The tree has one node, see `test_failed_rehash` for a deeper tree.
+        for nth in 1..=4 {
+            let storage = mock_storage(4096);
+            let mut map =
+                StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+            map.insert(1, 1).unwrap();
+            let root = *map.root_hash();
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::OutOfGas,
+            });
+            assert!(matches!(
+                map.insert(2, 2),
+                Err(Error::HostError(HostErrorKind::OutOfGas))
+            ));
+            assert_eq!(map.root_hash(), &root);
+            storage.clear_faults();
+
+            let mut map = StorageMerkleMap::<u32, u32, _>::lazy_load(&storage, &Sha256, 0).unwrap();
+            assert_eq!(Some(1), map.find(&1).unwrap());
+            // The failed insert may have linked the key before failing.
+            map.insert(2, 2).unwrap();
+            assert_eq!(None, map.insert(3, 3).unwrap());
+            let root = *map.root_hash();
+            for key in [1, 2, 3] {
+                assert_eq!(Some(key), map.find(&key).unwrap());
+                let proof = map.prove(&key).unwrap();
+                assert!(proof.verify_inclusion(&Sha256, &root, &key, &key));
+            }
+
+            // The unlinked node of the failed insert is dropped by the removes.
+            for key in [3, 1, 2] {
+                assert_eq!(Some(key), map.remove(&key).unwrap());
+            }
+            assert!(map.is_empty());
+            assert_eq!(map.root_hash(), &EMPTY_HASH);
+        }
+    }
+
+    #[test]
+    fn test_failed_remove() {
+        // Writes: header, parent, moved node, its parent, header.
+        for nth in 1..=5 {
+            let storage = mock_storage(4096);
+            let mut map =
+                StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 16).unwrap();
+            for key in [5, 2, 8] {
+                map.insert(key, key).unwrap();
+            }
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::OutOfGas,
+            });
+            assert!(map.remove(&2).is_err());
+            storage.clear_faults();
+
+            let mut map = StorageMerkleMap::<u32, u32, _>::lazy_load(&storage, &Sha256, 0).unwrap();
+            map.remove(&2).unwrap();
+            map.insert(9, 9).unwrap();
+            let root = *map.root_hash();
+            for key in [5, 8, 9] {
+                let proof = map.prove(&key).unwrap();
+                assert!(proof.verify_inclusion(&Sha256, &root, &key, &key));
+            }
+            for key in [5, 8, 9] {
+                assert_eq!(Some(key), map.remove(&key).unwrap());
+            }
+            assert!(map.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_failed_rehash() {
+        // Failing each write of an insert, an update and a remove in a deeper tree,
+        // then changing another path, until the change doesn't fail.
+        let keys: Vec<u32> = (0..20).collect();
+        for change in 0..3 {
+            for nth in 1.. {
+                let storage = mock_storage(8192);
+                let mut map =
+                    StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 64).unwrap();
+                for key in &keys {
+                    map.insert(*key, *key).unwrap();
+                }
+
+                storage.inject(Fault::NthWrite {
+                    n: storage.write_count() + nth,
+                    kind: HostErrorKind::OutOfGas,
+                });
+                let result = match change {
+                    0 => map.insert(100, 100).map(|_| ()),
+                    1 => map.insert(7, 7).map(|_| ()),
+                    _ => map.remove(&7).map(|_| ()),
+                };
+                storage.clear_faults();
+
+                let snapshot = storage.snapshot();
+                let loaded =
+                    StorageMerkleMap::<u32, u32, _>::lazy_load(&snapshot, &Sha256, 0).unwrap();
+                for mut map in [map, loaded] {
+                    map.insert(200, 200).unwrap();
+                    let root = *map.root_hash();
+                    for key in keys.iter().chain(&[200]) {
+                        if change == 2 && *key == 7 {
+                            continue;
+                        }
+                        let proof = map.prove(key).unwrap();
+                        assert!(proof.verify_inclusion(&Sha256, &root, key, key));
+                    }
+                }
+                if result.is_ok() {
+                    assert!(nth > 3);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_ordered_keys() {
+        // The increasing keys don't make a long path.
+        let storage = mock_storage(128 * 1024);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 1000).unwrap();
+        for key in 0..1000 {
+            map.insert(key, key).unwrap();
+        }
+        let depth = (0..1000)
+            .map(|key| map.prove(&key).unwrap().path.len())
+            .max()
+            .unwrap();
+        assert!(depth < 40, "depth: {}", depth);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // The keys are inserted in the order of their hashes, so each key is a child of the last one.
+        let storage = mock_storage(32 * 1024);
+        let mut map = StorageMerkleMap::<u32, u32, _>::create(&storage, &Sha256, 0, 256).unwrap();
+        let mut keys: Vec<u32> = (0..MAX_DEPTH as u32 + 1).collect();
+        keys.sort_by_key(|key| key_hash(&Sha256, key));
+        for key in &keys[..MAX_DEPTH] {
+            map.insert(*key, *key).unwrap();
+        }
+        let last = keys[MAX_DEPTH - 1];
+        assert_eq!(map.prove(&last).unwrap().path.len(), MAX_DEPTH);
+
+        let root = *map.root_hash();
+        let key = keys[MAX_DEPTH];
+        assert!(matches!(map.insert(key, key), Err(Error::OutOfCapacity)));
+        assert_eq!(map.root_hash(), &root);
+        assert_eq!(map.len(), MAX_DEPTH as u32);
+
+        // Updating a deep key still works.
+        assert_eq!(Some(last), map.insert(last, 0).unwrap());
+    }
+}
@@ -0,0 +1,10 @@
+//! Storage Merkle Map
+
+pub mod map;
+pub mod proof;
+
+mod header;
+mod node;
+
+pub use map::StorageMerkleMap;
+pub use proof::{Proof, ProofNode};
@@ -0,0 +1,72 @@
+use crate::hash::{bytes_of, Hash, Hasher, NoPadding, EMPTY_HASH};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem::size_of;
+
+/// The node keeps the hashes of its children,
+/// so the hashes on a path can be updated without reading the other children.
+/// It keeps the hash of its key too, which orders the nodes in the tree.
+#[repr(C)]
+pub(super) struct Node<K: Sized, V: Sized> {
+    pub left: u32,
+    pub right: u32,
+    pub left_hash: Hash,
+    pub right_hash: Hash,
+    pub key_hash: Hash,
+    pub key: K,
+    pub value: V,
+}
+
+impl<K: NoPadding, V: NoPadding> Node<K, V> {
+    pub fn new(key_hash: Hash, key: K, value: V) -> Self {
+        Self {
+            left: 0,
+            right: 0,
+            left_hash: EMPTY_HASH,
+            right_hash: EMPTY_HASH,
+            key_hash,
+            key,
+            value,
+        }
+    }
+
+    pub fn hash<H: Hasher + ?Sized>(&self, hasher: &H) -> Hash {
+        node_hash(
+            hasher,
+            &self.key,
+            &self.value,
+            &self.left_hash,
+            &self.right_hash,
+        )
+    }
+}
+
+/// Returns the hash of a key, which orders the keys in the tree.
+pub(super) fn key_hash<K: NoPadding, H: Hasher + ?Sized>(hasher: &H, key: &K) -> Hash {
+    hasher.hash(bytes_of(key))
+}
+
+/// compares two keys by their hashes, and by the keys if the hashes are equal.
+/// Ordering the keys by their hashes keeps the tree balanced on average,
+/// even if the keys are inserted in order.
+pub(super) fn cmp_keys<K: Ord>(a_hash: &Hash, a: &K, b_hash: &Hash, b: &K) -> Ordering {
+    a_hash.cmp(b_hash).then_with(|| a.cmp(b))
+}
+
+/// Returns the hash of a node, which is the hash of the key, the value
+/// and the hashes of the left and the right children.
+/// The key and the value are hashed as they are kept in the storage.
+pub(super) fn node_hash<K: NoPadding, V: NoPadding, H: Hasher + ?Sized>(
+    hasher: &H,
+    key: &K,
+    value: &V,
+    left_hash: &Hash,
+    right_hash: &Hash,
+) -> Hash {
+    let mut data = Vec::with_capacity(size_of::<K>() + size_of::<V>() + 64);
+    data.extend_from_slice(bytes_of(key));
+    data.extend_from_slice(bytes_of(value));
+    data.extend_from_slice(left_hash);
+    data.extend_from_slice(right_hash);
+    hasher.hash(&data)
+}
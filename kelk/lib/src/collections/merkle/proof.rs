@@ -0,0 +1,117 @@
+//! Merkle proofs of the Storage Merkle Map.
+//!
+//! A proof is the path of the nodes from the root to a key, or to the place that
+//! the key should be if it doesn't exist. Anyone who knows the root hash of the map,
+//! e.g. a light client, can verify the proof without accessing the storage.
+
+use super::node::{cmp_keys, key_hash, node_hash};
+use crate::error::Error;
+use crate::hash::{Hash, Hasher, NoPadding, EMPTY_HASH};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// A node on the path of a proof.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofNode<K, V> {
+    /// The key of the node
+    pub key: K,
+    /// The value of the node
+    pub value: V,
+    /// The hash of the left child, which keeps the greater keys in the order of the map
+    pub left_hash: Hash,
+    /// The hash of the right child, which keeps the smaller keys in the order of the map
+    pub right_hash: Hash,
+}
+
+/// The inclusion or exclusion proof of a key in a Storage Merkle Map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proof<K, V> {
+    /// The nodes from the root down to the key,
+    /// or down to the node that has no child for the key.
+    pub path: Vec<ProofNode<K, V>>,
+}
+
+impl<K: Ord + NoPadding, V: NoPadding> Proof<K, V> {
+    /// verifies the proof against the root hash.
+    /// It returns the value of the key if the proof includes the key,
+    /// or None if it proves that the key doesn't exist.
+    /// It returns `InvalidProof` error if the proof doesn't match the root hash.
+    pub fn verify<H: Hasher + ?Sized>(
+        &self,
+        hasher: &H,
+        root_hash: &Hash,
+        key: &K,
+    ) -> Result<Option<&V>, Error> {
+        let last = match self.path.last() {
+            Some(last) => last,
+            None if *root_hash == EMPTY_HASH => return Ok(None),
+            None => return Err(Error::InvalidProof),
+        };
+
+        let hash = key_hash(hasher, key);
+        let cmp =
+            |node: &ProofNode<K, V>| cmp_keys(&key_hash(hasher, &node.key), &node.key, &hash, key);
+
+        let value = match cmp(last) {
+            Ordering::Equal => Some(&last.value),
+            // The greater keys are kept in the left subtree.
+            Ordering::Less if last.left_hash == EMPTY_HASH => None,
+            Ordering::Greater if last.right_hash == EMPTY_HASH => None,
+            _ => return Err(Error::InvalidProof),
+        };
+
+        let mut hash = node_hash(
+            hasher,
+            &last.key,
+            &last.value,
+            &last.left_hash,
+            &last.right_hash,
+        );
+        for node in self.path.iter().rev().skip(1) {
+            let child_hash = match cmp(node) {
+                Ordering::Less => &node.left_hash,
+                Ordering::Greater => &node.right_hash,
+                Ordering::Equal => return Err(Error::InvalidProof),
+            };
+            if *child_hash != hash {
+                return Err(Error::InvalidProof);
+            }
+            hash = node_hash(
+                hasher,
+                &node.key,
+                &node.value,
+                &node.left_hash,
+                &node.right_hash,
+            );
+        }
+
+        if hash != *root_hash {
+            return Err(Error::InvalidProof);
+        }
+        Ok(value)
+    }
+
+    /// returns true if the proof shows that the map has the key with the given value.
+    pub fn verify_inclusion<H: Hasher + ?Sized>(
+        &self,
+        hasher: &H,
+        root_hash: &Hash,
+        key: &K,
+        value: &V,
+    ) -> bool
+    where
+        V: PartialEq,
+    {
+        matches!(self.verify(hasher, root_hash, key), Ok(Some(v)) if v == value)
+    }
+
+    /// returns true if the proof shows that the map doesn't have the key.
+    pub fn verify_exclusion<H: Hasher + ?Sized>(
+        &self,
+        hasher: &H,
+        root_hash: &Hash,
+        key: &K,
+    ) -> bool {
+        matches!(self.verify(hasher, root_hash, key), Ok(None))
+    }
+}
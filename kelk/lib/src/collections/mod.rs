@@ -1,4 +1,12 @@
 //! Collection types.
 
+pub mod bitmap;
 pub mod bst;
+pub mod btree;
+pub mod buffer;
+pub mod deque;
+pub mod indexed;
+pub mod item;
+pub mod kv;
+pub mod merkle;
 pub mod vector;
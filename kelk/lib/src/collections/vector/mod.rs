@@ -1,6 +1,10 @@
 //! Storage Vector
 
-pub mod error;
+/// The errors of the collections are unified into `kelk_lib::Error`.
+#[deprecated(note = "use `kelk_lib::Error` instead")]
+pub mod error {
+    pub use crate::error::Error;
+}
 pub mod vec;
 
 mod header;
@@ -1,43 +1,33 @@
 //! Storage Vector, is a Vector or Array that instead of using Random Access Memory,
 //! Read and writes from contract's storage. Therefore it's permanently store inside contract's storage.
 
-use super::error::Error;
 use super::header::Header;
-use crate::storage::{sread_struct, swrite_struct, Storage};
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
 use core::marker::PhantomData;
 use core::mem::size_of;
 use core::result::Result;
 
-/// The instance of Storage Vector
-pub struct StorageVec<'a, V>
+/// The instance of Storage Vector.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageVec<'a, V, S = dyn Storage + 'a>
 where
     V: Sized,
+    S: ReadOnlyStorage + ?Sized,
 {
-    storage: &'a dyn Storage,
+    storage: &'a S,
     offset: u32,
     header: Header,
     _phantom: PhantomData<V>,
 }
 
-impl<'a, V> StorageVec<'a, V>
+impl<'a, V, S> StorageVec<'a, V, S>
 where
     V: Sized,
+    S: ReadOnlyStorage + ?Sized,
 {
-    /// creates and store a new instance of Storage Vector at the given offset
-    pub fn create(storage: &'a dyn Storage, offset: u32, capacity: u32) -> Result<Self, Error> {
-        let header = Header::new::<V>(capacity);
-        swrite_struct(storage, offset, &header)?;
-
-        Ok(StorageVec {
-            storage,
-            offset,
-            header,
-            _phantom: PhantomData,
-        })
-    }
-
     /// load the Storage Vector
-    pub fn lazy_load(storage: &'a dyn Storage, offset: u32) -> Result<Self, Error> {
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
         let header = sread_struct::<Header>(storage, offset)?;
 
         // TODO:
@@ -65,6 +55,37 @@ where
         self.len() == 0
     }
 
+    /// Returns an element at the given index or None if out of bounds..
+    pub fn get(&self, index: u32) -> Result<Option<V>, Error> {
+        if index >= self.header.size {
+            return Ok(None);
+        }
+
+        let offset =
+            self.offset + size_of::<Header>() as u32 + (index * self.header.value_len as u32);
+        let val: V = sread_struct(self.storage, offset)?;
+        Ok(Some(val))
+    }
+}
+
+impl<'a, V, S> StorageVec<'a, V, S>
+where
+    V: Sized,
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Vector at the given offset
+    pub fn create(storage: &'a S, offset: u32, capacity: u32) -> Result<Self, Error> {
+        let header = Header::new::<V>(capacity);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageVec {
+            storage,
+            offset,
+            header,
+            _phantom: PhantomData,
+        })
+    }
+
     /// Appends an element to the back of a vector.
     pub fn push(&mut self, value: V) -> Result<(), Error> {
         if self.header.size >= self.header.capacity {
@@ -75,28 +96,22 @@ where
             + size_of::<Header>() as u32
             + (self.header.size * self.header.value_len as u32);
 
-        self.header.size += 1;
-        swrite_struct(self.storage, self.offset, &self.header)?;
+        // The value is written before the header, so a failed write never leaves
+        // the size pointing to an unwritten element.
         swrite_struct(self.storage, offset, &value)?;
-        Ok(())
-    }
-
-    /// Returns an element at the given index or None if out of bounds..
-    pub fn get(&self, index: u32) -> Result<Option<V>, Error> {
-        if index >= self.header.size {
-            return Ok(None);
+        self.header.size += 1;
+        if let Err(err) = swrite_struct(self.storage, self.offset, &self.header) {
+            self.header.size -= 1;
+            return Err(err.into());
         }
-
-        let offset =
-            self.offset + size_of::<Header>() as u32 + (index * self.header.value_len as u32);
-        let val: V = sread_struct(self.storage, offset)?;
-        Ok(Some(val))
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::mock::mock_storage;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
 
     use super::*;
     use core::mem::size_of;
@@ -152,6 +167,42 @@ mod tests {
         assert_eq!(Some(1), vec.get(0).unwrap());
     }
 
+    #[test]
+    fn test_failed_push() {
+        let storage = mock_storage(1024);
+        let mut vec = StorageVec::<i32>::create(&storage, 0, 4).unwrap();
+        vec.push(1).unwrap();
+
+        // Failing the header write
+        storage.inject(Fault::NthWrite {
+            n: 5,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(vec.push(2).is_err());
+        assert_eq!(vec.len(), 1);
+        assert_eq!(None, vec.get(1).unwrap());
+
+        let vec = StorageVec::<i32>::lazy_load(&storage, 0).unwrap();
+        assert_eq!(vec.len(), 1);
+        assert_eq!(Some(1), vec.get(0).unwrap());
+    }
+
+    #[test]
+    fn test_failed_read() {
+        let storage = mock_storage(1024);
+        let mut vec = StorageVec::<i32>::create(&storage, 0, 4).unwrap();
+        vec.push(1).unwrap();
+
+        storage.inject(Fault::ReadRange {
+            range: 16..20,
+            kind: HostErrorKind::OutOfGas,
+        });
+        assert!(matches!(
+            vec.get(0),
+            Err(Error::HostError(HostErrorKind::OutOfGas))
+        ));
+    }
+
     #[test]
     fn test_capacity() {
         let storage = mock_storage(1024);
@@ -1,22 +1,308 @@
-//! Define the Host error.
+//! Define the Host error and the general error of kelk-lib.
 
+use crate::schema::Schema;
 use core::fmt::{self, Debug};
+use minicbor::{decode, encode, Decode, Decoder, Encode, Encoder};
+
+/// The kind of the error raised by the host.
+/// Each kind has a stable numeric code that is shared between kelk and the host.
+/// The code `0` means success and it is never used as an error code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostErrorKind {
+    /// Accessing outside of the storage file (code: 1)
+    OutOfBounds,
+    /// Running out of gas (code: 2)
+    OutOfGas,
+    /// No space left in the storage file (code: 3)
+    StorageFull,
+    /// The pointer to the sandbox memory is invalid (code: 4)
+    InvalidPointer,
+    /// The contract is not allowed to perform the operation (code: 5)
+    PermissionDenied,
+    /// The requested parameter is not defined (code: 6)
+    UnknownParam,
+    /// The arguments of the host call are invalid (code: 7)
+    InvalidArgument,
+    /// The host raised an error code that is not known by kelk.
+    /// It is made by `HostErrorKind::from`, so it never holds a known code.
+    Unknown(UnknownCode),
+}
+
+/// An error code that is not known by kelk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownCode(i32);
+
+impl UnknownCode {
+    /// returns the numeric code
+    pub fn code(&self) -> i32 {
+        self.0
+    }
+}
+
+impl HostErrorKind {
+    /// returns the numeric code of the error kind
+    pub fn code(&self) -> i32 {
+        match self {
+            HostErrorKind::OutOfBounds => 1,
+            HostErrorKind::OutOfGas => 2,
+            HostErrorKind::StorageFull => 3,
+            HostErrorKind::InvalidPointer => 4,
+            HostErrorKind::PermissionDenied => 5,
+            HostErrorKind::UnknownParam => 6,
+            HostErrorKind::InvalidArgument => 7,
+            HostErrorKind::Unknown(code) => code.code(),
+        }
+    }
+}
+
+impl From<i32> for HostErrorKind {
+    fn from(code: i32) -> Self {
+        match code {
+            1 => HostErrorKind::OutOfBounds,
+            2 => HostErrorKind::OutOfGas,
+            3 => HostErrorKind::StorageFull,
+            4 => HostErrorKind::InvalidPointer,
+            5 => HostErrorKind::PermissionDenied,
+            6 => HostErrorKind::UnknownParam,
+            7 => HostErrorKind::InvalidArgument,
+            _ => HostErrorKind::Unknown(UnknownCode(code)),
+        }
+    }
+}
+
+impl From<HostErrorKind> for i32 {
+    fn from(kind: HostErrorKind) -> Self {
+        kind.code()
+    }
+}
+
+impl fmt::Display for HostErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostErrorKind::OutOfBounds => write!(f, "out of bounds"),
+            HostErrorKind::OutOfGas => write!(f, "out of gas"),
+            HostErrorKind::StorageFull => write!(f, "storage is full"),
+            HostErrorKind::InvalidPointer => write!(f, "invalid pointer"),
+            HostErrorKind::PermissionDenied => write!(f, "permission denied"),
+            HostErrorKind::UnknownParam => write!(f, "unknown parameter"),
+            HostErrorKind::InvalidArgument => write!(f, "invalid argument"),
+            HostErrorKind::Unknown(code) => write!(f, "unknown error code: {:?}", code.code()),
+        }
+    }
+}
+
+impl Encode for HostErrorKind {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.i32(self.code())?.ok()
+    }
+}
+
+impl<'b> Decode<'b> for HostErrorKind {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        Ok(d.i32()?.into())
+    }
+}
 
 /// Error raised by the host
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct HostError {
-    /// Error raised by the host
-    pub code: i32,
+    /// The kind of the error raised by the host
+    pub kind: HostErrorKind,
+}
+
+impl HostError {
+    /// returns the numeric code of the error
+    pub fn code(&self) -> i32 {
+        self.kind.code()
+    }
+}
+
+impl From<HostErrorKind> for HostError {
+    fn from(kind: HostErrorKind) -> Self {
+        HostError { kind }
+    }
+}
+
+impl From<i32> for HostError {
+    fn from(code: i32) -> Self {
+        HostError { kind: code.into() }
+    }
 }
 
 impl Debug for HostError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("HostError")
-            .field("code", &self.code)
+            .field("kind", &self.kind)
             .finish()
     }
 }
 impl fmt::Display for HostError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Host error. Code: {:?}", self.code)
+        write!(f, "Host error: {}", self.kind)
+    }
+}
+
+/// A general list of kelk-lib errors.
+/// Contracts can embed it inside their own error type and propagate it with `?`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode, Schema)]
+pub enum Error {
+    /// Error raised by the host
+    #[n(0)]
+    HostError(#[n(0)] HostErrorKind),
+
+    /// Invalid offset for loading a collection
+    #[n(1)]
+    InvalidOffset(#[n(0)] u32),
+
+    /// Capacity of the collection is full
+    #[n(2)]
+    OutOfCapacity,
+
+    /// Reading outside of the collection's range
+    #[n(3)]
+    OutOfRange,
+
+    /// The data is not a valid UTF-8 string
+    #[n(4)]
+    InvalidUtf8,
+
+    /// The value doesn't exist in the storage
+    #[n(5)]
+    NotFound,
+
+    /// The value can't be encoded or decoded as CBOR
+    #[n(6)]
+    InvalidCbor,
+
+    /// The result of an arithmetic operation doesn't fit in the type
+    #[n(7)]
+    Overflow,
+
+    /// Dividing by zero
+    #[n(8)]
+    DivisionByZero,
+
+    /// The contract is instantiated before
+    #[n(9)]
+    AlreadyInstantiated,
+
+    /// The contract is not instantiated yet
+    #[n(10)]
+    NotInstantiated,
+
+    /// The version of the contract is not supported, e.g. for migration
+    #[n(11)]
+    InvalidVersion,
+
+    /// The proof doesn't match the root hash of the collection
+    #[n(12)]
+    InvalidProof,
+
+    /// The key exists already, e.g. in a unique index
+    #[n(13)]
+    DuplicateKey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HostError(kind) => write!(f, "host error: {}", kind),
+            Error::InvalidOffset(offset) => write!(f, "invalid offset: {:?}", offset),
+            Error::OutOfCapacity => write!(f, "Capacity is full"),
+            Error::OutOfRange => write!(f, "out of range"),
+            Error::InvalidUtf8 => write!(f, "invalid UTF-8 string"),
+            Error::NotFound => write!(f, "not found"),
+            Error::InvalidCbor => write!(f, "invalid CBOR data"),
+            Error::Overflow => write!(f, "arithmetic overflow"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::AlreadyInstantiated => write!(f, "contract is already instantiated"),
+            Error::NotInstantiated => write!(f, "contract is not instantiated"),
+            Error::InvalidVersion => write!(f, "invalid contract version"),
+            Error::InvalidProof => write!(f, "invalid proof"),
+            Error::DuplicateKey => write!(f, "duplicate key"),
+        }
+    }
+}
+
+impl From<HostError> for Error {
+    fn from(error: HostError) -> Self {
+        Error::HostError(error.kind)
+    }
+}
+
+impl From<HostErrorKind> for Error {
+    fn from(kind: HostErrorKind) -> Self {
+        Error::HostError(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_codes() {
+        for code in -1..10 {
+            let kind = HostErrorKind::from(code);
+            assert_eq!(kind.code(), code);
+            assert_eq!(i32::from(kind), code);
+        }
+        assert_eq!(HostErrorKind::from(1), HostErrorKind::OutOfBounds);
+        assert_eq!(HostErrorKind::from(5), HostErrorKind::PermissionDenied);
+        assert_eq!(
+            HostErrorKind::from(100),
+            HostErrorKind::Unknown(UnknownCode(100))
+        );
+        assert!(matches!(HostErrorKind::from(0), HostErrorKind::Unknown(_)));
+        for code in 1..=7 {
+            assert!(!matches!(
+                HostErrorKind::from(code),
+                HostErrorKind::Unknown(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_host_error() {
+        let err = HostError::from(2);
+        assert_eq!(err.kind, HostErrorKind::OutOfGas);
+        assert_eq!(err.code(), 2);
+    }
+
+    #[test]
+    fn test_error_from() {
+        let err: Error = HostError::from(3).into();
+        assert_eq!(err, Error::HostError(HostErrorKind::StorageFull));
+    }
+
+    #[test]
+    fn test_error_cbor() {
+        let errors = [
+            Error::HostError(HostErrorKind::OutOfGas),
+            Error::HostError(HostErrorKind::from(-5)),
+            Error::InvalidOffset(512),
+            Error::OutOfCapacity,
+            Error::OutOfRange,
+            Error::InvalidUtf8,
+            Error::NotFound,
+            Error::InvalidCbor,
+            Error::Overflow,
+            Error::DivisionByZero,
+            Error::AlreadyInstantiated,
+            Error::NotInstantiated,
+            Error::InvalidVersion,
+            Error::InvalidProof,
+            Error::DuplicateKey,
+        ];
+        for err in errors {
+            let mut buf = Vec::new();
+            minicbor::encode(err, &mut buf).unwrap();
+            assert_eq!(minicbor::decode::<Error>(&buf).unwrap(), err);
+        }
+
+        let mut buf = Vec::new();
+        minicbor::encode(Error::HostError(HostErrorKind::OutOfGas), &mut buf).unwrap();
+        assert_eq!(buf, [0x82, 0x00, 0x81, 0x02]); // http://cbor.me/?bytes=82008102
     }
 }
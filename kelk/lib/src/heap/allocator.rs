@@ -0,0 +1,794 @@
+//! Storage Heap, allocates blocks of any size inside a region of the contract's storage.
+//!
+//! The free blocks are kept in free lists by their size class. An allocation takes the first
+//! block that fits from its class, or any block from the larger classes, and splits it if
+//! the rest is large enough to be a block. If there is no free block, the block is taken
+//! from the unused space at the end of the heap.
+//!
+//! A freed block is merged with its free neighbours, so the free space doesn't break
+//! into small pieces. A free block at the end of the heap is returned to the unused space.
+//! The remaining holes can be removed by compaction, which moves the used blocks to the start
+//! of the heap and tells the caller the new offsets to patch its references.
+//!
+//! The tags of the blocks are always valid, and the free lists are rebuilt from the tags
+//! if a change is interrupted by a failed write.
+
+use super::block::{block_size, class_of, FreeBlock, Tag, CLASSES, MIN_BLOCK, TAG_LEN};
+use super::header::Header;
+use crate::error::Error;
+use crate::storage::{sread_struct, swrite_struct, ReadOnlyStorage, Storage};
+use alloc::vec::Vec;
+use core::mem::{offset_of, size_of};
+use core::result::Result;
+
+/// The instance of Storage Heap.
+/// If it is loaded from a read-only storage, only the read methods are available.
+pub struct StorageHeap<'a, S = dyn Storage + 'a>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    storage: &'a S,
+    offset: u32,
+    header: Header,
+}
+
+impl<'a, S> StorageHeap<'a, S>
+where
+    S: ReadOnlyStorage + ?Sized,
+{
+    /// load the Storage Heap
+    pub fn lazy_load(storage: &'a S, offset: u32) -> Result<Self, Error> {
+        let header = sread_struct::<Header>(storage, offset)?;
+
+        if header.boom != 0xb3000000 || header.top > header.size {
+            return Err(Error::InvalidOffset(offset));
+        }
+
+        Ok(StorageHeap {
+            storage,
+            offset,
+            header,
+        })
+    }
+
+    /// Returns the size of the heap in bytes, without the header.
+    pub fn size(&self) -> u32 {
+        self.header.size
+    }
+
+    /// Returns the number of the bytes from the start of the heap that are used by the blocks,
+    /// including the free blocks between them.
+    pub fn used(&self) -> u32 {
+        self.header.top
+    }
+
+    /// Returns the offset after the end of the heap.
+    pub fn end(&self) -> u32 {
+        self.start() + self.header.size
+    }
+
+    /// Returns the number of the bytes that can be kept in the allocated block at the given offset.
+    /// It can be more than the requested length.
+    pub fn block_len(&self, offset: u32) -> Result<u32, Error> {
+        let tag = self.used_tag(offset)?;
+        Ok(tag.size() - TAG_LEN)
+    }
+
+    /// Returns the old and the new offset of the block that was moved when the last
+    /// compaction failed, or None. The caller may still keep the old offset of the block,
+    /// which is free now, so it should be updated before the next change of the heap.
+    /// It is cleared by the next change of the heap.
+    pub fn moving(&self) -> Result<Option<(u32, u32)>, Error> {
+        let [old, new] = self.header.moving;
+        if old == 0 {
+            return Ok(None);
+        }
+        // The move is recorded before the block is written at its new offset.
+        // The new offset is in the free space until then.
+        let tag = sread_struct::<Tag>(self.storage, new - TAG_LEN)?;
+        if !tag.is_used() {
+            return Ok(None);
+        }
+        Ok(Some((old, new)))
+    }
+
+    fn start(&self) -> u32 {
+        self.offset + size_of::<Header>() as u32
+    }
+
+    fn read_block(&self, at: u32) -> Result<FreeBlock, Error> {
+        Ok(sread_struct::<FreeBlock>(self.storage, at)?)
+    }
+
+    /// Returns the tag of the used block that its data starts at the given offset.
+    fn used_tag(&self, offset: u32) -> Result<Tag, Error> {
+        let start = self.start();
+        if offset < start + TAG_LEN {
+            return Err(Error::InvalidOffset(offset));
+        }
+        let at = offset - TAG_LEN;
+        if at >= start + self.header.top || !(at - start).is_multiple_of(8) {
+            return Err(Error::InvalidOffset(offset));
+        }
+        let tag = sread_struct::<Tag>(self.storage, at)?;
+        if !tag.is_used() {
+            return Err(Error::InvalidOffset(offset));
+        }
+        Ok(tag)
+    }
+
+    /// Returns the first free block that is large enough.
+    fn find_free(&self, size: u32) -> Result<Option<(u32, FreeBlock)>, Error> {
+        let class = class_of(size);
+        let mut at = self.header.free[class];
+        while at != 0 {
+            let block = self.read_block(at)?;
+            if block.tag.size() >= size {
+                return Ok(Some((at, block)));
+            }
+            at = block.next;
+        }
+
+        // All the blocks in the larger classes are large enough.
+        for class in class + 1..CLASSES {
+            let at = self.header.free[class];
+            if at != 0 {
+                return Ok(Some((at, self.read_block(at)?)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a, S> StorageHeap<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    /// creates and store a new instance of Storage Heap at the given offset.
+    /// The blocks are allocated from `size` bytes after the header.
+    pub fn create(storage: &'a S, offset: u32, size: u32) -> Result<Self, Error> {
+        let header = Header::new(size);
+        swrite_struct(storage, offset, &header)?;
+
+        Ok(StorageHeap {
+            storage,
+            offset,
+            header,
+        })
+    }
+
+    /// Allocates a block for `len` bytes and returns the offset of its data.
+    /// It returns `OutOfCapacity` error if there is no space for the block.
+    pub fn salloc(&mut self, len: u32) -> Result<u32, Error> {
+        self.recover()?;
+
+        let size = block_size(len).ok_or(Error::OutOfRange)?;
+        let found = self.find_free(size)?;
+        if found.is_none() && size > self.header.size - self.header.top {
+            return Err(Error::OutOfCapacity);
+        }
+
+        let mut header = self.begin()?;
+        let at = match found {
+            Some((at, block)) => {
+                self.unlink(&mut header, &block)?;
+                let block_size = block.tag.size();
+                let rest = block_size - size;
+                if rest >= MIN_BLOCK {
+                    // The rest is tagged before the block is shrunk, while it is still inside the block.
+                    let next = at + block_size;
+                    self.push(&mut header, at + size, rest, size)?;
+                    self.set_prev_size(&mut header, next, rest)?;
+                    swrite_struct(self.storage, at, &Tag::used(size, block.tag.prev_size))?;
+                } else {
+                    swrite_struct(
+                        self.storage,
+                        at,
+                        &Tag::used(block_size, block.tag.prev_size),
+                    )?;
+                }
+                at
+            }
+            None => {
+                let at = self.start() + header.top;
+                swrite_struct(self.storage, at, &Tag::used(size, header.last))?;
+                header.top += size;
+                header.last = size;
+                at
+            }
+        };
+        self.commit(header)?;
+
+        Ok(at + TAG_LEN)
+    }
+
+    /// Frees the block that its data starts at the given offset, and merges it with its free neighbours.
+    /// It returns `InvalidOffset` error if the offset is not returned by `salloc`,
+    /// or the block is freed before.
+    pub fn sfree(&mut self, offset: u32) -> Result<(), Error> {
+        self.recover()?;
+
+        let tag = self.used_tag(offset)?;
+        let end = self.start() + self.header.top;
+        let mut header = self.begin()?;
+
+        let mut at = offset - TAG_LEN;
+        let mut size = tag.size();
+        let mut prev_size = tag.prev_size;
+
+        let next = at + size;
+        if next < end {
+            let block = self.read_block(next)?;
+            if !block.tag.is_used() {
+                self.unlink(&mut header, &block)?;
+                size += block.tag.size();
+            }
+        }
+
+        if prev_size != 0 {
+            let prev = at - prev_size;
+            let block = self.read_block(prev)?;
+            if !block.tag.is_used() {
+                self.unlink(&mut header, &block)?;
+                at = prev;
+                size += block.tag.size();
+                prev_size = block.tag.prev_size;
+            }
+        }
+
+        if at + size == end {
+            header.top = at - self.start();
+            header.last = prev_size;
+        } else {
+            self.push(&mut header, at, size, prev_size)?;
+            self.set_prev_size(&mut header, at + size, size)?;
+        }
+        self.commit(header)
+    }
+
+    /// Moves the used blocks to the start of the heap, so all the free space is at the end.
+    /// After moving each block, `relocate` is called with the old and the new offset of its data,
+    /// so the caller can update its references to the block.
+    /// If it fails, the blocks that are moved keep their new offsets, and the offsets of the
+    /// last moved block are kept in the header, see `moving`.
+    pub fn compact<F>(&mut self, mut relocate: F) -> Result<(), Error>
+    where
+        F: FnMut(u32, u32) -> Result<(), Error>,
+    {
+        self.recover()?;
+
+        let mut header = self.begin()?;
+        let end = self.start() + header.top;
+        let mut at = self.start();
+        let mut dest = at;
+        let mut prev_size = 0;
+        while at < end {
+            let tag = sread_struct::<Tag>(self.storage, at)?;
+            let size = tag.size();
+            if tag.is_used() {
+                if at != dest {
+                    // The move is recorded before the block is moved, so if the caller fails
+                    // to update its reference, it can find the new offset of the block.
+                    self.set_moving(&mut header, [at + TAG_LEN, dest + TAG_LEN])?;
+
+                    // The block and the tag of the free space after it are written at once,
+                    // so the blocks can be walked after each move.
+                    let mut data = self.storage.sread(at, size)?;
+                    data[..TAG_LEN as usize]
+                        .copy_from_slice(&Tag::used(size, prev_size).to_bytes());
+                    data.extend_from_slice(&Tag::free(at - dest, size).to_bytes());
+                    self.storage.swrite(dest, &data)?;
+                    relocate(at + TAG_LEN, dest + TAG_LEN)?;
+                }
+                dest += size;
+                prev_size = size;
+            }
+            at += size;
+        }
+
+        header.top = dest - self.start();
+        header.last = prev_size;
+        header.moving = [0; 2];
+        header.free = [0; CLASSES];
+        self.commit(header)
+    }
+
+    /// Records the old and the new offset of the block that is moved by the compaction.
+    fn set_moving(&mut self, header: &mut Header, moving: [u32; 2]) -> Result<(), Error> {
+        let offset = self.offset + offset_of!(Header, moving) as u32;
+        swrite_struct(self.storage, offset, &moving)?;
+        self.header.moving = moving;
+        header.moving = moving;
+        Ok(())
+    }
+
+    /// Adds a free block to the head of its free list.
+    fn push(&self, header: &mut Header, at: u32, size: u32, prev_size: u32) -> Result<(), Error> {
+        let class = class_of(size);
+        let head = header.free[class];
+        let block = FreeBlock {
+            tag: Tag::free(size, prev_size),
+            next: head,
+            prev: 0,
+        };
+        swrite_struct(self.storage, at, &block)?;
+        if head != 0 {
+            swrite_struct(self.storage, head + offset_of!(FreeBlock, prev) as u32, &at)?;
+        }
+        header.free[class] = at;
+        Ok(())
+    }
+
+    /// Removes a free block from its free list.
+    fn unlink(&self, header: &mut Header, block: &FreeBlock) -> Result<(), Error> {
+        if block.prev == 0 {
+            header.free[class_of(block.tag.size())] = block.next;
+        } else {
+            let next = block.prev + offset_of!(FreeBlock, next) as u32;
+            swrite_struct(self.storage, next, &block.next)?;
+        }
+        if block.next != 0 {
+            let prev = block.next + offset_of!(FreeBlock, prev) as u32;
+            swrite_struct(self.storage, prev, &block.prev)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the size of the previous block in the tag of the block at the given offset,
+    /// or in the header if it is the end of the heap.
+    fn set_prev_size(&self, header: &mut Header, at: u32, prev_size: u32) -> Result<(), Error> {
+        if at == self.start() + header.top {
+            header.last = prev_size;
+            return Ok(());
+        }
+        let offset = at + offset_of!(Tag, prev_size) as u32;
+        swrite_struct(self.storage, offset, &prev_size)?;
+        Ok(())
+    }
+
+    /// Marks the heap as changing, and returns a copy of the header to be changed and committed.
+    /// If the change is not committed, the free lists are rebuilt before the next change.
+    fn begin(&mut self) -> Result<Header, Error> {
+        let dirty = self.offset + offset_of!(Header, dirty) as u32;
+        swrite_struct(self.storage, dirty, &1u32)?;
+        self.header.dirty = 1;
+
+        let mut header = self.header.clone();
+        header.dirty = 0;
+        Ok(header)
+    }
+
+    fn commit(&mut self, header: Header) -> Result<(), Error> {
+        swrite_struct(self.storage, self.offset, &header)?;
+        self.header = header;
+        Ok(())
+    }
+
+    /// Rebuilds the free lists if a change was interrupted.
+    fn recover(&mut self) -> Result<(), Error> {
+        if self.header.dirty != 0 {
+            self.rebuild()?;
+        }
+        Ok(())
+    }
+
+    /// Walks the blocks by their tags, merges the adjacent free blocks and rebuilds the free lists.
+    fn rebuild(&mut self) -> Result<(), Error> {
+        let start = self.start();
+        let end = start + self.header.top;
+        let mut blocks: Vec<(u32, Tag)> = Vec::new();
+        let mut at = start;
+        while at < end {
+            let tag = sread_struct::<Tag>(self.storage, at)?;
+            let size = tag.size();
+            if size < MIN_BLOCK || !size.is_multiple_of(8) || size > end - at {
+                return Err(Error::InvalidOffset(at));
+            }
+            match blocks.last_mut() {
+                Some((prev_at, prev)) if !prev.is_used() && !tag.is_used() => {
+                    *prev = Tag::free(at + size - *prev_at, prev.prev_size);
+                }
+                _ => blocks.push((at, tag)),
+            }
+            at += size;
+        }
+
+        let mut header = self.header.clone();
+        header.dirty = 0;
+        header.moving = [0; 2];
+        header.free = [0; CLASSES];
+        if let Some((at, tag)) = blocks.last() {
+            if !tag.is_used() {
+                header.top = at - start;
+                blocks.pop();
+            }
+        }
+
+        let mut prev_size = 0;
+        for (at, tag) in blocks {
+            let size = tag.size();
+            if !tag.is_used() {
+                self.push(&mut header, at, size, prev_size)?;
+            } else if tag.prev_size != prev_size {
+                swrite_struct(self.storage, at, &Tag::used(size, prev_size))?;
+            }
+            prev_size = size;
+        }
+        header.last = prev_size;
+        self.commit(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault, MockStorage};
+    use alloc::vec;
+
+    /// Walks the blocks and the free lists, and checks that they match.
+    fn check(heap: &StorageHeap<'_, MockStorage>) {
+        assert_eq!(heap.header.dirty, 0);
+        let end = heap.start() + heap.header.top;
+        let mut at = heap.start();
+        let mut prev_size = 0;
+        let mut prev_free = false;
+        let mut free = Vec::new();
+        while at < end {
+            let tag = sread_struct::<Tag>(heap.storage, at).unwrap();
+            assert_eq!(tag.prev_size, prev_size);
+            assert!(!prev_free || tag.is_used(), "adjacent free blocks");
+            if !tag.is_used() {
+                free.push(at);
+            }
+            prev_free = !tag.is_used();
+            prev_size = tag.size();
+            at += tag.size();
+        }
+        assert_eq!(at, end);
+        assert_eq!(heap.header.last, prev_size);
+        assert!(!prev_free, "free block at the end");
+
+        let mut listed = Vec::new();
+        for class in 0..CLASSES {
+            let mut at = heap.header.free[class];
+            let mut prev = 0;
+            while at != 0 {
+                let block = heap.read_block(at).unwrap();
+                assert_eq!(class_of(block.tag.size()), class);
+                assert_eq!(block.prev, prev);
+                listed.push(at);
+                prev = at;
+                at = block.next;
+            }
+        }
+        listed.sort();
+        assert_eq!(listed, free);
+    }
+
+    #[test]
+    fn test_alloc() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 1024).unwrap();
+        assert_eq!(heap.end(), size_of::<Header>() as u32 + 1024);
+
+        let a = heap.salloc(10).unwrap();
+        let b = heap.salloc(0).unwrap();
+        let c = heap.salloc(100).unwrap();
+        assert_eq!(a, heap.start() + TAG_LEN);
+        assert_eq!(b, a + 24);
+        assert_eq!(c, b + 16);
+        assert_eq!(heap.block_len(a).unwrap(), 16);
+        assert_eq!(heap.block_len(b).unwrap(), 8);
+        assert_eq!(heap.block_len(c).unwrap(), 104);
+        assert_eq!(heap.used(), 24 + 16 + 112);
+
+        storage.swrite(a, &[1; 10]).unwrap();
+        storage.swrite(c, &[3; 100]).unwrap();
+        assert_eq!(storage.sread(a, 10).unwrap(), [1; 10]);
+        assert_eq!(storage.sread(c, 100).unwrap(), [3; 100]);
+        check(&heap);
+
+        let heap = StorageHeap::lazy_load(&storage, 0).unwrap();
+        assert_eq!(heap.used(), 152);
+        assert_eq!(heap.block_len(c).unwrap(), 104);
+        check(&heap);
+    }
+
+    #[test]
+    fn test_out_of_capacity() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 64).unwrap();
+        heap.salloc(40).unwrap();
+        let snapshot = storage.snapshot();
+
+        assert!(matches!(heap.salloc(9), Err(Error::OutOfCapacity)));
+        assert!(matches!(heap.salloc(u32::MAX), Err(Error::OutOfRange)));
+        // Nothing is written
+        assert!(storage.diff(&snapshot).is_empty());
+        heap.salloc(8).unwrap();
+        check(&heap);
+    }
+
+    #[test]
+    fn test_invalid_free() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 1024).unwrap();
+        let a = heap.salloc(10).unwrap();
+        let b = heap.salloc(10).unwrap();
+
+        assert!(matches!(heap.sfree(a + 8), Err(Error::InvalidOffset(_))));
+        assert!(matches!(heap.sfree(a - 8), Err(Error::InvalidOffset(_))));
+        assert!(matches!(heap.sfree(0), Err(Error::InvalidOffset(0))));
+        assert!(matches!(heap.sfree(b + 1024), Err(Error::InvalidOffset(_))));
+        heap.sfree(a).unwrap();
+        assert!(matches!(heap.sfree(a), Err(Error::InvalidOffset(_))));
+        assert!(matches!(heap.block_len(a), Err(Error::InvalidOffset(_))));
+        check(&heap);
+    }
+
+    #[test]
+    fn test_reuse() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 1024).unwrap();
+        let a = heap.salloc(100).unwrap();
+        let b = heap.salloc(100).unwrap();
+        let _c = heap.salloc(100).unwrap();
+
+        heap.sfree(b).unwrap();
+        check(&heap);
+        assert_eq!(heap.salloc(100).unwrap(), b);
+        check(&heap);
+
+        // The free block is split, and the rest is used by the next allocation
+        heap.sfree(b).unwrap();
+        assert_eq!(heap.salloc(40).unwrap(), b);
+        check(&heap);
+        assert_eq!(heap.salloc(40).unwrap(), b + 48);
+        check(&heap);
+
+        // The rest that is too small to be a block stays in the block
+        heap.sfree(a).unwrap();
+        assert_eq!(heap.salloc(96).unwrap(), a);
+        assert_eq!(heap.block_len(a).unwrap(), 104);
+        check(&heap);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 1024).unwrap();
+        let blocks: Vec<u32> = (0..5).map(|_| heap.salloc(24).unwrap()).collect();
+
+        heap.sfree(blocks[1]).unwrap();
+        heap.sfree(blocks[3]).unwrap();
+        check(&heap);
+        // Merged with both neighbours
+        heap.sfree(blocks[2]).unwrap();
+        check(&heap);
+        assert_eq!(heap.salloc(3 * 32 - 8).unwrap(), blocks[1]);
+        check(&heap);
+
+        // The free blocks at the end are returned to the unused space
+        heap.sfree(blocks[1]).unwrap();
+        heap.sfree(blocks[4]).unwrap();
+        assert_eq!(heap.used(), 32);
+        check(&heap);
+        heap.sfree(blocks[0]).unwrap();
+        assert_eq!(heap.used(), 0);
+        check(&heap);
+    }
+
+    #[test]
+    fn test_size_classes() {
+        let storage = mock_storage(16 * 1024);
+        let mut heap = StorageHeap::create(&storage, 0, 8192).unwrap();
+        let mut blocks = Vec::new();
+        for len in [8, 100, 1000, 2000] {
+            blocks.push(heap.salloc(len).unwrap());
+            blocks.push(heap.salloc(8).unwrap());
+        }
+        for block in blocks.iter().step_by(2) {
+            heap.sfree(*block).unwrap();
+        }
+        check(&heap);
+        assert_ne!(heap.header.free[class_of(16)], 0);
+        assert_ne!(heap.header.free[class_of(112)], 0);
+        assert_ne!(heap.header.free[class_of(1008)], 0);
+        assert_ne!(heap.header.free[class_of(2008)], 0);
+
+        // A larger class is used if the class has no large enough block
+        assert_eq!(heap.salloc(1200).unwrap(), blocks[6]);
+        assert_eq!(heap.salloc(90).unwrap(), blocks[2]);
+        check(&heap);
+    }
+
+    #[test]
+    fn test_compact() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 2048).unwrap();
+        let mut blocks = Vec::new();
+        for i in 0..10 {
+            let len = 10 + i * 10;
+            let offset = heap.salloc(len).unwrap();
+            storage
+                .swrite(offset, &vec![i as u8; len as usize])
+                .unwrap();
+            blocks.push((offset, len));
+        }
+        for i in [0, 3, 4, 8] {
+            heap.sfree(blocks[i].0).unwrap();
+        }
+        let mut live: Vec<(u32, u32)> = blocks
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ![0, 3, 4, 8].contains(i))
+            .map(|(_, b)| *b)
+            .collect();
+
+        let mut moves = Vec::new();
+        heap.compact(|old, new| {
+            moves.push((old, new));
+            Ok(())
+        })
+        .unwrap();
+        check(&heap);
+        assert_eq!(moves.len(), 6);
+        for (old, new) in moves {
+            let block = live.iter_mut().find(|(offset, _)| *offset == old).unwrap();
+            block.0 = new;
+        }
+
+        let mut offset = heap.start() + TAG_LEN;
+        for (i, (block, len)) in live.iter().enumerate() {
+            assert_eq!(*block, offset);
+            let value = [1, 2, 5, 6, 7, 9][i];
+            assert_eq!(
+                storage.sread(*block, *len).unwrap(),
+                vec![value; *len as usize]
+            );
+            offset += block_size(*len).unwrap();
+        }
+        assert_eq!(heap.used(), offset - TAG_LEN - heap.start());
+        assert!(heap.header.free.iter().all(|head| *head == 0));
+
+        // Nothing to move
+        heap.compact(|_, _| panic!("no block should move")).unwrap();
+        check(&heap);
+    }
+
+    #[test]
+    fn test_failed_compact() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 2048).unwrap();
+        let blocks: Vec<u32> = (0..6).map(|_| heap.salloc(20).unwrap()).collect();
+        heap.sfree(blocks[0]).unwrap();
+        heap.sfree(blocks[2]).unwrap();
+
+        let mut moved = 0;
+        assert!(matches!(
+            heap.compact(|_, _| {
+                moved += 1;
+                if moved == 2 {
+                    return Err(Error::NotFound);
+                }
+                Ok(())
+            }),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(moved, 2);
+        assert_eq!(heap.moving().unwrap(), Some((blocks[3], blocks[1])));
+        let loaded = StorageHeap::lazy_load(&storage, 0).unwrap();
+        assert_eq!(loaded.moving().unwrap(), Some((blocks[3], blocks[1])));
+
+        // The free lists are rebuilt from the tags before the next change
+        let offset = heap.salloc(20).unwrap();
+        check(&heap);
+        assert!(offset > blocks[1]);
+        assert_eq!(heap.moving().unwrap(), None);
+        let mut heap = StorageHeap::lazy_load(&storage, 0).unwrap();
+        heap.compact(|_, _| Ok(())).unwrap();
+        check(&heap);
+        assert_eq!(heap.used(), 5 * 32);
+        assert_eq!(heap.moving().unwrap(), None);
+    }
+
+    #[test]
+    fn test_failed_move() {
+        let storage = mock_storage(4096);
+        let mut heap = StorageHeap::create(&storage, 0, 2048).unwrap();
+        let blocks: Vec<u32> = (0..3).map(|_| heap.salloc(20).unwrap()).collect();
+        heap.sfree(blocks[0]).unwrap();
+
+        // Failing to write the block at its new offset, after the move is recorded
+        storage.inject(Fault::NthWrite {
+            n: storage.write_count() + 3,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(heap.compact(|_, _| Ok(())).is_err());
+        storage.clear_faults();
+
+        assert_eq!(heap.header.moving, [blocks[1], blocks[0]]);
+        assert_eq!(heap.moving().unwrap(), None);
+        heap.compact(|_, _| Ok(())).unwrap();
+        check(&heap);
+        assert_eq!(heap.used(), 2 * 32);
+    }
+
+    #[test]
+    fn test_failed_writes() {
+        // Fails each write of each operation, and checks the heap after rebuilding it
+        for nth in 1..=8 {
+            let storage = mock_storage(4096);
+            let mut heap = StorageHeap::create(&storage, 0, 1024).unwrap();
+            let blocks: Vec<u32> = (0..6).map(|_| heap.salloc(40).unwrap()).collect();
+            heap.sfree(blocks[1]).unwrap();
+            heap.sfree(blocks[3]).unwrap();
+
+            storage.inject(Fault::NthWrite {
+                n: storage.write_count() + nth,
+                kind: HostErrorKind::StorageFull,
+            });
+            let _ = heap.sfree(blocks[2]);
+            let _ = heap.salloc(10);
+            storage.clear_faults();
+
+            // The heap that is loaded after the failure
+            let snapshot = storage.snapshot();
+            let mut loaded = StorageHeap::lazy_load(&snapshot, 0).unwrap();
+            loaded.salloc(8).unwrap();
+            check(&loaded);
+
+            // The heap that has seen the failure
+            heap.salloc(8).unwrap();
+            check(&heap);
+        }
+    }
+
+    #[test]
+    fn test_random() {
+        use crate::random::Rng;
+
+        let storage = mock_storage(64 * 1024);
+        let mut heap = StorageHeap::create(&storage, 0, 32 * 1024).unwrap();
+        let mut blocks: Vec<(u32, u32, u8)> = Vec::new();
+        let mut rng = Rng::seed_from_u64(1);
+        for i in 0..1000u32 {
+            if !blocks.is_empty() && rng.below(3).unwrap() == 0 {
+                let index = rng.below(blocks.len() as u64).unwrap() as usize;
+                let (offset, _, _) = blocks.swap_remove(index);
+                heap.sfree(offset).unwrap();
+            } else {
+                let len = rng.below(300).unwrap() as u32;
+                match heap.salloc(len) {
+                    Ok(offset) => {
+                        storage
+                            .swrite(offset, &vec![i as u8; len as usize])
+                            .unwrap();
+                        blocks.push((offset, len, i as u8));
+                    }
+                    Err(Error::OutOfCapacity) => {}
+                    Err(err) => panic!("{:?}", err),
+                }
+            }
+            if i % 100 == 0 {
+                check(&heap);
+            }
+        }
+        check(&heap);
+
+        heap.compact(|old, new| {
+            let block = blocks.iter_mut().find(|(offset, _, _)| *offset == old);
+            block.unwrap().0 = new;
+            Ok(())
+        })
+        .unwrap();
+        check(&heap);
+        for (offset, len, value) in blocks {
+            assert_eq!(
+                storage.sread(offset, len).unwrap(),
+                vec![value; len as usize]
+            );
+        }
+    }
+}
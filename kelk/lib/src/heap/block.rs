@@ -0,0 +1,110 @@
+//! Each block starts with a tag that keeps the size of the block and the size of the previous block,
+//! so the neighbours of a block can be found in both directions.
+//! The first bit of the size is set if the block is used.
+//! The free blocks keep the links of their free list after the tag.
+
+use core::mem::size_of;
+
+/// The number of the size classes. The last class keeps all the larger blocks.
+pub(super) const CLASSES: usize = 16;
+
+/// The length of the tag.
+pub(super) const TAG_LEN: u32 = size_of::<Tag>() as u32;
+
+/// The size of the smallest block, which can keep the tag and the links of a free block.
+pub(super) const MIN_BLOCK: u32 = size_of::<FreeBlock>() as u32;
+
+const USED: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct Tag {
+    size: u32,
+    pub prev_size: u32,
+}
+
+impl Tag {
+    pub fn used(size: u32, prev_size: u32) -> Self {
+        Tag {
+            size: size | USED,
+            prev_size,
+        }
+    }
+
+    pub fn free(size: u32, prev_size: u32) -> Self {
+        Tag { size, prev_size }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size & !USED
+    }
+
+    pub fn is_used(&self) -> bool {
+        self.size & USED != 0
+    }
+
+    pub fn to_bytes(self) -> [u8; TAG_LEN as usize] {
+        let mut bytes = [0; TAG_LEN as usize];
+        bytes[..4].copy_from_slice(&self.size.to_ne_bytes());
+        bytes[4..].copy_from_slice(&self.prev_size.to_ne_bytes());
+        bytes
+    }
+}
+
+/// A free block with the offsets of the next and the previous free blocks in its list.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct FreeBlock {
+    pub tag: Tag,
+    pub next: u32,
+    pub prev: u32,
+}
+
+/// Returns the size of the block that keeps `len` bytes.
+/// The blocks are aligned to 8 bytes.
+pub(super) fn block_size(len: u32) -> Option<u32> {
+    let size = len.checked_add(TAG_LEN + 7)? & !7;
+    Some(size.max(MIN_BLOCK))
+}
+
+/// Returns the size class of a block. The class `n` keeps the blocks
+/// from `MIN_BLOCK << n` bytes up to twice as large.
+pub(super) fn class_of(size: u32) -> usize {
+    let class = (size / MIN_BLOCK).ilog2() as usize;
+    class.min(CLASSES - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_size() {
+        assert_eq!(block_size(0), Some(16));
+        assert_eq!(block_size(8), Some(16));
+        assert_eq!(block_size(9), Some(24));
+        assert_eq!(block_size(100), Some(112));
+        assert_eq!(block_size(u32::MAX - 15), Some(u32::MAX - 7));
+        assert_eq!(block_size(u32::MAX - 14), None);
+    }
+
+    #[test]
+    fn test_class_of() {
+        assert_eq!(class_of(16), 0);
+        assert_eq!(class_of(24), 0);
+        assert_eq!(class_of(32), 1);
+        assert_eq!(class_of(1024), 6);
+        assert_eq!(class_of(MIN_BLOCK << 15), CLASSES - 1);
+        assert_eq!(class_of(u32::MAX - 7), CLASSES - 1);
+    }
+
+    #[test]
+    fn test_tag() {
+        let tag = Tag::used(32, 16);
+        assert!(tag.is_used());
+        assert_eq!(tag.size(), 32);
+        let tag = Tag::free(32, 16);
+        assert!(!tag.is_used());
+        assert_eq!(tag.size(), 32);
+    }
+}
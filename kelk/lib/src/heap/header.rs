@@ -0,0 +1,35 @@
+use super::block::CLASSES;
+
+#[repr(C)]
+#[derive(Clone)]
+pub(super) struct Header {
+    pub boom: u32,
+    /// The size of the heap, without the header
+    pub size: u32,
+    /// The number of the bytes that are used by the blocks from the start of the heap
+    pub top: u32,
+    /// The size of the last block, or zero if there is no block
+    pub last: u32,
+    /// It is not zero while the blocks are changing, so the free lists should be rebuilt
+    /// if a change is interrupted.
+    pub dirty: u32,
+    /// The old and the new offset of the block that is moved by the compaction,
+    /// or zeros if no compaction is interrupted.
+    pub moving: [u32; 2],
+    /// The offset of the first free block in each size class, or zero if there is no free block
+    pub free: [u32; CLASSES],
+}
+
+impl Header {
+    pub fn new(size: u32) -> Self {
+        Self {
+            boom: 0xb3000000,
+            size,
+            top: 0,
+            last: 0,
+            dirty: 0,
+            moving: [0; 2],
+            free: [0; CLASSES],
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Storage Heap, is a dynamic allocator for the blocks of the contract's storage.
+
+pub mod allocator;
+
+mod block;
+mod header;
+
+pub use allocator::StorageHeap;
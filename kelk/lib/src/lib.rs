@@ -5,14 +5,12 @@
     missing_docs,
     bad_style,
     bare_trait_objects,
-    const_err,
     improper_ctypes,
     non_shorthand_field_patterns,
     no_mangle_generic_items,
     overflowing_literals,
     path_statements,
     patterns_in_fns_without_body,
-    private_in_public,
     unconditional_recursion,
     unused_allocation,
     unused_comparisons,
@@ -23,12 +21,28 @@
     unused_extern_crates
 )]
 
+pub mod cbor;
 pub mod collections;
 pub mod error;
+pub mod hash;
+pub mod heap;
+pub mod math;
 pub mod mock;
+pub mod random;
+pub mod schema;
 pub mod storage;
+pub mod u256;
+
+pub use error::Error;
+pub use u256::U256;
 
 pub extern crate alloc;
 
+// Allows the derive macros to refer to `kelk_lib` from inside this crate.
+extern crate self as kelk_lib;
+
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
 #[macro_use]
 extern crate doc_comment;
@@ -0,0 +1,386 @@
+//! Fixed-point decimal number with 18 fractional digits.
+//!
+//! `Decimal` is backed by an `u128` and all the intermediate results are calculated in `U256`.
+//! There is no floating point arithmetic involved, so the results are deterministic on every platform.
+
+use crate::error::Error;
+use crate::U256;
+use core::fmt;
+use minicbor::{decode, encode, Decode, Decoder, Encode, Encoder};
+
+/// The rounding mode of the operations that may lose precision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round toward zero (truncate).
+    Down,
+    /// Round away from zero.
+    Up,
+    /// Round to the nearest value. The ties are rounded away from zero.
+    HalfUp,
+    /// Round to the nearest value. The ties are rounded to the even value (banker's rounding).
+    HalfEven,
+}
+
+/// divides `num` by `den` and rounds the quotient according to `rounding`.
+pub(crate) fn div_round(num: U256, den: U256, rounding: Rounding) -> Result<U256, Error> {
+    let (quotient, remainder) = num.checked_div_rem(den).ok_or(Error::DivisionByZero)?;
+    if remainder.is_zero() {
+        return Ok(quotient);
+    }
+
+    // `remainder` is less than `den`, so `den - remainder` never underflows.
+    let rest = den.checked_sub(remainder).unwrap();
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::HalfUp => remainder >= rest,
+        Rounding::HalfEven => {
+            remainder > rest || (remainder == rest && quotient.to_be_bytes()[31] & 1 == 1)
+        }
+    };
+
+    match round_up {
+        true => quotient.checked_add(U256::ONE).ok_or(Error::Overflow),
+        false => Ok(quotient),
+    }
+}
+
+/// A fixed-point decimal number with 18 fractional digits.
+/// The value is stored as `value * 10^18` inside an `u128`, so it can represent numbers up to
+/// about 3.4 * 10^20 with 18 digits of precision.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Decimal(u128);
+
+impl Decimal {
+    /// Number of fractional digits
+    pub const DECIMALS: u32 = 18;
+    /// The scale of the decimal, 10^18
+    pub const SCALE: u128 = 1_000_000_000_000_000_000;
+    /// The value zero: 0.0
+    pub const ZERO: Decimal = Decimal(0);
+    /// The value one: 1.0
+    pub const ONE: Decimal = Decimal(Self::SCALE);
+    /// The largest value
+    pub const MAX: Decimal = Decimal(u128::MAX);
+
+    /// creates a decimal from its raw value, which is `value * 10^18`.
+    pub const fn from_raw(raw: u128) -> Self {
+        Decimal(raw)
+    }
+
+    /// returns the raw value of the decimal, which is `value * 10^18`.
+    pub const fn raw(&self) -> u128 {
+        self.0
+    }
+
+    /// creates a decimal from an integer.
+    /// It returns `Overflow` error if the value doesn't fit.
+    pub fn from_int(value: u128) -> Result<Self, Error> {
+        value
+            .checked_mul(Self::SCALE)
+            .map(Decimal)
+            .ok_or(Error::Overflow)
+    }
+
+    /// creates a decimal from `numerator / denominator`, rounded according to `rounding`.
+    pub fn from_ratio(
+        numerator: u128,
+        denominator: u128,
+        rounding: Rounding,
+    ) -> Result<Self, Error> {
+        let num = U256::from(numerator)
+            .checked_mul(U256::from(Self::SCALE))
+            .unwrap(); // 128 bits * 60 bits fits in 256 bits
+        Self::from_u256(div_round(num, U256::from(denominator), rounding)?)
+    }
+
+    /// creates a decimal from a percentage, e.g. `percent(5)` is 0.05.
+    pub fn percent(value: u64) -> Self {
+        Decimal(value as u128 * (Self::SCALE / 100))
+    }
+
+    /// creates a decimal from a permille, e.g. `permille(5)` is 0.005.
+    pub fn permille(value: u64) -> Self {
+        Decimal(value as u128 * (Self::SCALE / 1000))
+    }
+
+    fn from_u256(value: U256) -> Result<Self, Error> {
+        value.to_u128().map(Decimal).ok_or(Error::Overflow)
+    }
+
+    /// returns true if the value is zero
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Checked addition. It returns `Overflow` error if the result doesn't fit.
+    pub fn checked_add(self, rhs: Self) -> Result<Self, Error> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Decimal)
+            .ok_or(Error::Overflow)
+    }
+
+    /// Checked subtraction. It returns `Overflow` error if the result is negative.
+    pub fn checked_sub(self, rhs: Self) -> Result<Self, Error> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Decimal)
+            .ok_or(Error::Overflow)
+    }
+
+    /// Checked multiplication. The result is rounded according to `rounding`.
+    pub fn checked_mul(self, rhs: Self, rounding: Rounding) -> Result<Self, Error> {
+        let product = U256::from(self.0).checked_mul(U256::from(rhs.0)).unwrap(); // 128 bits * 128 bits fits in 256 bits
+        Self::from_u256(div_round(product, U256::from(Self::SCALE), rounding)?)
+    }
+
+    /// Checked division. The result is rounded according to `rounding`.
+    /// It returns `DivisionByZero` error if `rhs` is zero.
+    pub fn checked_div(self, rhs: Self, rounding: Rounding) -> Result<Self, Error> {
+        let num = U256::from(self.0)
+            .checked_mul(U256::from(Self::SCALE))
+            .unwrap(); // 128 bits * 60 bits fits in 256 bits
+        Self::from_u256(div_round(num, U256::from(rhs.0), rounding)?)
+    }
+
+    /// Raises the decimal to the power of `exp`. Each multiplication is rounded according to `rounding`.
+    pub fn checked_pow(self, mut exp: u32, rounding: Rounding) -> Result<Self, Error> {
+        let mut base = self;
+        let mut acc = Decimal::ONE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc.checked_mul(base, rounding)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(base, rounding)?;
+            }
+        }
+        Ok(acc)
+    }
+
+    /// Multiplies an integer amount by the decimal, e.g. applying a price or a fee rate on an amount.
+    /// The result is rounded according to `rounding`.
+    pub fn mul_int(self, amount: u128, rounding: Rounding) -> Result<u128, Error> {
+        super::mul_div(amount, self.0, Self::SCALE, rounding)
+    }
+
+    /// Divides an integer amount by the decimal. The result is rounded according to `rounding`.
+    /// It returns `DivisionByZero` error if the decimal is zero.
+    pub fn div_int(self, amount: u128, rounding: Rounding) -> Result<u128, Error> {
+        super::mul_div(amount, Self::SCALE, self.0, rounding)
+    }
+
+    /// Saturating addition.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Decimal(self.0.saturating_add(rhs.0))
+    }
+
+    /// Saturating subtraction.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Decimal(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Rounds the decimal to an integer according to `rounding`.
+    pub fn to_int(self, rounding: Rounding) -> u128 {
+        // The result is never bigger than the raw value, so it can't overflow.
+        div_round(U256::from(self.0), U256::from(Self::SCALE), rounding)
+            .ok()
+            .and_then(|v| v.to_u128())
+            .unwrap()
+    }
+
+    /// Returns the largest integer less than or equal to the decimal.
+    pub fn floor(self) -> u128 {
+        self.to_int(Rounding::Down)
+    }
+
+    /// Returns the smallest integer greater than or equal to the decimal.
+    pub fn ceil(self) -> u128 {
+        self.to_int(Rounding::Up)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let int = self.0 / Self::SCALE;
+        let mut frac = self.0 % Self::SCALE;
+        if frac == 0 {
+            return write!(f, "{}", int);
+        }
+
+        let mut digits = Self::DECIMALS as usize;
+        while frac.is_multiple_of(10) {
+            frac /= 10;
+            digits -= 1;
+        }
+        write!(f, "{}.{:0width$}", int, frac, width = digits)
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decimal({})", self)
+    }
+}
+
+impl Encode for Decimal {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        crate::cbor::u128::encode(&self.0, e)
+    }
+}
+
+impl<'b> Decode<'b> for Decimal {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        crate::cbor::u128::decode(d).map(Decimal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_storage;
+    use crate::storage::{sread_struct, swrite_struct};
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    fn dec(numerator: u128, denominator: u128) -> Decimal {
+        Decimal::from_ratio(numerator, denominator, Rounding::Down).unwrap()
+    }
+
+    #[test]
+    fn test_constructors() {
+        assert_eq!(Decimal::from_int(1).unwrap(), Decimal::ONE);
+        assert_eq!(Decimal::percent(50), dec(1, 2));
+        assert_eq!(Decimal::permille(125), dec(1, 8));
+        assert_eq!(Decimal::from_raw(1).raw(), 1);
+        assert_eq!(Decimal::from_int(u128::MAX), Err(Error::Overflow));
+        assert_eq!(
+            Decimal::from_ratio(1, 0, Rounding::Down),
+            Err(Error::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let a = Decimal::percent(150);
+        let b = Decimal::percent(25);
+        assert_eq!(a.checked_add(b).unwrap(), Decimal::percent(175));
+        assert_eq!(a.checked_sub(b).unwrap(), Decimal::percent(125));
+        assert_eq!(b.checked_sub(a), Err(Error::Overflow));
+        assert_eq!(Decimal::MAX.checked_add(Decimal::ONE), Err(Error::Overflow));
+        assert_eq!(b.saturating_sub(a), Decimal::ZERO);
+        assert_eq!(Decimal::MAX.saturating_add(a), Decimal::MAX);
+    }
+
+    #[test]
+    fn test_mul_div() {
+        let a = Decimal::percent(150);
+        let b = Decimal::percent(20);
+        assert_eq!(
+            a.checked_mul(b, Rounding::Down).unwrap(),
+            Decimal::percent(30)
+        );
+        assert_eq!(
+            a.checked_div(b, Rounding::Down).unwrap(),
+            Decimal::percent(750)
+        );
+        assert_eq!(
+            a.checked_div(Decimal::ZERO, Rounding::Down),
+            Err(Error::DivisionByZero)
+        );
+        assert_eq!(
+            Decimal::MAX.checked_mul(Decimal::percent(200), Rounding::Down),
+            Err(Error::Overflow)
+        );
+
+        // 1 / 3 = 0.333...
+        let third = Decimal::ONE.checked_div(Decimal::from_int(3).unwrap(), Rounding::Down);
+        assert_eq!(third.unwrap().raw(), 333_333_333_333_333_333);
+        let third = Decimal::ONE.checked_div(Decimal::from_int(3).unwrap(), Rounding::Up);
+        assert_eq!(third.unwrap().raw(), 333_333_333_333_333_334);
+    }
+
+    #[test]
+    fn test_rounding() {
+        // 0.5, 1.5 and 2.5 with the tie rounding modes
+        let cases = [
+            (5, Rounding::Down, 0),
+            (5, Rounding::Up, 1),
+            (5, Rounding::HalfUp, 1),
+            (5, Rounding::HalfEven, 0),
+            (15, Rounding::HalfEven, 2),
+            (25, Rounding::HalfEven, 2),
+            (25, Rounding::HalfUp, 3),
+            (24, Rounding::HalfUp, 2),
+            (26, Rounding::HalfEven, 3),
+        ];
+        for (tenths, rounding, expected) in cases {
+            assert_eq!(dec(tenths, 10).to_int(rounding), expected);
+        }
+        assert_eq!(dec(7, 2).floor(), 3);
+        assert_eq!(dec(7, 2).ceil(), 4);
+        assert_eq!(Decimal::from_int(7).unwrap().ceil(), 7);
+    }
+
+    #[test]
+    fn test_int() {
+        // Applying a 0.3% fee on an amount
+        let fee = Decimal::permille(3);
+        assert_eq!(fee.mul_int(1_000, Rounding::Down).unwrap(), 3);
+        assert_eq!(fee.mul_int(1_001, Rounding::Down).unwrap(), 3);
+        assert_eq!(fee.mul_int(1_001, Rounding::Up).unwrap(), 4);
+        assert_eq!(
+            Decimal::MAX.mul_int(u128::MAX, Rounding::Down),
+            Err(Error::Overflow)
+        );
+
+        let price = Decimal::percent(250);
+        assert_eq!(price.div_int(10, Rounding::Down).unwrap(), 4);
+        assert_eq!(
+            Decimal::ZERO.div_int(10, Rounding::Down),
+            Err(Error::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_pow() {
+        // Compound interest: 1.05^2 = 1.1025
+        let rate = Decimal::percent(105);
+        assert_eq!(
+            rate.checked_pow(2, Rounding::Down).unwrap(),
+            dec(11025, 10000)
+        );
+        assert_eq!(rate.checked_pow(0, Rounding::Down).unwrap(), Decimal::ONE);
+        assert_eq!(
+            Decimal::from_int(10)
+                .unwrap()
+                .checked_pow(21, Rounding::Down),
+            Err(Error::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", Decimal::ZERO), "0");
+        assert_eq!(format!("{}", Decimal::from_int(42).unwrap()), "42");
+        assert_eq!(format!("{}", Decimal::percent(105)), "1.05");
+        assert_eq!(format!("{}", Decimal::from_raw(1)), "0.000000000000000001");
+        assert_eq!(format!("{:?}", Decimal::percent(5)), "Decimal(0.05)");
+    }
+
+    #[test]
+    fn test_encoding() {
+        let value = Decimal::percent(12345);
+
+        let mut buf = Vec::new();
+        minicbor::encode(value, &mut buf).unwrap();
+        assert_eq!(minicbor::decode::<Decimal>(&buf).unwrap(), value);
+
+        let storage = mock_storage(16);
+        swrite_struct(&storage, 0, &value).unwrap();
+        assert_eq!(sread_struct::<Decimal>(&storage, 0).unwrap(), value);
+    }
+}
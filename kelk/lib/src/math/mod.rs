@@ -0,0 +1,227 @@
+//! Checked and saturating arithmetic for contracts.
+//!
+//! The helpers in this module never panic and never wrap silently.
+//! Checked operations return `Overflow` or `DivisionByZero` errors,
+//! so they can be propagated with `?` inside the contract's error type.
+
+pub mod decimal;
+
+pub use decimal::{Decimal, Rounding};
+
+use crate::error::Error;
+use crate::U256;
+
+/// Integer types that support checked and saturating arithmetic.
+pub trait Integer: Copy + PartialOrd {
+    /// Checked addition. Returns None if overflow occurred.
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    /// Checked subtraction. Returns None if overflow occurred.
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    /// Checked multiplication. Returns None if overflow occurred.
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    /// Checked division. Returns None if `rhs` is zero or overflow occurred.
+    fn checked_div(self, rhs: Self) -> Option<Self>;
+    /// Checked remainder. Returns None if `rhs` is zero or overflow occurred.
+    fn checked_rem(self, rhs: Self) -> Option<Self>;
+    /// Checked exponentiation. Returns None if overflow occurred.
+    fn checked_pow(self, exp: u32) -> Option<Self>;
+    /// Saturating addition.
+    fn saturating_add(self, rhs: Self) -> Self;
+    /// Saturating subtraction.
+    fn saturating_sub(self, rhs: Self) -> Self;
+    /// Saturating multiplication.
+    fn saturating_mul(self, rhs: Self) -> Self;
+    /// Returns true if the value is zero.
+    fn is_zero(&self) -> bool;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl Integer for $ty {
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_add(self, rhs)
+                }
+                fn checked_sub(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_sub(self, rhs)
+                }
+                fn checked_mul(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_mul(self, rhs)
+                }
+                fn checked_div(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_div(self, rhs)
+                }
+                fn checked_rem(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_rem(self, rhs)
+                }
+                fn checked_pow(self, exp: u32) -> Option<Self> {
+                    <$ty>::checked_pow(self, exp)
+                }
+                fn saturating_add(self, rhs: Self) -> Self {
+                    <$ty>::saturating_add(self, rhs)
+                }
+                fn saturating_sub(self, rhs: Self) -> Self {
+                    <$ty>::saturating_sub(self, rhs)
+                }
+                fn saturating_mul(self, rhs: Self) -> Self {
+                    <$ty>::saturating_mul(self, rhs)
+                }
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Integer for U256 {
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        U256::checked_add(self, rhs)
+    }
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        U256::checked_sub(self, rhs)
+    }
+    fn checked_mul(self, rhs: Self) -> Option<Self> {
+        U256::checked_mul(self, rhs)
+    }
+    fn checked_div(self, rhs: Self) -> Option<Self> {
+        U256::checked_div(self, rhs)
+    }
+    fn checked_rem(self, rhs: Self) -> Option<Self> {
+        U256::checked_rem(self, rhs)
+    }
+    fn checked_pow(self, exp: u32) -> Option<Self> {
+        U256::checked_pow(self, exp)
+    }
+    fn saturating_add(self, rhs: Self) -> Self {
+        U256::saturating_add(self, rhs)
+    }
+    fn saturating_sub(self, rhs: Self) -> Self {
+        U256::saturating_sub(self, rhs)
+    }
+    fn saturating_mul(self, rhs: Self) -> Self {
+        U256::saturating_mul(self, rhs)
+    }
+    fn is_zero(&self) -> bool {
+        U256::is_zero(self)
+    }
+}
+
+/// Adds two numbers. It returns `Overflow` error if the result doesn't fit in the type.
+pub fn add<T: Integer>(a: T, b: T) -> Result<T, Error> {
+    a.checked_add(b).ok_or(Error::Overflow)
+}
+
+/// Subtracts `b` from `a`. It returns `Overflow` error if the result doesn't fit in the type.
+pub fn sub<T: Integer>(a: T, b: T) -> Result<T, Error> {
+    a.checked_sub(b).ok_or(Error::Overflow)
+}
+
+/// Multiplies two numbers. It returns `Overflow` error if the result doesn't fit in the type.
+pub fn mul<T: Integer>(a: T, b: T) -> Result<T, Error> {
+    a.checked_mul(b).ok_or(Error::Overflow)
+}
+
+/// Divides `a` by `b`. It returns `DivisionByZero` error if `b` is zero and
+/// `Overflow` error if the result doesn't fit in the type (e.g. `i32::MIN / -1`).
+pub fn div<T: Integer>(a: T, b: T) -> Result<T, Error> {
+    if b.is_zero() {
+        return Err(Error::DivisionByZero);
+    }
+    a.checked_div(b).ok_or(Error::Overflow)
+}
+
+/// Returns the remainder of dividing `a` by `b`.
+/// It returns `DivisionByZero` error if `b` is zero.
+pub fn rem<T: Integer>(a: T, b: T) -> Result<T, Error> {
+    if b.is_zero() {
+        return Err(Error::DivisionByZero);
+    }
+    a.checked_rem(b).ok_or(Error::Overflow)
+}
+
+/// Raises `a` to the power of `exp`. It returns `Overflow` error if the result doesn't fit in the type.
+pub fn pow<T: Integer>(a: T, exp: u32) -> Result<T, Error> {
+    a.checked_pow(exp).ok_or(Error::Overflow)
+}
+
+/// Adds two numbers, saturating at the numeric bounds.
+pub fn saturating_add<T: Integer>(a: T, b: T) -> T {
+    a.saturating_add(b)
+}
+
+/// Subtracts `b` from `a`, saturating at the numeric bounds.
+pub fn saturating_sub<T: Integer>(a: T, b: T) -> T {
+    a.saturating_sub(b)
+}
+
+/// Multiplies two numbers, saturating at the numeric bounds.
+pub fn saturating_mul<T: Integer>(a: T, b: T) -> T {
+    a.saturating_mul(b)
+}
+
+/// Computes `a * b / c` without overflowing in the intermediate product.
+/// The result is rounded according to `rounding`.
+pub fn mul_div(a: u128, b: u128, c: u128, rounding: Rounding) -> Result<u128, Error> {
+    let value = decimal::div_round(
+        U256::from(a).checked_mul(U256::from(b)).unwrap(), // 128 bits * 128 bits fits in 256 bits
+        U256::from(c),
+        rounding,
+    )?;
+    value.to_u128().ok_or(Error::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked() {
+        assert_eq!(add(1i32, 2), Ok(3));
+        assert_eq!(add(i32::MAX, 1), Err(Error::Overflow));
+        assert_eq!(sub(0u64, 1), Err(Error::Overflow));
+        assert_eq!(sub(-1i8, i8::MAX), Ok(i8::MIN));
+        assert_eq!(mul(u128::MAX, 2), Err(Error::Overflow));
+        assert_eq!(div(7i64, 2), Ok(3));
+        assert_eq!(div(7i64, 0), Err(Error::DivisionByZero));
+        assert_eq!(div(i32::MIN, -1), Err(Error::Overflow));
+        assert_eq!(rem(7u8, 4), Ok(3));
+        assert_eq!(rem(7u8, 0), Err(Error::DivisionByZero));
+        assert_eq!(pow(10u64, 19), Ok(10_000_000_000_000_000_000));
+        assert_eq!(pow(10u64, 20), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn test_saturating() {
+        assert_eq!(saturating_add(i32::MAX, 1), i32::MAX);
+        assert_eq!(saturating_sub(0u32, 1), 0);
+        assert_eq!(saturating_mul(i16::MIN, 2), i16::MIN);
+        assert_eq!(saturating_add(U256::MAX, U256::ONE), U256::MAX);
+    }
+
+    #[test]
+    fn test_u256() {
+        assert_eq!(add(U256::MAX, U256::ONE), Err(Error::Overflow));
+        assert_eq!(div(U256::ONE, U256::ZERO), Err(Error::DivisionByZero));
+        assert_eq!(
+            mul(U256::from(u128::MAX), U256::from(2u8)),
+            Ok(U256::from(u128::MAX)
+                .checked_add(U256::from(u128::MAX))
+                .unwrap())
+        );
+    }
+
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(mul_div(u128::MAX, 3, 3, Rounding::Down), Ok(u128::MAX));
+        assert_eq!(
+            mul_div(u128::MAX, 3, 2, Rounding::Down),
+            Err(Error::Overflow)
+        );
+        assert_eq!(mul_div(10, 1, 3, Rounding::Down), Ok(3));
+        assert_eq!(mul_div(10, 1, 3, Rounding::Up), Ok(4));
+        assert_eq!(mul_div(1, 1, 0, Rounding::Down), Err(Error::DivisionByZero));
+    }
+}
@@ -1,39 +1,336 @@
 //! Mocking Context for testing contracts
 
-use crate::error::HostError;
-use crate::storage::Storage;
+use crate::error::{HostError, HostErrorKind};
+use crate::storage::{ReadOnlyStorage, Storage};
+use alloc::string::String;
 use alloc::vec::Vec;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+/// The magic number that marks the beginning of a collection header.
+const BOOM: u32 = 0xb3000000;
+
+/// Number of bytes in each line of the hexdump.
+const DUMP_WIDTH: usize = 16;
 
 /// `MockStorage` mocks the storage for testing purpose.
+#[derive(Clone)]
 pub struct MockStorage {
     storage: RefCell<Vec<u8>>,
+    faults: RefCell<Vec<Fault>>,
+    writes: Cell<u32>,
+    reads: Cell<u32>,
+}
+
+/// `Fault` defines a failure that can be injected into the mocked storage.
+/// It helps to test the error paths of contracts and collections.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Fails the Nth write (starting from 1) with the given host error.
+    NthWrite {
+        /// The number of the write to fail
+        n: u32,
+        /// The kind of the host error
+        kind: HostErrorKind,
+    },
+    /// Fails the reads overlapping the given offset range with the given host error.
+    ReadRange {
+        /// The range of offsets
+        range: Range<u32>,
+        /// The kind of the host error
+        kind: HostErrorKind,
+    },
+    /// Fails the writes overlapping the given offset range with the given host error.
+    WriteRange {
+        /// The range of offsets
+        range: Range<u32>,
+        /// The kind of the host error
+        kind: HostErrorKind,
+    },
+    /// Simulates a crash after the given number of writes.
+    /// All the writes after that fail with the given host error.
+    CrashAfter {
+        /// The number of successful writes before the crash
+        writes: u32,
+        /// The kind of the host error
+        kind: HostErrorKind,
+    },
+}
+
+/// `Change` is a range of bytes that differs between two storage files.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The offset of the first changed byte
+    pub offset: u32,
+    /// The bytes in the original storage
+    pub old: Vec<u8>,
+    /// The bytes in the other storage
+    pub new: Vec<u8>,
+}
+
+impl fmt::Debug for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x}: {:02x?} -> {:02x?}",
+            self.offset, self.old, self.new
+        )
+    }
 }
 
 impl MockStorage {
     /// instantiates a new mock
     pub fn new(size: usize) -> Self {
-        let storage = RefCell::new(alloc::vec![0; size].to_vec());
-        Self { storage }
+        Self::from_bytes(alloc::vec![0; size].to_vec())
+    }
+
+    /// instantiates a new mock from a storage image
+    pub fn from_bytes(image: Vec<u8>) -> Self {
+        Self {
+            storage: RefCell::new(image),
+            faults: RefCell::new(Vec::new()),
+            writes: Cell::new(0),
+            reads: Cell::new(0),
+        }
+    }
+
+    /// injects a fault into the storage.
+    pub fn inject(&self, fault: Fault) {
+        self.faults.borrow_mut().push(fault);
+    }
+
+    /// removes all the injected faults.
+    pub fn clear_faults(&self) {
+        self.faults.borrow_mut().clear();
+    }
+
+    /// resets the read and write counters.
+    pub fn reset_counters(&self) {
+        self.writes.set(0);
+        self.reads.set(0);
+    }
+
+    /// returns the number of the writes, including the failed ones.
+    pub fn write_count(&self) -> u32 {
+        self.writes.get()
+    }
+
+    /// returns the number of the reads, including the failed ones.
+    pub fn read_count(&self) -> u32 {
+        self.reads.get()
+    }
+
+    fn check_read(&self, offset: u32, len: u32) -> Result<(), HostError> {
+        self.reads.set(self.reads.get() + 1);
+        for fault in self.faults.borrow().iter() {
+            if let Fault::ReadRange { range, kind } = fault {
+                if overlaps(range, offset, len) {
+                    return Err((*kind).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_write(&self, offset: u32, len: u32) -> Result<(), HostError> {
+        let n = self.writes.get() + 1;
+        self.writes.set(n);
+
+        for fault in self.faults.borrow().iter() {
+            match fault {
+                Fault::NthWrite { n: nth, kind } if *nth == n => {
+                    return Err((*kind).into());
+                }
+                Fault::WriteRange { range, kind } if overlaps(range, offset, len) => {
+                    return Err((*kind).into());
+                }
+                Fault::CrashAfter { writes, kind } if n > *writes => {
+                    return Err((*kind).into());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// returns a copy of the whole storage file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.storage.borrow().clone()
+    }
+
+    /// returns the size of the storage file
+    pub fn size(&self) -> usize {
+        self.storage.borrow().len()
+    }
+
+    /// takes a snapshot of the current storage.
+    /// The snapshot has only the bytes, without the faults and the counters.
+    /// It can be restored later or compared with the storage by `diff`.
+    pub fn snapshot(&self) -> MockStorage {
+        Self::from_bytes(self.to_bytes())
+    }
+
+    /// restores the storage to the given snapshot
+    pub fn restore(&self, snapshot: &MockStorage) {
+        self.storage.replace(snapshot.to_bytes());
+    }
+
+    /// compares this storage with the other one and returns the changed byte ranges.
+    /// Consecutive changed bytes are merged into one range.
+    /// If the sizes differ, the extra bytes are reported as a change with an empty side.
+    pub fn diff(&self, other: &MockStorage) -> Vec<Change> {
+        let old = self.storage.borrow();
+        let new = other.storage.borrow();
+        let common = old.len().min(new.len());
+        let mut changes = Vec::new();
+
+        let mut i = 0;
+        while i < common {
+            if old[i] == new[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < common && old[i] != new[i] {
+                i += 1;
+            }
+            changes.push(Change {
+                offset: start as u32,
+                old: old[start..i].to_vec(),
+                new: new[start..i].to_vec(),
+            });
+        }
+
+        if old.len() != new.len() {
+            changes.push(Change {
+                offset: common as u32,
+                old: old[common..].to_vec(),
+                new: new[common..].to_vec(),
+            });
+        }
+
+        changes
+    }
+
+    /// returns the hexdump of the given range of the storage.
+    pub fn hexdump(&self, offset: u32, len: u32) -> String {
+        let storage = self.storage.borrow();
+        let start = (offset as usize).min(storage.len());
+        let end = (start + len as usize).min(storage.len());
+
+        let mut out = String::new();
+        for (i, line) in storage[start..end].chunks(DUMP_WIDTH).enumerate() {
+            write_line(&mut out, start + i * DUMP_WIDTH, line).unwrap();
+        }
+        out
+    }
+
+    /// returns a human readable dump of the whole storage.
+    /// Lines containing only zeros are collapsed into `*`,
+    /// and collection headers (starting with the `boom` magic) are annotated.
+    pub fn dump(&self) -> String {
+        let storage = self.storage.borrow();
+        let mut out = String::new();
+
+        let mut skipped = false;
+        for (i, line) in storage.chunks(DUMP_WIDTH).enumerate() {
+            if line.iter().all(|b| *b == 0) {
+                if !skipped {
+                    out.push_str("*\n");
+                    skipped = true;
+                }
+                continue;
+            }
+            skipped = false;
+            write_line(&mut out, i * DUMP_WIDTH, line).unwrap();
+        }
+
+        let boom = BOOM.to_ne_bytes();
+        for offset in 0..storage.len().saturating_sub(15) {
+            if storage[offset..offset + 4] == boom {
+                write_header(&mut out, offset, &storage[offset..offset + 16]).unwrap();
+            }
+        }
+        out
+    }
+
+    /// loads a storage image from the given file.
+    #[cfg(any(test, feature = "std"))]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::from_bytes(std::fs::read(path)?))
     }
+
+    /// saves the storage image into the given file.
+    #[cfg(any(test, feature = "std"))]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, &*self.storage.borrow())
+    }
+}
+
+fn overlaps(range: &Range<u32>, offset: u32, len: u32) -> bool {
+    let end = offset as u64 + len.max(1) as u64;
+    (offset as u64) < range.end as u64 && end > range.start as u64
+}
+
+fn write_line(out: &mut String, offset: usize, line: &[u8]) -> fmt::Result {
+    write!(out, "{:08x} ", offset)?;
+    for i in 0..DUMP_WIDTH {
+        if i % 8 == 0 {
+            out.push(' ');
+        }
+        match line.get(i) {
+            Some(b) => write!(out, "{:02x} ", b)?,
+            None => out.push_str("   "),
+        }
+    }
+    out.push_str(" |");
+    for b in line {
+        match b.is_ascii_graphic() {
+            true => out.push(*b as char),
+            false => out.push('.'),
+        }
+    }
+    out.push_str("|\n");
+    Ok(())
+}
+
+fn write_header(out: &mut String, offset: usize, header: &[u8]) -> fmt::Result {
+    let u16_at = |i: usize| u16::from_ne_bytes(header[i..i + 2].try_into().unwrap());
+    let u32_at = |i: usize| u32::from_ne_bytes(header[i..i + 4].try_into().unwrap());
+
+    writeln!(
+        out,
+        "header at {:#010x}: lens: {}/{}, size: {}, capacity: {}",
+        offset,
+        u16_at(4),
+        u16_at(6),
+        u32_at(8),
+        u32_at(12),
+    )
 }
 
 impl Storage for MockStorage {
     fn swrite(&self, offset: u32, data: &[u8]) -> Result<(), HostError> {
+        self.check_write(offset, data.len() as u32)?;
         if offset as usize + data.len() > self.storage.borrow().len() {
-            return Err(HostError { code: 1 });
+            return Err(HostErrorKind::OutOfBounds.into());
         }
         for (i, d) in data.iter().enumerate() {
             self.storage.borrow_mut()[i + offset as usize] = *d;
         }
         Ok(())
     }
+}
 
+impl ReadOnlyStorage for MockStorage {
     fn sread(&self, offset: u32, length: u32) -> Result<Vec<u8>, HostError> {
-        if (offset + length) as usize > self.storage.borrow().len() {
-            return Err(HostError { code: 1 });
+        self.check_read(offset, length)?;
+        let end = offset as usize + length as usize;
+        if end > self.storage.borrow().len() {
+            return Err(HostErrorKind::OutOfBounds.into());
         }
-        let c = &self.storage.borrow()[offset as usize..(offset + length) as usize];
+        let c = &self.storage.borrow()[offset as usize..end];
         Ok(c.into())
     }
 }
@@ -42,3 +339,211 @@ impl Storage for MockStorage {
 pub fn mock_storage(storage_size: usize) -> MockStorage {
     MockStorage::new(storage_size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::vector::StorageVec;
+
+    #[test]
+    fn test_snapshot_restore() {
+        let storage = mock_storage(16);
+        storage.swrite_u32(0, 1).unwrap();
+
+        let snapshot = storage.snapshot();
+        storage.swrite_u32(0, 2).unwrap();
+        assert_eq!(storage.sread_u32(0).unwrap(), 2);
+
+        storage.restore(&snapshot);
+        assert_eq!(storage.sread_u32(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_bytes_only() {
+        let storage = mock_storage(16);
+        storage.swrite_u32(0, 1).unwrap();
+        storage.inject(Fault::CrashAfter {
+            writes: 0,
+            kind: HostErrorKind::StorageFull,
+        });
+
+        let snapshot = storage.snapshot();
+        assert_eq!(snapshot.write_count(), 0);
+        assert_eq!(snapshot.read_count(), 0);
+        assert!(storage.swrite_u32(0, 2).is_err());
+        snapshot.swrite_u32(0, 2).unwrap();
+        assert_eq!(snapshot.sread_u32(0).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_diff() {
+        let storage = mock_storage(16);
+        let snapshot = storage.snapshot();
+        assert!(snapshot.diff(&storage).is_empty());
+
+        storage.swrite(2, &[1, 2, 3]).unwrap();
+        storage.swrite_u8(8, 4).unwrap();
+
+        let changes = snapshot.diff(&storage);
+        assert_eq!(
+            changes,
+            alloc::vec![
+                Change {
+                    offset: 2,
+                    old: alloc::vec![0, 0, 0],
+                    new: alloc::vec![1, 2, 3],
+                },
+                Change {
+                    offset: 8,
+                    old: alloc::vec![0],
+                    new: alloc::vec![4],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_size() {
+        let storage_1 = mock_storage(4);
+        let storage_2 = mock_storage(6);
+
+        let changes = storage_1.diff(&storage_2);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].offset, 4);
+        assert!(changes[0].old.is_empty());
+        assert_eq!(changes[0].new, alloc::vec![0, 0]);
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let storage = mock_storage(20);
+        storage.swrite(0, b"kelk").unwrap();
+
+        let dump = storage.hexdump(0, 20);
+        let mut lines = dump.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "00000000  6b 65 6c 6b 00 00 00 00  00 00 00 00 00 00 00 00  |kelk............|"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "00000010  00 00 00 00                                       |....|"
+        );
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_dump() {
+        let storage = mock_storage(1024);
+        let mut vec = StorageVec::<i32>::create(&storage, 512, 16).unwrap();
+        vec.push(1).unwrap();
+
+        let dump = storage.dump();
+        assert!(dump.starts_with("*\n00000200"));
+        assert!(dump.contains("header at 0x00000200: lens: 0/4, size: 1, capacity: 16"));
+    }
+
+    #[test]
+    fn test_fault_nth_write() {
+        let storage = mock_storage(16);
+        storage.inject(Fault::NthWrite {
+            n: 2,
+            kind: HostErrorKind::StorageFull,
+        });
+
+        assert!(storage.swrite_u8(0, 1).is_ok());
+        assert_eq!(
+            storage.swrite_u8(1, 1).unwrap_err().kind,
+            HostErrorKind::StorageFull
+        );
+        assert!(storage.swrite_u8(2, 1).is_ok());
+        assert_eq!(storage.write_count(), 3);
+
+        storage.clear_faults();
+        assert!(storage.swrite_u8(1, 1).is_ok());
+        assert_eq!(storage.write_count(), 4);
+
+        storage.reset_counters();
+        assert_eq!(storage.write_count(), 0);
+    }
+
+    #[test]
+    fn test_read_count() {
+        let storage = mock_storage(16);
+        storage.sread_u8(0).unwrap();
+        storage.sread_u64(1).unwrap();
+        assert!(storage.sread_u8(16).is_err());
+        assert_eq!(storage.read_count(), 3);
+
+        storage.reset_counters();
+        assert_eq!(storage.read_count(), 0);
+    }
+
+    #[test]
+    fn test_fault_ranges() {
+        let storage = mock_storage(16);
+        storage.inject(Fault::ReadRange {
+            range: 4..8,
+            kind: HostErrorKind::PermissionDenied,
+        });
+        storage.inject(Fault::WriteRange {
+            range: 8..12,
+            kind: HostErrorKind::OutOfGas,
+        });
+
+        assert!(storage.sread_u32(0).is_ok());
+        assert_eq!(
+            storage.sread_u32(2).unwrap_err().kind,
+            HostErrorKind::PermissionDenied
+        );
+        assert!(storage.sread_u32(8).is_ok());
+
+        assert!(storage.swrite_u32(4, 1).is_ok());
+        assert_eq!(
+            storage.swrite_u32(6, 1).unwrap_err().kind,
+            HostErrorKind::OutOfGas
+        );
+        assert!(storage.swrite_u32(12, 1).is_ok());
+    }
+
+    #[test]
+    fn test_fault_crash() {
+        let storage = mock_storage(16);
+        storage.inject(Fault::CrashAfter {
+            writes: 1,
+            kind: HostErrorKind::from(9),
+        });
+
+        assert!(storage.swrite_u8(0, 1).is_ok());
+        assert_eq!(storage.swrite_u8(1, 1).unwrap_err().code(), 9);
+        assert_eq!(storage.swrite_u8(2, 1).unwrap_err().code(), 9);
+        assert_eq!(storage.sread_u8(0).unwrap(), 1);
+        assert_eq!(storage.sread_u8(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let storage = mock_storage(16);
+        assert_eq!(
+            storage.sread(u32::MAX, 2).unwrap_err().kind,
+            HostErrorKind::OutOfBounds
+        );
+        assert_eq!(
+            storage.swrite(15, &[1, 2]).unwrap_err().kind,
+            HostErrorKind::OutOfBounds
+        );
+    }
+
+    #[test]
+    fn test_load_save() {
+        let path = std::env::temp_dir().join("kelk_mock_storage_test.bin");
+        let storage = mock_storage(8);
+        storage.swrite_u64(0, 0x0123456789abcdef).unwrap();
+        storage.save(&path).unwrap();
+
+        let loaded = MockStorage::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.size(), 8);
+        assert_eq!(loaded.sread_u64(0).unwrap(), 0x0123456789abcdef);
+    }
+}
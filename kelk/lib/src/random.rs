@@ -0,0 +1,204 @@
+//! Deterministic pseudo-random number generator.
+//!
+//! Contracts must be deterministic, so they can't use an entropy source of their own.
+//! Instead they seed this generator from the host, e.g. with the random bytes of the context,
+//! which are the same for all the validators.
+//!
+//! The generator is xoshiro256** and the seed expansion is SplitMix64.
+//! They are fast and have a small state, but they are NOT cryptographically secure.
+
+/// SplitMix64 generator. It is used for expanding a 64-bit seed into the state of `Rng`.
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// creates a new generator from the given seed
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    /// returns the next random number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Rng is a xoshiro256** pseudo-random number generator.
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    /// creates a new generator from a 32-byte seed, e.g. the random bytes of the context.
+    /// The all-zero seed is not a valid state for xoshiro, so it is expanded like `seed_from_u64(0)`.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let mut state = [0; 4];
+        for (i, chunk) in seed.chunks_exact(8).enumerate() {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(chunk);
+            state[i] = u64::from_le_bytes(buf);
+        }
+        if state == [0; 4] {
+            return Self::seed_from_u64(0);
+        }
+        Rng { state }
+    }
+
+    /// creates a new generator from a 64-bit seed.
+    /// The seed is expanded with SplitMix64.
+    pub fn seed_from_u64(seed: u64) -> Self {
+        let mut sm = SplitMix64::new(seed);
+        Rng {
+            state: [sm.next_u64(), sm.next_u64(), sm.next_u64(), sm.next_u64()],
+        }
+    }
+
+    /// returns the next random `u64`
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
+    }
+
+    /// returns the next random `u32`
+    pub fn next_u32(&mut self) -> u32 {
+        // The higher bits have better quality
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// fills the buffer with random bytes
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// returns a uniformly distributed random number in `0..bound`.
+    /// It returns `None` if `bound` is zero.
+    pub fn below(&mut self, bound: u64) -> Option<u64> {
+        if bound == 0 {
+            return None;
+        }
+        // Rejecting the values in the last incomplete interval removes the modulo bias.
+        let zone = u64::MAX - (u64::MAX - bound + 1) % bound;
+        loop {
+            let value = self.next_u64();
+            if value <= zone {
+                return Some(value % bound);
+            }
+        }
+    }
+
+    /// shuffles the slice in place with the Fisher–Yates algorithm.
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            // `below` never fails here since `i + 1` is not zero
+            let j = self.below(i as u64 + 1).unwrap_or(0) as usize;
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64() {
+        let mut sm = SplitMix64::new(1234567);
+        assert_eq!(sm.next_u64(), 6457827717110365317);
+        assert_eq!(sm.next_u64(), 3203168211198807973);
+        assert_eq!(sm.next_u64(), 9817491932198370423);
+    }
+
+    #[test]
+    fn test_xoshiro() {
+        // Reference values from the C implementation with the state {1, 2, 3, 4}
+        let mut rng = Rng {
+            state: [1, 2, 3, 4],
+        };
+        assert_eq!(rng.next_u64(), 11520);
+        assert_eq!(rng.next_u64(), 0);
+        assert_eq!(rng.next_u64(), 1509978240);
+        assert_eq!(rng.next_u64(), 1215971899390074240);
+    }
+
+    #[test]
+    fn test_from_seed() {
+        let mut seed = [0; 32];
+        seed[0] = 1;
+        seed[8] = 2;
+        seed[16] = 3;
+        seed[24] = 4;
+        assert_eq!(Rng::from_seed(seed).next_u64(), 11520);
+
+        let mut zero = Rng::from_seed([0; 32]);
+        assert_eq!(zero.next_u64(), Rng::seed_from_u64(0).next_u64());
+        assert_ne!(zero.next_u64(), 0);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = Rng::seed_from_u64(42);
+        let mut b = Rng::seed_from_u64(42);
+        let mut c = Rng::seed_from_u64(43);
+        for _ in 0..10 {
+            let value = a.next_u64();
+            assert_eq!(value, b.next_u64());
+            assert_ne!(value, c.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_fill_bytes() {
+        let mut rng = Rng::seed_from_u64(1);
+        let first = rng.next_u64().to_le_bytes();
+
+        let mut rng = Rng::seed_from_u64(1);
+        let mut buf = [0; 11];
+        rng.fill_bytes(&mut buf);
+        assert_eq!(buf[..8], first);
+        assert_ne!(buf[8..], [0; 3]);
+    }
+
+    #[test]
+    fn test_below() {
+        let mut rng = Rng::seed_from_u64(7);
+        assert_eq!(rng.below(0), None);
+        assert_eq!(rng.below(1), Some(0));
+
+        let mut counts = [0; 6];
+        for _ in 0..6000 {
+            counts[rng.below(6).unwrap() as usize] += 1;
+        }
+        for count in counts {
+            assert!(count > 800 && count < 1200);
+        }
+        assert!(rng.below(u64::MAX).is_some());
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut rng = Rng::seed_from_u64(3);
+        let mut values = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        rng.shuffle(&mut values);
+        assert_ne!(values, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        values.sort();
+        assert_eq!(values, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,458 @@
+//! Machine-readable description of the contract's messages.
+//!
+//! Every message and error type that crosses the contract boundary can derive [`Schema`].
+//! The derived schema mirrors the CBOR layout produced by `minicbor`:
+//! variant indices, field indices and field types are taken from the `#[n(...)]`,
+//! `#[b(...)]` and `#[cbor(...)]` attributes.
+//! The schemas can be exported as JSON, so clients can be generated and checked against the contract.
+//!
+//! ```
+//! use kelk_lib::schema::{self, Schema};
+//! use minicbor::{Decode, Encode};
+//!
+//! #[derive(Encode, Decode, Schema)]
+//! enum ProcMsg {
+//!     #[n(0)]
+//!     Add {
+//!         #[n(0)]
+//!         a: i32,
+//!         #[n(1)]
+//!         b: i32,
+//!     },
+//! }
+//!
+//! let json = schema::to_json(&[ProcMsg::schema()]);
+//! assert!(json.contains("\"name\": \"Add\""));
+//! ```
+
+use crate::error::HostErrorKind;
+use alloc::string::String;
+use core::fmt::Write;
+
+pub use kelk_derive::Schema;
+
+/// Types that can describe their CBOR layout.
+pub trait Schema {
+    /// returns the schema of the type
+    fn schema() -> TypeSchema;
+}
+
+/// CBOR encoding of a struct or an enum variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Fields are encoded as a CBOR array, ordered by their indices
+    Array,
+    /// Fields are encoded as a CBOR map, keyed by their indices
+    Map,
+    /// Only the variant index is encoded, as a CBOR integer
+    IndexOnly,
+}
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Array => "array",
+            Encoding::Map => "map",
+            Encoding::IndexOnly => "index_only",
+        }
+    }
+}
+
+/// A field of a struct or an enum variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// The name of the field, or `None` for tuple fields
+    pub name: Option<&'static str>,
+    /// The CBOR index of the field
+    pub index: u32,
+    /// The Rust type of the field, as written in the source
+    pub ty: &'static str,
+}
+
+/// A variant of an enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Variant {
+    /// The name of the variant
+    pub name: &'static str,
+    /// The CBOR index of the variant
+    pub index: u32,
+    /// The encoding of the variant's fields
+    pub encoding: Encoding,
+    /// The fields of the variant
+    pub fields: &'static [Field],
+}
+
+/// The layout of a type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// The type is encoded as another type, e.g. a transparent wrapper
+    Alias(&'static str),
+    /// A struct with its fields
+    Struct {
+        /// The encoding of the fields
+        encoding: Encoding,
+        /// The fields of the struct
+        fields: &'static [Field],
+    },
+    /// An enum with its variants
+    Enum {
+        /// The encoding of the enum
+        encoding: Encoding,
+        /// The variants of the enum
+        variants: &'static [Variant],
+    },
+}
+
+/// The schema of a type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeSchema {
+    /// The name of the type
+    pub name: &'static str,
+    /// The layout of the type
+    pub kind: Kind,
+}
+
+impl TypeSchema {
+    /// returns the schema as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write_type(&mut out, self, 0);
+        out
+    }
+}
+
+/// returns the list of schemas as a JSON array
+pub fn to_json(schemas: &[TypeSchema]) -> String {
+    let mut out = String::new();
+    out.push('[');
+    for (i, schema) in schemas.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(&mut out, 1);
+        write_type(&mut out, schema, 1);
+    }
+    newline(&mut out, 0);
+    out.push(']');
+    out
+}
+
+/// writes the schemas as a JSON array into the file at `path`.
+/// It is intended to be called from a `schema` binary of the contract.
+#[cfg(feature = "std")]
+pub fn export(path: &str, schemas: &[TypeSchema]) -> std::io::Result<()> {
+    let mut json = to_json(schemas);
+    json.push('\n');
+    std::fs::write(path, json)
+}
+
+/// checks that the file at `path` keeps the schemas, e.g. the committed schema of a contract.
+/// It returns an `InvalidData` error if the file is out of date.
+/// If the `KELK_UPDATE_SCHEMA` environment variable is set, the file is written instead.
+/// It is intended to be called from a test of the contract.
+#[cfg(feature = "std")]
+pub fn check(path: &str, schemas: &[TypeSchema]) -> std::io::Result<()> {
+    if std::env::var_os("KELK_UPDATE_SCHEMA").is_some() {
+        return export(path, schemas);
+    }
+
+    let mut json = to_json(schemas);
+    json.push('\n');
+    if std::fs::read_to_string(path)? != json {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            std::format!("{path} is out of date, set KELK_UPDATE_SCHEMA to update it"),
+        ));
+    }
+    Ok(())
+}
+
+fn newline(out: &mut String, depth: usize) {
+    out.push('\n');
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn write_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_key(out: &mut String, key: &str, depth: usize) {
+    newline(out, depth);
+    write_str(out, key);
+    out.push_str(": ");
+}
+
+fn write_type(out: &mut String, schema: &TypeSchema, depth: usize) {
+    out.push('{');
+    write_key(out, "name", depth + 1);
+    write_str(out, schema.name);
+    out.push(',');
+    match schema.kind {
+        Kind::Alias(ty) => {
+            write_key(out, "kind", depth + 1);
+            write_str(out, "alias");
+            out.push(',');
+            write_key(out, "type", depth + 1);
+            write_str(out, ty);
+        }
+        Kind::Struct { encoding, fields } => {
+            write_key(out, "kind", depth + 1);
+            write_str(out, "struct");
+            out.push(',');
+            write_key(out, "encoding", depth + 1);
+            write_str(out, encoding.as_str());
+            out.push(',');
+            write_key(out, "fields", depth + 1);
+            write_fields(out, fields, depth + 1);
+        }
+        Kind::Enum { encoding, variants } => {
+            write_key(out, "kind", depth + 1);
+            write_str(out, "enum");
+            out.push(',');
+            write_key(out, "encoding", depth + 1);
+            write_str(out, encoding.as_str());
+            out.push(',');
+            write_key(out, "variants", depth + 1);
+            out.push('[');
+            for (i, variant) in variants.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 2);
+                out.push('{');
+                write_key(out, "name", depth + 3);
+                write_str(out, variant.name);
+                out.push(',');
+                write_key(out, "index", depth + 3);
+                let _ = write!(out, "{}", variant.index);
+                out.push(',');
+                write_key(out, "encoding", depth + 3);
+                write_str(out, variant.encoding.as_str());
+                out.push(',');
+                write_key(out, "fields", depth + 3);
+                write_fields(out, variant.fields, depth + 3);
+                newline(out, depth + 2);
+                out.push('}');
+            }
+            if !variants.is_empty() {
+                newline(out, depth + 1);
+            }
+            out.push(']');
+        }
+    }
+    newline(out, depth);
+    out.push('}');
+}
+
+fn write_fields(out: &mut String, fields: &[Field], depth: usize) {
+    out.push('[');
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, depth + 1);
+        out.push_str("{ \"name\": ");
+        match field.name {
+            Some(name) => write_str(out, name),
+            None => out.push_str("null"),
+        }
+        let _ = write!(out, ", \"index\": {}, \"type\": ", field.index);
+        write_str(out, field.ty);
+        out.push_str(" }");
+    }
+    if !fields.is_empty() {
+        newline(out, depth);
+    }
+    out.push(']');
+}
+
+impl Schema for HostErrorKind {
+    fn schema() -> TypeSchema {
+        TypeSchema {
+            name: "HostErrorKind",
+            kind: Kind::Alias("i32"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use alloc::vec::Vec;
+    use minicbor::{Decode, Encode};
+
+    #[derive(Encode, Decode, Schema)]
+    #[cbor(map)]
+    struct Config {
+        #[n(0)]
+        owner: [u8; 4],
+        #[n(2)]
+        limit: Option<u32>,
+        #[b(3)]
+        memo: Vec<u8>,
+    }
+
+    #[derive(Encode, Decode, Schema)]
+    #[cbor(transparent)]
+    struct Amount(#[n(0)] u64);
+
+    #[derive(Encode, Decode, Schema)]
+    #[cbor(index_only)]
+    enum Mode {
+        #[n(0)]
+        Fast,
+        #[n(5)]
+        Slow,
+    }
+
+    #[derive(Encode, Decode, Schema)]
+    enum Msg {
+        #[n(0)]
+        Set(#[n(0)] u8, #[n(1)] bool),
+        #[n(1)]
+        #[cbor(map)]
+        Transfer {
+            #[n(0)]
+            to: [u8; 4],
+            #[n(1)]
+            amount: Amount,
+        },
+    }
+
+    #[test]
+    fn test_struct() {
+        let schema = Config::schema();
+        assert_eq!(schema.name, "Config");
+        assert_eq!(
+            schema.kind,
+            Kind::Struct {
+                encoding: Encoding::Map,
+                fields: &[
+                    Field {
+                        name: Some("owner"),
+                        index: 0,
+                        ty: "[u8; 4]"
+                    },
+                    Field {
+                        name: Some("limit"),
+                        index: 2,
+                        ty: "Option<u32>"
+                    },
+                    Field {
+                        name: Some("memo"),
+                        index: 3,
+                        ty: "Vec<u8>"
+                    },
+                ],
+            }
+        );
+        assert_eq!(Amount::schema().kind, Kind::Alias("u64"));
+    }
+
+    #[test]
+    fn test_enum() {
+        match Mode::schema().kind {
+            Kind::Enum { encoding, variants } => {
+                assert_eq!(encoding, Encoding::IndexOnly);
+                assert_eq!(variants[1].name, "Slow");
+                assert_eq!(variants[1].index, 5);
+            }
+            _ => panic!("expected enum"),
+        }
+
+        match Msg::schema().kind {
+            Kind::Enum { encoding, variants } => {
+                assert_eq!(encoding, Encoding::Array);
+                assert_eq!(variants[0].encoding, Encoding::Array);
+                assert_eq!(variants[0].fields[1].name, None);
+                assert_eq!(variants[0].fields[1].ty, "bool");
+                assert_eq!(variants[1].encoding, Encoding::Map);
+                assert_eq!(variants[1].fields[1].ty, "Amount");
+            }
+            _ => panic!("expected enum"),
+        }
+    }
+
+    #[test]
+    fn test_error() {
+        match Error::schema().kind {
+            Kind::Enum { variants, .. } => {
+                // The indices are stable, so the variants can only be appended.
+                let find = |name| variants.iter().find(|v| v.name == name).unwrap();
+                assert_eq!(find("HostError").index, 0);
+                assert_eq!(find("HostError").fields[0].ty, "HostErrorKind");
+                assert_eq!(find("InvalidVersion").index, 11);
+                assert_eq!(find("InvalidProof").index, 12);
+                assert_eq!(find("DuplicateKey").index, 13);
+            }
+            _ => panic!("expected enum"),
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let expected = r#"[
+  {
+    "name": "Amount",
+    "kind": "alias",
+    "type": "u64"
+  },
+  {
+    "name": "Msg",
+    "kind": "enum",
+    "encoding": "array",
+    "variants": [
+      {
+        "name": "Set",
+        "index": 0,
+        "encoding": "array",
+        "fields": [
+          { "name": null, "index": 0, "type": "u8" },
+          { "name": null, "index": 1, "type": "bool" }
+        ]
+      },
+      {
+        "name": "Transfer",
+        "index": 1,
+        "encoding": "map",
+        "fields": [
+          { "name": "to", "index": 0, "type": "[u8; 4]" },
+          { "name": "amount", "index": 1, "type": "Amount" }
+        ]
+      }
+    ]
+  }
+]"#;
+        assert_eq!(to_json(&[Amount::schema(), Msg::schema()]), expected);
+    }
+
+    #[test]
+    fn test_json_escape() {
+        let schema = TypeSchema {
+            name: "a\"b\\c\n",
+            kind: Kind::Struct {
+                encoding: Encoding::Array,
+                fields: &[],
+            },
+        };
+        assert_eq!(
+            schema.to_json(),
+            "{\n  \"name\": \"a\\\"b\\\\c\\n\",\n  \"kind\": \"struct\",\n  \"encoding\": \"array\",\n  \"fields\": []\n}"
+        );
+    }
+}
@@ -1,12 +1,16 @@
 //! Storage trait to read and write primitives
 
-use crate::error::HostError;
+use crate::error::{Error, HostError};
+use crate::hash::NoPadding;
+use crate::U256;
 use ::core::result::Result;
 use alloc::{slice, vec::Vec};
+use core::marker::PhantomData;
 use core::mem::{self, size_of};
+use minicbor::{Decode, Encode};
 
-macro_rules! impl_num {
-    ($ty:ty, $size:literal, $sread_fn:ident, $swrite_fn:ident) => {
+macro_rules! impl_sread {
+    ($ty:ty, $size:literal, $sread_fn:ident) => {
         doc_comment! {
             concat!("reads ", stringify!($size), " byte(s) from storage file at the given offset and converts it to ", stringify!($ty),"."
             ),
@@ -17,7 +21,11 @@ macro_rules! impl_num {
                 ))
             }
         }
+    };
+}
 
+macro_rules! impl_swrite {
+    ($ty:ty, $size:literal, $swrite_fn:ident) => {
         doc_comment! {
                 concat!("converts ", stringify!($ty)," to ", stringify!($size), " byte(s) and writes into storage file at the given offset."
                 ),
@@ -29,17 +37,32 @@ macro_rules! impl_num {
     };
 }
 
-/// Storage trait
-pub trait Storage {
-    impl_num!(u8, 1, sread_u8, swrite_u8);
-    impl_num!(u16, 2, sread_u16, swrite_u16);
-    impl_num!(u32, 4, sread_u32, swrite_u32);
-    impl_num!(u64, 8, sread_u64, swrite_u64);
+/// Read-only storage trait.
+/// Queries receive a read-only storage, therefore they can't change the state of the contract.
+/// The collections that are loaded from a read-only storage have no write methods:
+///
+/// ```compile_fail
+/// use kelk_lib::collections::item::StorageItem;
+/// use kelk_lib::storage::ReadOnlyStorage;
+///
+/// fn query(storage: &dyn ReadOnlyStorage) {
+///     let item = StorageItem::<u32, _>::new(storage, 0);
+///     item.save(&1).unwrap(); // `save` needs a writable storage
+/// }
+/// ```
+pub trait ReadOnlyStorage {
+    impl_sread!(u8, 1, sread_u8);
+    impl_sread!(u16, 2, sread_u16);
+    impl_sread!(u32, 4, sread_u32);
+    impl_sread!(u64, 8, sread_u64);
+    impl_sread!(u128, 16, sread_u128);
+    impl_sread!(U256, 32, sread_u256);
 
-    impl_num!(i8, 1, sread_i8, swrite_i8);
-    impl_num!(i16, 2, sread_i16, swrite_i16);
-    impl_num!(i32, 4, sread_i32, swrite_i32);
-    impl_num!(i64, 8, sread_i64, swrite_i64);
+    impl_sread!(i8, 1, sread_i8);
+    impl_sread!(i16, 2, sread_i16);
+    impl_sread!(i32, 4, sread_i32);
+    impl_sread!(i64, 8, sread_i64);
+    impl_sread!(i128, 16, sread_i128);
 
     /// reads 1 byte from storage file at the given offset and converts it to bool.
     #[inline]
@@ -50,6 +73,25 @@ pub trait Storage {
         }
     }
 
+    /// reads `len` bytes from the storage file at the given offset
+    fn sread(&self, offset: u32, len: u32) -> Result<Vec<u8>, HostError>;
+}
+
+/// Storage trait, it extends the read-only storage with writes.
+pub trait Storage: ReadOnlyStorage {
+    impl_swrite!(u8, 1, swrite_u8);
+    impl_swrite!(u16, 2, swrite_u16);
+    impl_swrite!(u32, 4, swrite_u32);
+    impl_swrite!(u64, 8, swrite_u64);
+    impl_swrite!(u128, 16, swrite_u128);
+    impl_swrite!(U256, 32, swrite_u256);
+
+    impl_swrite!(i8, 1, swrite_i8);
+    impl_swrite!(i16, 2, swrite_i16);
+    impl_swrite!(i32, 4, swrite_i32);
+    impl_swrite!(i64, 8, swrite_i64);
+    impl_swrite!(i128, 16, swrite_i128);
+
     /// converts bool to 1 byte(s) and writes into storage file at the given offset.
     #[inline]
     fn swrite_bool(&self, offset: u32, value: bool) -> Result<(), HostError> {
@@ -60,21 +102,42 @@ pub trait Storage {
     }
 
     /// writes `data` into the storage file at the given offset
-    fn sread(&self, offset: u32, len: u32) -> Result<Vec<u8>, HostError>;
-
-    /// reads `data` from the storage file at the given offset and length
     fn swrite(&self, offset: u32, data: &[u8]) -> Result<(), HostError>;
 }
 
+/// `AnyBitPattern` marks the `NoPadding` types that are valid for any bit pattern,
+/// so they can be read from the bytes of the storage file as they are.
+/// `bool` is not one of them, since only 0 and 1 are valid for it.
+///
+/// # Safety
 ///
-pub fn sread_struct<T: Sized>(storage: &dyn Storage, offset: u32) -> Result<T, HostError> {
+/// The type should be `NoPadding` and every bit pattern of its memory should be a valid value,
+/// e.g. a `#[repr(C)]` struct of integers and arrays whose fields are aligned without any gap.
+pub unsafe trait AnyBitPattern: NoPadding + Copy {}
+
+macro_rules! impl_any_bit_pattern {
+    ($($ty:ty),*) => {
+        $(unsafe impl AnyBitPattern for $ty {})*
+    };
+}
+
+impl_any_bit_pattern!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+unsafe impl<T: AnyBitPattern, const N: usize> AnyBitPattern for [T; N] {}
+
+/// reads a `Sized` struct from the storage file at the given offset.
+pub fn sread_struct<T: Sized>(
+    storage: &(impl ReadOnlyStorage + ?Sized),
+    offset: u32,
+) -> Result<T, HostError> {
     let data = storage.sread(offset, size_of::<T>() as u32)?;
-    Ok(unsafe { core::ptr::read(data.as_ptr() as *const _) })
+    // The bytes of the storage are not aligned for `T`.
+    Ok(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const _) })
 }
 
-///
+/// writes a `Sized` struct into the storage file at the given offset.
 pub fn swrite_struct<T: Sized>(
-    storage: &dyn Storage,
+    storage: &(impl Storage + ?Sized),
     offset: u32,
     st: &T,
 ) -> Result<(), HostError> {
@@ -85,10 +148,134 @@ pub fn swrite_struct<T: Sized>(
     storage.swrite(offset, b)
 }
 
+/// The maximum length of a CBOR value in the storage, without its length prefix.
+/// A longer length is rejected before reading the data, since it can only be garbage.
+pub const MAX_CBOR_LEN: u32 = 64 * 1024;
+
+/// `CborStorage` extends the storage to read and write CBOR-encoded values.
+/// The values are length-prefixed: 4 bytes of length followed by the encoded data.
+pub trait CborStorage {
+    /// reads a length-prefixed CBOR value from the storage file at the given offset and decodes it.
+    fn sread_cbor<T>(&self, offset: u32) -> Result<T, Error>
+    where
+        T: for<'b> Decode<'b>;
+
+    /// encodes the value to CBOR and writes it with its length into the storage file at the given offset.
+    /// It returns `OutOfCapacity` error if the encoded value is longer than `MAX_CBOR_LEN`.
+    fn swrite_cbor<T: Encode>(&self, offset: u32, value: &T) -> Result<(), Error>
+    where
+        Self: Storage;
+}
+
+impl<S: ReadOnlyStorage + ?Sized> CborStorage for S {
+    fn sread_cbor<T>(&self, offset: u32) -> Result<T, Error>
+    where
+        T: for<'b> Decode<'b>,
+    {
+        let len = self.sread_u32(offset)?;
+        if len > MAX_CBOR_LEN {
+            return Err(Error::InvalidCbor);
+        }
+        let data_offset = offset.checked_add(4).ok_or(Error::InvalidOffset(offset))?;
+        let data = self.sread(data_offset, len)?;
+        minicbor::decode(&data).map_err(|_| Error::InvalidCbor)
+    }
+
+    fn swrite_cbor<T: Encode>(&self, offset: u32, value: &T) -> Result<(), Error>
+    where
+        Self: Storage,
+    {
+        swrite_cbor_data(self, offset, &encode_cbor(value)?)
+    }
+}
+
+/// writes the encoded CBOR value with its length into the storage file at the given offset.
+/// It returns `OutOfCapacity` error if the data is longer than `MAX_CBOR_LEN`.
+pub(crate) fn swrite_cbor_data<S: Storage + ?Sized>(
+    storage: &S,
+    offset: u32,
+    data: &[u8],
+) -> Result<(), Error> {
+    if data.len() > MAX_CBOR_LEN as usize {
+        return Err(Error::OutOfCapacity);
+    }
+
+    // The length and the data are written at once, so a failed write
+    // never leaves the old length with the new data.
+    let mut encoded = Vec::with_capacity(4 + data.len());
+    encoded.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    encoded.extend_from_slice(data);
+    storage.swrite(offset, &encoded)?;
+    Ok(())
+}
+
+pub(crate) fn encode_cbor<T: Encode>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    minicbor::encode(value, &mut data).map_err(|_| Error::InvalidCbor)?;
+    Ok(data)
+}
+
+/// `CborValue` keeps a CBOR-encoded value in a fixed-size buffer of `N` bytes.
+/// It has a fixed size, so it can be stored inside the collections as the CBOR value mode.
+/// For example `StorageVec<CborValue<Order, 64>>` stores the orders encoded in CBOR.
+/// The length is kept in little-endian bytes, so the value has no padding for any `N`.
+#[repr(C)]
+pub struct CborValue<T, const N: usize> {
+    len: [u8; 4],
+    data: [u8; N],
+    _phantom: PhantomData<T>,
+}
+
+impl<T, const N: usize> Clone for CborValue<T, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, const N: usize> Copy for CborValue<T, N> {}
+
+unsafe impl<T, const N: usize> NoPadding for CborValue<T, N> {}
+
+unsafe impl<T, const N: usize> AnyBitPattern for CborValue<T, N> {}
+
+impl<T, const N: usize> CborValue<T, N> {
+    /// encodes the value. It returns `OutOfCapacity` error if the encoded value is longer than `N`.
+    pub fn encode(value: &T) -> Result<Self, Error>
+    where
+        T: Encode,
+    {
+        let encoded = encode_cbor(value)?;
+        if encoded.len() > N {
+            return Err(Error::OutOfCapacity);
+        }
+
+        let mut data = [0; N];
+        data[..encoded.len()].copy_from_slice(&encoded);
+        Ok(CborValue {
+            len: (encoded.len() as u32).to_le_bytes(),
+            data,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// decodes the value.
+    pub fn decode(&self) -> Result<T, Error>
+    where
+        T: for<'b> Decode<'b>,
+    {
+        let data = self
+            .data
+            .get(..u32::from_le_bytes(self.len) as usize)
+            .ok_or(Error::InvalidCbor)?;
+        minicbor::decode(data).map_err(|_| Error::InvalidCbor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mock::mock_storage;
+    use crate::error::HostErrorKind;
+    use crate::mock::{mock_storage, Fault};
 
     #[test]
     fn test_negative_integers() {
@@ -120,6 +307,21 @@ mod tests {
         assert_eq!(mock.sread_u64(7).unwrap(), 4);
     }
 
+    #[test]
+    fn test_big_integers() {
+        let mock = mock_storage(64);
+        let big = U256::from(u128::MAX).checked_mul(U256::from(3u8)).unwrap();
+
+        mock.swrite_u128(0, u128::MAX - 1).unwrap();
+        mock.swrite_i128(16, i128::MIN + 1).unwrap();
+        mock.swrite_u256(32, big).unwrap();
+
+        assert_eq!(mock.sread_u128(0).unwrap(), u128::MAX - 1);
+        assert_eq!(mock.sread_i128(16).unwrap(), i128::MIN + 1);
+        assert_eq!(mock.sread_u256(32).unwrap(), big);
+        assert_eq!(mock.sread(32, 1).unwrap(), [0]);
+    }
+
     #[test]
     fn test_bool() {
         let mock = mock_storage(1);
@@ -148,4 +350,164 @@ mod tests {
         let foo_2 = sread_struct::<Test>(&storage, 13).unwrap();
         assert_eq!(foo_1, foo_2);
     }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    enum Side {
+        #[n(0)]
+        Buy,
+        #[n(1)]
+        Sell {
+            #[n(0)]
+            limit: Option<u64>,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Order {
+        #[n(0)]
+        owner: alloc::string::String,
+        #[n(1)]
+        side: Side,
+        #[n(2)]
+        memo: Option<alloc::string::String>,
+    }
+
+    fn order(owner: &str, side: Side) -> Order {
+        Order {
+            owner: owner.into(),
+            side,
+            memo: None,
+        }
+    }
+
+    #[test]
+    fn test_cbor() {
+        let storage = mock_storage(128);
+        let order_1 = Order {
+            owner: "alice".into(),
+            side: Side::Sell { limit: Some(100) },
+            memo: Some("first".into()),
+        };
+        storage.swrite_cbor(8, &order_1).unwrap();
+        let order_2: Order = storage.sread_cbor(8).unwrap();
+        assert_eq!(order_1, order_2);
+
+        storage.swrite_cbor(8, &order("bob", Side::Buy)).unwrap();
+        let order_3: Order = storage.sread_cbor(8).unwrap();
+        assert_eq!(order("bob", Side::Buy), order_3);
+    }
+
+    #[test]
+    fn test_cbor_invalid() {
+        let storage = mock_storage(128);
+        assert_eq!(
+            storage.sread_cbor::<Order>(0).unwrap_err(),
+            Error::InvalidCbor
+        );
+        storage.swrite_cbor(0, &1u32).unwrap();
+        assert_eq!(
+            storage.sread_cbor::<Order>(0).unwrap_err(),
+            Error::InvalidCbor
+        );
+        assert_eq!(storage.sread_cbor::<u32>(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_cbor_len() {
+        let storage = mock_storage(128);
+        storage.swrite_u32(0, MAX_CBOR_LEN + 1).unwrap();
+        assert_eq!(
+            storage.sread_cbor::<u32>(0).unwrap_err(),
+            Error::InvalidCbor
+        );
+        storage.swrite_u32(0, 0).unwrap();
+        assert!(storage.sread_cbor::<u32>(u32::MAX - 3).is_err());
+
+        let long = alloc::vec![0u8; MAX_CBOR_LEN as usize];
+        assert_eq!(
+            storage.swrite_cbor(0, &long).unwrap_err(),
+            Error::OutOfCapacity
+        );
+    }
+
+    #[test]
+    fn test_failed_cbor_write() {
+        let storage = mock_storage(128);
+        storage.swrite_cbor(0, &order("bob", Side::Buy)).unwrap();
+        let snapshot = storage.snapshot();
+
+        // A value with another length is written with one write
+        let writes = storage.write_count();
+        storage.inject(Fault::NthWrite {
+            n: writes + 1,
+            kind: HostErrorKind::StorageFull,
+        });
+        assert!(storage.swrite_cbor(0, &order("alice", Side::Buy)).is_err());
+        assert!(storage.diff(&snapshot).is_empty());
+        assert_eq!(
+            storage.sread_cbor::<Order>(0).unwrap(),
+            order("bob", Side::Buy)
+        );
+    }
+
+    #[test]
+    fn test_cbor_dyn_storage() {
+        let mock = mock_storage(128);
+        let storage: &dyn Storage = &mock;
+        storage.swrite_cbor(0, &Side::Buy).unwrap();
+        assert_eq!(storage.sread_cbor::<Side>(0).unwrap(), Side::Buy);
+    }
+
+    #[test]
+    fn test_cbor_value() {
+        let value = CborValue::<Side, 8>::encode(&Side::Sell { limit: None }).unwrap();
+        assert_eq!(value.decode().unwrap(), Side::Sell { limit: None });
+
+        let long = order("a long name that does not fit", Side::Buy);
+        assert!(matches!(
+            CborValue::<Order, 8>::encode(&long),
+            Err(Error::OutOfCapacity)
+        ));
+    }
+
+    #[test]
+    fn test_cbor_value_size() {
+        assert_eq!(size_of::<CborValue<Side, 5>>(), 9);
+        assert_eq!(size_of::<CborValue<Side, 8>>(), 12);
+
+        // An invalid length is rejected on decoding
+        let storage = mock_storage(16);
+        storage.swrite(0, &[0xff; 9]).unwrap();
+        let value = sread_struct::<CborValue<Side, 5>>(&storage, 0).unwrap();
+        assert!(matches!(value.decode(), Err(Error::InvalidCbor)));
+    }
+
+    #[test]
+    fn test_cbor_collections() {
+        use crate::collections::bst::StorageBST;
+        use crate::collections::item::StorageItem;
+        use crate::collections::vector::StorageVec;
+
+        let storage = mock_storage(4096);
+
+        let mut vec = StorageVec::<CborValue<Order, 32>>::create(&storage, 0, 4).unwrap();
+        vec.push(CborValue::encode(&order("alice", Side::Buy)).unwrap())
+            .unwrap();
+        let sell = Side::Sell { limit: Some(7) };
+        vec.push(CborValue::encode(&order("bob", sell)).unwrap())
+            .unwrap();
+        let order_2 = vec.get(1).unwrap().unwrap().decode().unwrap();
+        assert_eq!(order_2.side, Side::Sell { limit: Some(7) });
+
+        let mut bst = StorageBST::<u32, CborValue<Side, 8>>::create(&storage, 1024, 4).unwrap();
+        bst.insert(1, CborValue::encode(&Side::Buy).unwrap())
+            .unwrap();
+        let side = bst.find(&1).unwrap().unwrap().decode().unwrap();
+        assert_eq!(side, Side::Buy);
+
+        let item = StorageItem::<CborValue<Order, 32>>::new(&storage, 2048);
+        item.save(&CborValue::encode(&order("carol", Side::Buy)).unwrap())
+            .unwrap();
+        assert_eq!(item.load().unwrap().decode().unwrap().owner, "carol");
+    }
 }
@@ -0,0 +1,434 @@
+//! 256-bit unsigned integer with checked arithmetic.
+//! It is useful for token amounts that exceed 64 or 128 bits.
+
+use core::cmp::Ordering;
+use core::fmt;
+use minicbor::{decode, encode, Decode, Decoder, Encode, Encoder};
+
+/// 256-bit unsigned integer.
+/// The arithmetic operations are checked and they return `None` on overflow or division by zero.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]); // little-endian limbs
+
+impl U256 {
+    /// The smallest value: 0
+    pub const ZERO: U256 = U256([0; 4]);
+    /// The value one: 1
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    /// The largest value: 2^256 - 1
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    /// creates a U256 from a `u128` value
+    pub const fn from_u128(value: u128) -> Self {
+        U256([value as u64, (value >> 64) as u64, 0, 0])
+    }
+
+    /// converts the value to `u128`. It returns None if the value doesn't fit in 128 bits.
+    pub fn to_u128(&self) -> Option<u128> {
+        match self.0[2] == 0 && self.0[3] == 0 {
+            true => Some(self.0[0] as u128 | (self.0[1] as u128) << 64),
+            false => None,
+        }
+    }
+
+    /// creates a U256 from its big-endian bytes
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 24 - i * 8;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    /// returns the big-endian bytes of the value
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 24 - i * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// returns true if the value is zero
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// returns the number of bits needed to represent the value
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + (64 - self.0[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        self.0[(index / 64) as usize] >> (index % 64) & 1 == 1
+    }
+
+    fn set_bit(&mut self, index: u32) {
+        self.0[(index / 64) as usize] |= 1 << (index % 64);
+    }
+
+    fn shl1(&self) -> Self {
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = self.0[i] << 1;
+            if i > 0 {
+                *limb |= self.0[i - 1] >> 63;
+            }
+        }
+        U256(limbs)
+    }
+
+    fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+        let mut limbs = [0; 4];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(rhs.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (U256(limbs), carry)
+    }
+
+    fn overflowing_sub(self, rhs: Self) -> (Self, bool) {
+        let mut limbs = [0; 4];
+        let mut borrow = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(rhs.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        (U256(limbs), borrow)
+    }
+
+    /// Checked addition. Returns None if overflow occurred.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.overflowing_add(rhs) {
+            (value, false) => Some(value),
+            (_, true) => None,
+        }
+    }
+
+    /// Checked subtraction. Returns None if overflow occurred.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        match self.overflowing_sub(rhs) {
+            (value, false) => Some(value),
+            (_, true) => None,
+        }
+    }
+
+    /// Checked multiplication. Returns None if overflow occurred.
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let mut limbs = [0u64; 4];
+        for i in 0..4 {
+            if self.0[i] == 0 {
+                continue;
+            }
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = self.0[i] as u128 * rhs.0[j] as u128 + carry;
+                if i + j < 4 {
+                    let sum = limbs[i + j] as u128 + (product & u64::MAX as u128);
+                    limbs[i + j] = sum as u64;
+                    carry = (product >> 64) + (sum >> 64);
+                } else if product != 0 {
+                    return None;
+                }
+            }
+            if carry != 0 {
+                return None;
+            }
+        }
+        Some(U256(limbs))
+    }
+
+    /// Checked division and remainder. Returns None if `rhs` is zero.
+    pub fn checked_div_rem(self, rhs: Self) -> Option<(Self, Self)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        if self < rhs {
+            return Some((U256::ZERO, self));
+        }
+
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for i in (0..self.bits()).rev() {
+            remainder = remainder.shl1();
+            if self.bit(i) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= rhs {
+                remainder = remainder.overflowing_sub(rhs).0;
+                quotient.set_bit(i);
+            }
+        }
+        Some((quotient, remainder))
+    }
+
+    /// Checked division. Returns None if `rhs` is zero.
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        self.checked_div_rem(rhs).map(|(q, _)| q)
+    }
+
+    /// Checked remainder. Returns None if `rhs` is zero.
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        self.checked_div_rem(rhs).map(|(_, r)| r)
+    }
+
+    /// Checked exponentiation. Returns None if overflow occurred.
+    pub fn checked_pow(self, mut exp: u32) -> Option<Self> {
+        let mut base = self;
+        let mut acc = U256::ONE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc.checked_mul(base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(base)?;
+            }
+        }
+        Some(acc)
+    }
+
+    /// Saturating addition. Returns `MAX` if overflow occurred.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).unwrap_or(U256::MAX)
+    }
+
+    /// Saturating subtraction. Returns `ZERO` if overflow occurred.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).unwrap_or(U256::ZERO)
+    }
+
+    /// Saturating multiplication. Returns `MAX` if overflow occurred.
+    pub fn saturating_mul(self, rhs: Self) -> Self {
+        self.checked_mul(rhs).unwrap_or(U256::MAX)
+    }
+
+    /// divides the value by a `u64` and returns the quotient and the remainder.
+    fn div_rem_u64(self, rhs: u64) -> (Self, u64) {
+        let mut limbs = [0; 4];
+        let mut rem = 0u128;
+        for i in (0..4).rev() {
+            let cur = rem << 64 | self.0[i] as u128;
+            limbs[i] = (cur / rhs as u128) as u64;
+            rem = cur % rhs as u128;
+        }
+        (U256(limbs), rem as u64)
+    }
+}
+
+macro_rules! impl_from {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for U256 {
+                fn from(value: $ty) -> Self {
+                    U256::from_u128(u128::from(value))
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(u8, u16, u32, u64);
+
+impl From<u128> for U256 {
+    fn from(value: u128) -> Self {
+        U256::from_u128(value)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const TEN_19: u64 = 10_000_000_000_000_000_000;
+
+        if self.is_zero() {
+            return f.pad_integral(true, "", "0");
+        }
+
+        // 2^256 has 78 decimal digits, which are at most 5 chunks of 19 digits
+        let mut chunks = [0u64; 5];
+        let mut count = 0;
+        let mut value = *self;
+        while !value.is_zero() {
+            let (q, r) = value.div_rem_u64(TEN_19);
+            chunks[count] = r;
+            count += 1;
+            value = q;
+        }
+
+        let mut buf = [0u8; 95];
+        let mut len = 0;
+        for (i, chunk) in chunks[..count].iter().rev().enumerate() {
+            let mut digits = [b'0'; 19];
+            let mut chunk = *chunk;
+            for d in digits.iter_mut().rev() {
+                *d = b'0' + (chunk % 10) as u8;
+                chunk /= 10;
+            }
+            let digits = match i {
+                0 => {
+                    let start = digits.iter().position(|d| *d != b'0').unwrap_or(18);
+                    &digits[start..]
+                }
+                _ => &digits[..],
+            };
+            buf[len..len + digits.len()].copy_from_slice(digits);
+            len += digits.len();
+        }
+
+        f.pad_integral(true, "", core::str::from_utf8(&buf[..len]).unwrap())
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Encode for U256 {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        crate::cbor::encode_bignum(e, false, &self.to_be_bytes())
+    }
+}
+
+impl<'b> Decode<'b> for U256 {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        match crate::cbor::decode_bignum::<32>(d)? {
+            (false, buf) => Ok(U256::from_be_bytes(buf)),
+            (true, _) => Err(decode::Error::Message("negative value for U256")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    fn pow2(exp: u32) -> U256 {
+        U256::from(2u8).checked_pow(exp).unwrap()
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut bytes = [0; 32];
+        bytes[31] = 1;
+        bytes[0] = 0x80;
+        let value = U256::from_be_bytes(bytes);
+        assert_eq!(value.to_be_bytes(), bytes);
+        assert_eq!(value, pow2(255).checked_add(U256::ONE).unwrap());
+        assert_eq!(value.bits(), 256);
+    }
+
+    #[test]
+    fn test_u128() {
+        assert_eq!(U256::from(u128::MAX).to_u128(), Some(u128::MAX));
+        assert_eq!(pow2(128).to_u128(), None);
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let a = U256::from(u64::MAX);
+        assert_eq!(a.checked_add(U256::ONE), Some(pow2(64)));
+        assert_eq!(pow2(64).checked_sub(U256::ONE), Some(a));
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+        assert_eq!(U256::MAX.saturating_add(U256::ONE), U256::MAX);
+        assert_eq!(U256::ZERO.saturating_sub(U256::ONE), U256::ZERO);
+    }
+
+    #[test]
+    fn test_mul() {
+        let a = U256::from(u128::MAX);
+        let b = a.checked_mul(a).unwrap();
+        // (2^128 - 1)^2 = 2^256 - 2^129 + 1
+        let expected = U256::MAX
+            .checked_sub(pow2(129))
+            .unwrap()
+            .checked_add(U256::from(2u8))
+            .unwrap();
+        assert_eq!(b, expected);
+        assert_eq!(pow2(128).checked_mul(pow2(128)), None);
+        assert_eq!(pow2(255).checked_mul(U256::from(2u8)), None);
+        assert_eq!(U256::MAX.checked_mul(U256::ZERO), Some(U256::ZERO));
+        assert_eq!(pow2(200).saturating_mul(pow2(100)), U256::MAX);
+    }
+
+    #[test]
+    fn test_div_rem() {
+        let a = U256::from(1_000_000_007u64);
+        let b = U256::from(97u8);
+        assert_eq!(a.checked_div(b), Some(U256::from(1_000_000_007u64 / 97)));
+        assert_eq!(a.checked_rem(b), Some(U256::from(1_000_000_007u64 % 97)));
+        assert_eq!(a.checked_div(U256::ZERO), None);
+        assert_eq!(b.checked_div(a), Some(U256::ZERO));
+
+        let (q, r) = U256::MAX.checked_div_rem(pow2(128)).unwrap();
+        assert_eq!(q, U256::from(u128::MAX));
+        assert_eq!(r, U256::from(u128::MAX));
+    }
+
+    #[test]
+    fn test_ord() {
+        assert!(pow2(64) > U256::from(u64::MAX));
+        assert!(pow2(200) > pow2(199));
+        assert!(U256::ZERO < U256::ONE);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", U256::ZERO), "0");
+        assert_eq!(format!("{}", U256::from(1234u16)), "1234");
+        assert_eq!(
+            format!("{}", U256::from(10_000_000_000_000_000_000u64)),
+            "10000000000000000000"
+        );
+        assert_eq!(
+            format!("{}", U256::MAX),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        assert_eq!(format!("{:>6}", U256::from(42u8)), "    42");
+    }
+
+    #[test]
+    fn test_cbor() {
+        for value in [U256::ZERO, U256::from(u64::MAX), pow2(64), U256::MAX] {
+            let mut buf = Vec::new();
+            minicbor::encode(value, &mut buf).unwrap();
+            assert_eq!(minicbor::decode::<U256>(&buf).unwrap(), value);
+        }
+
+        let mut buf = Vec::new();
+        minicbor::encode(pow2(64), &mut buf).unwrap();
+        // http://cbor.me/?bytes=c249010000000000000000
+        assert_eq!(
+            buf,
+            [0xc2, 0x49, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+}